@@ -0,0 +1,8 @@
+use cfg_aliases::cfg_aliases;
+
+fn main() {
+    cfg_aliases! {
+        // Where the trash crate has somewhere to put deleted files
+        can_trash: { any(windows, target_os = "macos", all(unix, not(target_os = "ios"), not(target_os = "android"))) },
+    }
+}
@@ -0,0 +1,45 @@
+use std::path::Path;
+use chrono::{DateTime, Local};
+use crate::git_deploy::head_commit;
+
+/// Where a build id came from
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BuildSource {
+    /// The short hash of the site's commit
+    Git,
+    /// The site isn't a git repo, so the id is just when it was built
+    Timestamp
+}
+
+/// What a build was made from, so a deployed site can be traced back to its source. Layouts see
+/// the id as `$build_id`, and the whole thing goes in the build as `build-info.json`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BuildInfo {
+    pub build_id: String,
+    pub source: BuildSource,
+    /// For a commit, when it was committed rather than when it was built, so building the same
+    /// commit again gives the same file, and a deploy of it has nothing to push
+    pub built_at: DateTime<Local>
+}
+
+impl BuildInfo {
+    pub fn for_site(root: &Path) -> Self {
+        match head_commit(root) {
+            Some((sha, committed)) => Self { build_id: sha, source: BuildSource::Git, built_at: committed.with_timezone(&Local) },
+            None => {
+                let built_at = Local::now();
+                Self { build_id: built_at.format("%Y%m%d%H%M%S").to_string(), source: BuildSource::Timestamp, built_at }
+            }
+        }
+    }
+
+    /// The contents of `build-info.json`. Nothing in it ever needs escaping.
+    pub fn to_json(&self) -> String {
+        let source = match self.source {
+            BuildSource::Git => "git",
+            BuildSource::Timestamp => "timestamp"
+        };
+        format!("{{\n  \"build_id\": \"{}\",\n  \"source\": \"{}\",\n  \"built_at\": \"{}\"\n}}\n",
+                self.build_id, source, self.built_at.to_rfc3339())
+    }
+}
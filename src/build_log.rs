@@ -0,0 +1,99 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local};
+use crate::fleen_app::FleenError;
+
+/// Past this size the log is moved aside to `build.log.1` (replacing the one before) and a new
+/// one started, so it never grows past twice this
+const MAX_LOG_BYTES: u64 = 256 * 1024;
+
+/// One build or deploy, for `_fleen/build.log`
+#[derive(Clone, Debug, PartialEq)]
+pub struct BuildLogEntry {
+    pub time: DateTime<Local>,
+    /// What we did, like "build" or "deploy (script)"
+    pub action: String,
+    pub duration: Duration,
+    /// What went wrong, if it failed
+    pub error: Option<String>
+}
+
+impl BuildLogEntry {
+    /// An entry for something that started at `started` and just finished
+    pub fn new<T>(action: impl Into<String>, started: Instant, result: &Result<T, FleenError>) -> Self {
+        Self {
+            time: Local::now(),
+            action: action.into(),
+            duration: started.elapsed(),
+            error: result.as_ref().err().map(|e| e.to_string())
+        }
+    }
+
+    /// The entry as one line of the log. Errors can run to several lines, so they're flattened.
+    fn line(&self) -> String {
+        let outcome = match &self.error {
+            None => "ok".to_string(),
+            Some(error) => format!("failed: {}", error.split_whitespace().collect::<Vec<_>>().join(" "))
+        };
+        format!("{}  {}  {:.1}s  {}", self.time.format("%Y-%m-%d %H:%M:%S"), self.action, self.duration.as_secs_f64(), outcome)
+    }
+}
+
+fn log_path(root: &Path) -> PathBuf {
+    root.join("_fleen/build.log")
+}
+
+/// Add an entry to the site's build log
+pub fn append(root: &Path, entry: &BuildLogEntry) -> Result<(), FleenError> {
+    let path = log_path(root);
+    append_to(&path, entry, MAX_LOG_BYTES).map_err(|e| FleenError::FileIo(path.to_string_lossy().to_string(), e.to_string()))
+}
+
+fn append_to(path: &Path, entry: &BuildLogEntry, max_bytes: u64) -> io::Result<()> {
+    if let Some(parent) = path.parent() { fs::create_dir_all(parent)? }
+    if fs::metadata(path).is_ok_and(|m| m.len() >= max_bytes) {
+        fs::rename(path, path.with_extension("log.1"))?
+    }
+    let mut file = fs::File::options().create(true).append(true).open(path)?;
+    writeln!(file, "{}", entry.line())
+}
+
+/// The newest `count` lines of the log, newest first. No log just means no builds yet.
+pub fn recent(root: &Path, count: usize) -> Vec<String> {
+    let contents = fs::read_to_string(log_path(root)).unwrap_or_default();
+    contents.lines().rev().take(count).map(String::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let started = Instant::now();
+        append(dir.path(), &BuildLogEntry::new("build", started, &Ok::<(), FleenError>(()))).unwrap();
+        append(dir.path(), &BuildLogEntry::new("deploy (script)", started, &Err::<(), _>(FleenError::DeployError("exit 1\nno route to host".to_string())))).unwrap();
+
+        let lines = recent(dir.path(), 10);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("  deploy (script)  ") && lines[0].ends_with("failed: Deploy script error: exit 1 no route to host"));
+        assert!(lines[1].contains("  build  ") && lines[1].ends_with("  ok"));
+        assert_eq!(recent(dir.path(), 1), lines[0..1]);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("build.log");
+        let entry = BuildLogEntry { time: Local::now(), action: "build".to_string(), duration: Duration::from_secs(1), error: None };
+        for _ in 0..10 {
+            append_to(&path, &entry, 200).unwrap();
+        }
+        // Never much past the limit, and the older entries are kept one file over
+        assert!(fs::metadata(&path).unwrap().len() < 200 + entry.line().len() as u64 + 1);
+        assert!(dir.path().join("build.log.1").is_file());
+    }
+}
@@ -0,0 +1,47 @@
+use clipboard_rs::{Clipboard, ClipboardContext};
+use clipboard_rs::common::RustImage;
+use crate::fleen_app::FleenError;
+
+/// Image formats we'd rather take as they are than have converted to png, by the names the
+/// clipboard knows them by on each platform
+const NATIVE_FORMATS: [&str; 8] = [
+    "image/jpeg", "public.jpeg", "JFIF", "image/gif", "com.compuserve.gif", "GIF", "image/webp", "org.webmproject.webp"
+];
+
+/// The bits of the clipboard Fleen uses. Mostly here so tests can stand in for the real one.
+pub trait ImageClipboard {
+    /// The clipboard's image file data, in its own format if it's one we can tell, or else as a
+    /// png. None if it's holding something else (or nothing).
+    fn image(&self) -> Result<Option<Vec<u8>>, FleenError>;
+    fn set_text(&self, text: String) -> Result<(), FleenError>;
+}
+
+/// The system clipboard
+pub struct SystemClipboard(ClipboardContext);
+
+impl SystemClipboard {
+    /// Fails with `ClipboardUnavailable` when there's no clipboard to talk to at all, like on a
+    /// Linux box without a display
+    pub fn open() -> Result<Self, FleenError> {
+        ClipboardContext::new().map(SystemClipboard).map_err(|e| FleenError::ClipboardUnavailable(e.to_string()))
+    }
+}
+
+impl ImageClipboard for SystemClipboard {
+    fn image(&self) -> Result<Option<Vec<u8>>, FleenError> {
+        let formats = self.0.available_formats().unwrap_or_default();
+        let native = NATIVE_FORMATS.iter().find(|format| formats.iter().any(|f| f == *format));
+        if let Some(format) = native && let Ok(bytes) = self.0.get_buffer(format) && !bytes.is_empty() {
+            return Ok(Some(bytes))
+        }
+        // clipboard_rs doesn't tell "no image" apart from other failures, but we've already
+        // reached the clipboard, so it's almost certainly the former
+        let Ok(image) = self.0.get_image() else { return Ok(None) };
+        let png = image.to_png().map_err(|_| FleenError::NoClipboardImage)?;
+        Ok(Some(png.get_bytes().to_vec()))
+    }
+
+    fn set_text(&self, text: String) -> Result<(), FleenError> {
+        self.0.set_text(text).map_err(|e| FleenError::ClipboardUnavailable(e.to_string()))
+    }
+}
@@ -0,0 +1,457 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use serde::Deserialize;
+use toml_edit::DocumentMut;
+use crate::fleen_app::FleenError;
+use crate::git_deploy::GitDeploy;
+
+/// Per-site settings, read from `_config.toml` in the site root. Every field has a default, so
+/// a missing file (or a file missing some keys) is fine.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SiteConfig {
+    /// Where the site is published, like `https://example.com`. Used to make absolute urls.
+    pub base_url: Option<String>,
+    /// The name of the site, available to layouts and templates as `$site_title`
+    pub title: Option<String>,
+    /// The layout (relative to the root) for markdown pages that don't name one or have a type
+    /// with one in `[layouts]`
+    pub default_layout: Option<String>,
+    /// What the site's `feed.xml` is called, if not the site's title
+    pub feed_title: Option<String>,
+    /// Names of files / dirs in the build target that shouldn't be deleted when we clear it
+    pub preserve: Vec<String>,
+    /// Where "Build site" builds to, relative to the root (or absolute), instead of asking. It
+    /// can be inside the site; it's left out of the tree and the build if so.
+    pub output_dir: Option<String>,
+    /// Add a charset and viewport meta to layouts whose head doesn't already have them
+    pub inject_meta: bool,
+    /// Treat build warnings (unpublished pages, broken links) as errors
+    pub strict: bool,
+    /// Have the preview server show draft pages. They're never built either way.
+    pub drafts: bool,
+    /// Have pages from the preview server reload themselves when the site changes
+    pub live_reload: bool,
+    /// What a build does with unpublished pages
+    pub hidden_mode: HiddenMode,
+    /// Where pasted and dropped images go, relative to the root
+    pub image_dir: String,
+    /// What new images are called (before the extension). `{date}` is today, as YYYY-MM-DD, and
+    /// `{random}` is eight random hex digits.
+    pub image_name: String,
+    /// Shrink pasted images wider than this, keeping their shape
+    pub max_image_width: Option<u32>,
+    /// Shrink pasted images taller than this, keeping their shape
+    pub max_image_height: Option<u32>,
+    /// What format pasted images are saved in
+    pub paste_format: PasteFormat,
+    /// An image (relative to the root) to publish as the site's favicon
+    pub favicon: Option<String>,
+    /// Warn about images narrower than this
+    pub min_image_width: Option<u32>,
+    /// Warn about images shorter than this
+    pub min_image_height: Option<u32>,
+    /// Wrap each line of fenced code blocks in a `<span class="line" data-line="N">`, so layouts
+    /// can show line numbers (with a little css)
+    pub line_numbers: bool,
+    /// Highlight the code in fenced code blocks when rendering, with spans classed by what each
+    /// token is, rather than leaving it to a script in the browser
+    pub highlight_code: bool,
+    /// The GitHub extras to markdown: footnotes, `~~strikethrough~~`, task lists and bare urls
+    /// as links. Turn them off for plain CommonMark. (Tables are always on.)
+    pub gfm: bool,
+    /// Put captioned images that sit in a paragraph of their own in a `<figure>`, numbered
+    /// "Figure 1.", "Figure 2." and so on down the page
+    pub figures: bool,
+    /// The port the preview server starts on
+    pub server_port: u16,
+    /// How many requests the preview server handles at once; the rest wait their turn
+    pub server_concurrency: usize,
+    /// If the preview server's port is taken, try the next few (and then any free one) rather
+    /// than failing
+    pub port_fallback: bool,
+    /// Never start other programs: no opening files, no deploy scripts
+    pub safe_mode: bool,
+    /// Layouts for each page `type`, so a page can say `type = "post"` rather than naming its layout
+    pub layouts: BTreeMap<String, String>,
+    /// Where to commit the built site, for deploying without a script
+    pub git_deploy: Option<GitDeploy>,
+    /// How many times to retry writing or deleting a file in the build target that something else
+    /// (on Windows, usually a virus scanner) briefly has locked
+    pub fs_retries: u32,
+    /// How many seconds the site has to sit unchanged before an auto-deploy (if turned on) fires
+    pub auto_deploy_delay: u64,
+    /// How many seconds a deploy script gets before it's stopped, so a hung one can't keep us
+    /// deploying forever. Zero means no limit.
+    pub deploy_timeout: u64,
+    /// Not a setting: a build fills this in, for `$build_id`
+    #[serde(skip)]
+    pub build_id: Option<String>,
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        Self {
+            title: None,
+            feed_title: None,
+            base_url: None,
+            default_layout: None,
+            preserve: vec![],
+            output_dir: None,
+            inject_meta: true,
+            strict: false,
+            drafts: false,
+            live_reload: true,
+            hidden_mode: HiddenMode::Omit,
+            image_dir: "images".to_string(),
+            image_name: "image_{random}".to_string(),
+            max_image_width: None,
+            max_image_height: None,
+            paste_format: PasteFormat::Keep,
+            favicon: None,
+            min_image_width: None,
+            min_image_height: None,
+            auto_deploy_delay: 30,
+            deploy_timeout: 600,
+            fs_retries: 3,
+            line_numbers: false,
+            highlight_code: false,
+            gfm: true,
+            figures: false,
+            server_port: 3000,
+            server_concurrency: 16,
+            port_fallback: true,
+            safe_mode: false,
+            layouts: BTreeMap::new(),
+            git_deploy: None,
+            build_id: None,
+        }
+    }
+}
+
+/// What happens to unpublished (`published = false`) pages in a build. The preview server
+/// shows them either way.
+#[derive(Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HiddenMode {
+    /// Leave them out
+    #[default]
+    Omit,
+    /// Build them, but ask search engines not to index them, so they're only found by direct link
+    Noindex
+}
+
+impl HiddenMode {
+    fn name(&self) -> &'static str {
+        match self {
+            HiddenMode::Omit => "omit",
+            HiddenMode::Noindex => "noindex"
+        }
+    }
+}
+
+/// What format pasted images are saved in
+#[derive(Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PasteFormat {
+    /// Whatever the clipboard had, or png if we couldn't tell
+    #[default]
+    Keep,
+    Png,
+    Jpeg,
+    /// Lossless, but usually still much smaller than png
+    Webp
+}
+
+impl PasteFormat {
+    pub const ALL: [PasteFormat; 4] = [PasteFormat::Keep, PasteFormat::Png, PasteFormat::Jpeg, PasteFormat::Webp];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PasteFormat::Keep => "keep",
+            PasteFormat::Png => "png",
+            PasteFormat::Jpeg => "jpeg",
+            PasteFormat::Webp => "webp"
+        }
+    }
+}
+
+impl SiteConfig {
+    /// The configured favicon's source path, and the name it gets in the root of the built site
+    pub fn favicon_output(&self) -> Option<(PathBuf, String)> {
+        let source = PathBuf::from(self.favicon.as_ref()?);
+        let name = match source.extension() {
+            Some(ext) => format!("favicon.{}", ext.to_string_lossy()),
+            None => "favicon.ico".to_string()
+        };
+        Some((source, name))
+    }
+
+    /// The dir pasted and dropped images go in
+    pub fn image_path(&self, root: &Path) -> PathBuf {
+        root.join(&self.image_dir)
+    }
+
+    /// Where builds go, if there's an output dir configured
+    pub fn output_path(&self, root: &Path) -> Option<PathBuf> {
+        self.output_dir.as_ref().map(|dir| root.join(dir))
+    }
+
+    /// The output dir relative to the root, if it's somewhere inside the site
+    pub fn nested_output_dir(&self, root: &Path) -> Option<PathBuf> {
+        let path = self.output_path(root)?;
+        let relative = path.strip_prefix(root).ok()?;
+        let nested = relative.components().next().is_some() && relative.components().all(|c| matches!(c, Component::Normal(_)));
+        nested.then(|| relative.to_path_buf())
+    }
+
+    /// Whether a path (relative to the root) is something other than site content: Fleen's own
+    /// `_fleen` dir, or a build sitting in the output dir
+    pub fn excludes(&self, root: &Path, source: &Path) -> bool {
+        source.starts_with("_fleen") || self.nested_output_dir(root).is_some_and(|dir| source.starts_with(dir))
+    }
+
+    pub fn load(root: &Path) -> Result<Self, FleenError> {
+        let path = config_path(root);
+        if !path.exists() { return Ok(Self::default()) }
+        let contents = fs::read_to_string(&path).map_err(|e| FleenError::FileIo(path.to_string_lossy().to_string(), e.to_string()))?;
+        toml::from_str(&contents).map_err(|e| FleenError::ConfigParse(e.to_string()))
+    }
+}
+
+fn config_path(root: &Path) -> PathBuf {
+    root.join("_config.toml")
+}
+
+/// The settings form's view of the config: everything as something a widget can edit. Saving
+/// only touches the keys that changed, so comments and keys we don't know about survive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigForm {
+    pub title: String,
+    pub feed_title: String,
+    pub base_url: String,
+    pub favicon: String,
+    pub default_layout: String,
+    pub image_dir: String,
+    pub image_name: String,
+    /// Comma-separated
+    pub preserve: String,
+    pub output_dir: String,
+    pub inject_meta: bool,
+    pub strict: bool,
+    pub drafts: bool,
+    pub live_reload: bool,
+    /// Build unpublished pages with a noindex meta, rather than leaving them out
+    pub hidden_noindex: bool,
+    pub line_numbers: bool,
+    pub highlight_code: bool,
+    pub gfm: bool,
+    pub figures: bool,
+    pub port_fallback: bool,
+    pub safe_mode: bool,
+    pub min_image_width: String,
+    pub min_image_height: String,
+    pub max_image_width: String,
+    pub max_image_height: String,
+    pub paste_format: PasteFormat,
+    pub auto_deploy_delay: String,
+    pub deploy_timeout: String,
+    pub server_port: String,
+    pub server_concurrency: String,
+    /// What the config was when the form opened, to see what changed
+    original: SiteConfig
+}
+
+impl From<&SiteConfig> for ConfigForm {
+    fn from(config: &SiteConfig) -> Self {
+        let text = |value: &Option<String>| value.clone().unwrap_or_default();
+        let number = |value: Option<u32>| value.map(|n| n.to_string()).unwrap_or_default();
+        Self {
+            title: text(&config.title),
+            feed_title: text(&config.feed_title),
+            base_url: text(&config.base_url),
+            favicon: text(&config.favicon),
+            default_layout: text(&config.default_layout),
+            image_dir: config.image_dir.clone(),
+            image_name: config.image_name.clone(),
+            preserve: config.preserve.join(", "),
+            output_dir: text(&config.output_dir),
+            inject_meta: config.inject_meta,
+            strict: config.strict,
+            drafts: config.drafts,
+            live_reload: config.live_reload,
+            hidden_noindex: config.hidden_mode == HiddenMode::Noindex,
+            line_numbers: config.line_numbers,
+            highlight_code: config.highlight_code,
+            gfm: config.gfm,
+            figures: config.figures,
+            port_fallback: config.port_fallback,
+            safe_mode: config.safe_mode,
+            min_image_width: number(config.min_image_width),
+            min_image_height: number(config.min_image_height),
+            max_image_width: number(config.max_image_width),
+            max_image_height: number(config.max_image_height),
+            paste_format: config.paste_format,
+            auto_deploy_delay: config.auto_deploy_delay.to_string(),
+            deploy_timeout: config.deploy_timeout.to_string(),
+            server_port: config.server_port.to_string(),
+            server_concurrency: config.server_concurrency.to_string(),
+            original: config.clone()
+        }
+    }
+}
+
+impl ConfigForm {
+    /// The config the form describes, or what's wrong with it
+    pub fn to_config(&self) -> Result<SiteConfig, String> {
+        let text = |value: &str| Some(value.trim().to_string()).filter(|s| !s.is_empty());
+        let optional_number = |name: &str, value: &str| -> Result<Option<u32>, String> {
+            text(value).map(|v| v.parse().map_err(|_| format!("{} has to be a whole number", name))).transpose()
+        };
+        let base_url = text(&self.base_url);
+        if let Some(url) = &base_url && !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err("Base url has to start with http:// or https://".to_string())
+        }
+        let server_concurrency = self.server_concurrency.trim().parse().ok().filter(|n| *n > 0)
+            .ok_or("Server concurrency has to be a number above zero")?;
+
+        Ok(SiteConfig {
+            title: text(&self.title),
+            feed_title: text(&self.feed_title),
+            base_url,
+            favicon: text(&self.favicon),
+            default_layout: text(&self.default_layout),
+            image_dir: text(&self.image_dir)
+                .filter(|dir| Path::new(dir).components().all(|c| matches!(c, Component::Normal(_))))
+                .ok_or("Image dir has to be a folder inside the site")?,
+            image_name: text(&self.image_name).filter(|name| !name.contains(['/', '\\'])).ok_or("Image names can't be blank or have slashes in them")?,
+            preserve: self.preserve.split(',').filter_map(text).collect(),
+            output_dir: text(&self.output_dir),
+            inject_meta: self.inject_meta,
+            strict: self.strict,
+            drafts: self.drafts,
+            live_reload: self.live_reload,
+            hidden_mode: if self.hidden_noindex { HiddenMode::Noindex } else { HiddenMode::Omit },
+            line_numbers: self.line_numbers,
+            highlight_code: self.highlight_code,
+            gfm: self.gfm,
+            figures: self.figures,
+            safe_mode: self.safe_mode,
+            min_image_width: optional_number("Minimum image width", &self.min_image_width)?,
+            min_image_height: optional_number("Minimum image height", &self.min_image_height)?,
+            max_image_width: optional_number("Maximum image width", &self.max_image_width)?.filter(|n| *n > 0),
+            max_image_height: optional_number("Maximum image height", &self.max_image_height)?.filter(|n| *n > 0),
+            paste_format: self.paste_format,
+            auto_deploy_delay: self.auto_deploy_delay.trim().parse().map_err(|_| "Auto deploy delay has to be a whole number of seconds")?,
+            deploy_timeout: self.deploy_timeout.trim().parse().map_err(|_| "Deploy timeout has to be a whole number of seconds")?,
+            server_port: self.server_port.trim().parse().map_err(|_| "Server port has to be a number from 0 to 65535")?,
+            server_concurrency,
+            port_fallback: self.port_fallback,
+            // Not on the form; edit the file for these
+            layouts: self.original.layouts.clone(),
+            fs_retries: self.original.fs_retries,
+            git_deploy: self.original.git_deploy.clone(),
+            build_id: None
+        })
+    }
+
+    /// Write the changed settings into a config file's text, leaving everything else alone
+    pub fn apply(&self, contents: &str) -> Result<String, String> {
+        let new = self.to_config()?;
+        let old = &self.original;
+        let mut doc: DocumentMut = contents.parse().map_err(|e: toml_edit::TomlError| e.to_string())?;
+        let mut set = |key: &str, value: Option<toml_edit::Value>| match value {
+            Some(value) => { doc[key] = toml_edit::value(value); }
+            None => { doc.remove(key); }
+        };
+
+        if new.title != old.title { set("title", new.title.clone().map(Into::into)) }
+        if new.feed_title != old.feed_title { set("feed_title", new.feed_title.clone().map(Into::into)) }
+        if new.base_url != old.base_url { set("base_url", new.base_url.clone().map(Into::into)) }
+        if new.favicon != old.favicon { set("favicon", new.favicon.clone().map(Into::into)) }
+        if new.image_dir != old.image_dir { set("image_dir", Some(new.image_dir.as_str().into())) }
+        if new.image_name != old.image_name { set("image_name", Some(new.image_name.as_str().into())) }
+        if new.default_layout != old.default_layout { set("default_layout", new.default_layout.clone().map(Into::into)) }
+        if new.output_dir != old.output_dir { set("output_dir", new.output_dir.clone().map(Into::into)) }
+        if new.preserve != old.preserve { set("preserve", Some(new.preserve.iter().collect::<toml_edit::Array>().into())) }
+        if new.inject_meta != old.inject_meta { set("inject_meta", Some(new.inject_meta.into())) }
+        if new.strict != old.strict { set("strict", Some(new.strict.into())) }
+        if new.drafts != old.drafts { set("drafts", Some(new.drafts.into())) }
+        if new.live_reload != old.live_reload { set("live_reload", Some(new.live_reload.into())) }
+        if new.hidden_mode != old.hidden_mode { set("hidden_mode", Some(new.hidden_mode.name().into())) }
+        if new.line_numbers != old.line_numbers { set("line_numbers", Some(new.line_numbers.into())) }
+        if new.highlight_code != old.highlight_code { set("highlight_code", Some(new.highlight_code.into())) }
+        if new.gfm != old.gfm { set("gfm", Some(new.gfm.into())) }
+        if new.figures != old.figures { set("figures", Some(new.figures.into())) }
+        if new.safe_mode != old.safe_mode { set("safe_mode", Some(new.safe_mode.into())) }
+        if new.min_image_width != old.min_image_width { set("min_image_width", new.min_image_width.map(|n| (n as i64).into())) }
+        if new.min_image_height != old.min_image_height { set("min_image_height", new.min_image_height.map(|n| (n as i64).into())) }
+        if new.max_image_width != old.max_image_width { set("max_image_width", new.max_image_width.map(|n| (n as i64).into())) }
+        if new.max_image_height != old.max_image_height { set("max_image_height", new.max_image_height.map(|n| (n as i64).into())) }
+        if new.paste_format != old.paste_format { set("paste_format", Some(new.paste_format.name().into())) }
+        if new.auto_deploy_delay != old.auto_deploy_delay { set("auto_deploy_delay", Some((new.auto_deploy_delay as i64).into())) }
+        if new.deploy_timeout != old.deploy_timeout { set("deploy_timeout", Some((new.deploy_timeout as i64).into())) }
+        if new.server_port != old.server_port { set("server_port", Some((new.server_port as i64).into())) }
+        if new.server_concurrency != old.server_concurrency { set("server_concurrency", Some((new.server_concurrency as i64).into())) }
+        if new.port_fallback != old.port_fallback { set("port_fallback", Some(new.port_fallback.into())) }
+        Ok(doc.to_string())
+    }
+
+    /// Save the form into the site's `_config.toml`, creating it if need be
+    pub fn save(&self, root: &Path) -> Result<(), FleenError> {
+        let path = config_path(root);
+        let io_error = |e: std::io::Error| FleenError::FileIo(path.to_string_lossy().to_string(), e.to_string());
+        let contents = if path.exists() { fs::read_to_string(&path).map_err(io_error)? } else { String::new() };
+        let contents = self.apply(&contents).map_err(FleenError::ConfigParse)?;
+        fs::write(&path, contents).map_err(io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_form_preserves_other_keys() {
+        let contents = "# My site\ntitle = \"Old\" # the name\nstrict = true\nmystery = [1, 2]\n";
+        let config: SiteConfig = toml::from_str(contents).unwrap();
+        let mut form = ConfigForm::from(&config);
+        form.base_url = "https://example.com".to_string();
+
+        let saved = form.apply(contents).unwrap();
+        assert_eq!(saved, "# My site\ntitle = \"Old\" # the name\nstrict = true\nmystery = [1, 2]\nbase_url = \"https://example.com\"\n");
+        let reloaded: SiteConfig = toml::from_str(&saved).unwrap();
+        assert_eq!(reloaded, form.to_config().unwrap());
+
+        // Clearing a field removes its key
+        let mut form = ConfigForm::from(&reloaded);
+        form.title = String::new();
+        assert!(!form.apply(&saved).unwrap().contains("title"));
+    }
+
+    #[test]
+    fn test_form_validation() {
+        let mut form = ConfigForm::from(&SiteConfig::default());
+        form.min_image_width = "wide".to_string();
+        assert!(form.to_config().is_err());
+        form.min_image_width = " 640 ".to_string();
+        assert_eq!(form.to_config().unwrap().min_image_width, Some(640));
+
+        form.base_url = "example.com".to_string();
+        assert!(form.apply("").is_err());
+        form.base_url = String::new();
+        form.server_concurrency = "0".to_string();
+        assert!(form.to_config().is_err());
+        form.server_concurrency = "16".to_string();
+        form.server_port = "70000".to_string();
+        assert!(form.to_config().is_err());
+        form.server_port = "8080".to_string();
+        for outside in ["", "/home/me/pictures", "../pictures", "images/../.."] {
+            form.image_dir = outside.to_string();
+            assert!(form.to_config().is_err());
+        }
+        form.image_dir = "assets/images".to_string();
+        assert_eq!(form.apply("").unwrap(), "image_dir = \"assets/images\"\nmin_image_width = 640\nserver_port = 8080\n");
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::VecDeque;
+
+/// The lines of output from the most recent script run, for the console panel. Only the most
+/// recent `capacity` lines are kept, so a chatty script can't eat all our memory.
+pub struct ConsoleLog {
+    lines: VecDeque<String>,
+    capacity: usize
+}
+
+impl ConsoleLog {
+    pub fn new(capacity: usize) -> Self {
+        Self { lines: VecDeque::new(), capacity }
+    }
+
+    /// Add one line, dropping the oldest line if we're full
+    pub fn push(&mut self, line: impl Into<String>) {
+        if self.lines.len() >= self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line.into())
+    }
+
+    /// Add a whole blob of output, a line at a time
+    pub fn push_text(&mut self, text: &str) {
+        for line in text.lines() {
+            self.push(line)
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear()
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &String> {
+        self.lines.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Everything in the log as one string, for copying to the clipboard
+    pub fn text(&self) -> String {
+        self.lines.iter().map(String::as_str).collect::<Vec<_>>().join("\n")
+    }
+}
+
+impl Default for ConsoleLog {
+    fn default() -> Self {
+        Self::new(1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_and_clear() {
+        let mut log = ConsoleLog::new(10);
+        assert!(log.is_empty());
+        log.push("Deploying...");
+        log.push_text("sent 3 files\nDone!\n");
+        assert_eq!(log.text(), "Deploying...\nsent 3 files\nDone!");
+
+        log.clear();
+        assert!(log.is_empty());
+        assert_eq!(log.text(), "");
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut log = ConsoleLog::new(3);
+        for n in 1..=5 {
+            log.push(format!("line {}", n))
+        }
+        // Only the newest three survive
+        assert_eq!(log.lines().cloned().collect::<Vec<_>>(), vec!["line 3", "line 4", "line 5"]);
+    }
+}
@@ -0,0 +1,91 @@
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use chrono::NaiveDateTime;
+use crate::renderer::{frontmatter_fields, parse_date, RenderError, RenderOutput};
+use crate::sitemap::page_url;
+
+/// A dated page, as it appears in the site's `feed.xml`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeedItem {
+    pub title: String,
+    /// Feeds want a time zone, and dates don't keep theirs, so this is taken as UTC
+    pub date: NaiveDateTime,
+    /// Where the page is in the build
+    pub path: PathBuf,
+    /// The page's `description`, if it has one
+    pub description: Option<String>
+}
+
+/// Every built page (paired with its source) that belongs in the feed: published html pages with
+/// a `date` in their frontmatter. Pages built with a noindex meta are unpublished, so they're out.
+pub fn feed_items(pages: &[(PathBuf, RenderOutput)], root: &Path) -> Result<Vec<FeedItem>, RenderError> {
+    let mut items = vec![];
+    for (source, output) in pages {
+        let RenderOutput::Rendered(path, _) = output else { continue };
+        if path.extension().is_none_or(|e| e != "html") { continue }
+        let Some(fields) = frontmatter_fields(source, root)? else { continue };
+        let field = |key: &str| fields.iter().find(|(k, _)| k == key).map(|(_, value)| value.clone());
+        if field("published").is_some_and(|p| p == "false") { continue }
+        let Some(date) = field("date").as_deref().and_then(parse_date) else { continue };
+        items.push(FeedItem {
+            title: field("title").unwrap_or_else(|| source.file_stem().unwrap_or_default().to_string_lossy().to_string()),
+            date,
+            path: path.clone(),
+            description: field("description")
+        })
+    }
+    Ok(items)
+}
+
+/// Whether the site makes its own `feed.xml`, which we shouldn't replace
+pub fn has_feed<'a>(mut outputs: impl Iterator<Item=&'a RenderOutput>) -> bool {
+    outputs.any(|output| matches!(output,
+        RenderOutput::Rendered(path, _) | RenderOutput::Hidden(path, _) | RenderOutput::Draft(path, _) | RenderOutput::RawFile(path)
+        if path == Path::new("feed.xml")))
+}
+
+/// An RSS 2.0 document of the items, newest first
+pub fn rss(mut items: Vec<FeedItem>, title: &str, base_url: &str) -> String {
+    let base = base_url.trim_end_matches('/');
+    items.sort_by_key(|item| Reverse(item.date));
+    let entries: Vec<String> = items.iter().map(|item| {
+        let url = escape(&page_url(base, &item.path));
+        let description = item.description.as_ref()
+            .map(|d| format!("      <description>{}</description>\n", escape(d)))
+            .unwrap_or_default();
+        format!("    <item>\n      <title>{}</title>\n      <link>{}</link>\n      <guid>{}</guid>\n      <pubDate>{}</pubDate>\n{}    </item>\n",
+                escape(&item.title), url, url, item.date.and_utc().to_rfc2822(), description)
+    }).collect();
+    format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">\n  <channel>\n    <title>{}</title>\n    <link>{}/</link>\n    <description>{}</description>\n{}  </channel>\n</rss>\n",
+            escape(title), escape(base), escape(title), entries.concat())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rss() {
+        let item = |title: &str, date: &str, path: &str| FeedItem {
+            title: title.to_string(), date: parse_date(date).unwrap(), path: PathBuf::from(path), description: None
+        };
+        let items = vec![
+            item("Older", "2024-01-01", "posts/older.html"),
+            FeedItem { description: Some("Fish & chips".to_string()), ..item("Newer <3", "2024-06-01", "posts/newer/index.html") }
+        ];
+        assert_eq!(rss(items, "My Blog", "https://example.com/"), concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">\n  <channel>\n",
+            "    <title>My Blog</title>\n    <link>https://example.com/</link>\n    <description>My Blog</description>\n",
+            "    <item>\n      <title>Newer &lt;3</title>\n      <link>https://example.com/posts/newer/</link>\n",
+            "      <guid>https://example.com/posts/newer/</guid>\n      <pubDate>Sat, 1 Jun 2024 00:00:00 +0000</pubDate>\n",
+            "      <description>Fish &amp; chips</description>\n    </item>\n",
+            "    <item>\n      <title>Older</title>\n      <link>https://example.com/posts/older.html</link>\n",
+            "      <guid>https://example.com/posts/older.html</guid>\n      <pubDate>Mon, 1 Jan 2024 00:00:00 +0000</pubDate>\n    </item>\n",
+            "  </channel>\n</rss>\n"
+        ));
+    }
+}
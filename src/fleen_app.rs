@@ -1,15 +1,29 @@
 use std::{fs, io};
+use std::io::Write;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::fmt::{Display, Formatter};
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::sync::Arc;
-use clipboard_rs::Clipboard;
-use clipboard_rs::common::RustImage;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::time::SystemTime;
+use rayon::prelude::*;
 use thiserror::Error;
 use crate::fleen_app::FleenError::{RootDirNonexistence, RootDirPopulated, TargetDir};
 use crate::fleen_app::TreeEntry::{CloseDir, Dir};
-use crate::{renderer, utils};
-use crate::renderer::{RenderError, RenderOutput};
+use crate::{feed, import, live_diff, renderer, sitemap, utils};
+use crate::build_info::BuildInfo;
+use crate::clipboard::{ImageClipboard, SystemClipboard};
+use crate::renderer::{RenderError, RenderOutput, ResolvedSource};
+use crate::config::{HiddenMode, PasteFormat, SiteConfig};
+use crate::live_diff::LiveDiff;
+use crate::output::{FsSink, InMemorySink, OutputSink};
+use crate::watch_build::WatchBuild;
 
 #[derive(Error, Debug)]
 pub enum FleenError {
@@ -27,6 +41,10 @@ pub enum FleenError {
     NoImageDir,
     #[error("No image on clipboard")]
     NoClipboardImage,
+    #[error("Can't convert the image: {0}")]
+    ImageConvert(String),
+    #[error("Can't reach the clipboard ({0}). On Linux, Fleen needs a running X11 or Wayland session for this")]
+    ClipboardUnavailable(String),
     #[error("Render error: {0}")]
     RenderError(#[from] RenderError),
     #[error("Target dir is invalid (can't contain the app dir or vice versa)")]
@@ -35,17 +53,190 @@ pub enum FleenError {
     Io(#[from] io::Error),
     #[error("Deploy script missing! Create _scripts/deploy.sh")]
     ScriptMissing,
+    #[error("Safe mode is on, so Fleen won't {0}")]
+    SafeMode(String),
+    #[error("Set up [git_deploy] in _config.toml to deploy to a branch")]
+    NoGitDeploy,
     #[error("Deploy script error:\n\n{0}")]
-    DeployError(String)
+    DeployError(String),
+    #[error("Deploy timed out")]
+    DeployTimedOut,
+    #[error("Deploy cancelled")]
+    DeployCancelled,
+    #[error("Can't move {0} to the trash: {1}")]
+    TrashFailed(String, String),
+    #[error("{0} isn't a page")]
+    NotAPage(PathBuf),
+    #[error("Can't start the server on port {0}: {1}")]
+    ServerBind(u16, String),
+    #[error("{0} isn't a valid name")]
+    InvalidName(String),
+    #[error("Can't rename to {0}: it would collide with another file")]
+    RenameCollision(PathBuf),
+    #[error("Error parsing _config.toml: {0}")]
+    ConfigParse(String),
+    #[error("Set base_url in _config.toml so we know where the live site is")]
+    NoBaseUrl,
+    #[error("Error fetching {0} from the live site: {1}")]
+    LiveSite(String, String),
+    #[error("Can't import feed: {0}")]
+    Import(String),
+    #[error("The site has problems that need fixing first:\n\n{}", .0.iter().map(|d| d.to_string()).collect::<Vec<_>>().join("\n"))]
+    Invalid(Vec<Diagnostic>)
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Severity {
+    /// The site will build, but maybe not how you wanted
+    Warning,
+    /// The site won't build
+    Error
+}
+
+/// A problem found while validating a site, and the (relative) source file it's in
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: PathBuf,
+    pub message: String
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity { Severity::Warning => "warning", Severity::Error => "error" };
+        write!(f, "{}: {}: {}", severity, self.file.display(), self.message)
+    }
+}
+
+/// A link or image in a built page that points at something the build doesn't have
+#[derive(Clone, Debug, PartialEq)]
+pub struct BrokenLink {
+    /// The (relative) source file the link is in: the page, or the layout if it came from there
+    pub file: PathBuf,
+    pub url: String
+}
+
+impl Display for BrokenLink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.file.display(), self.url)
+    }
+}
+
+impl From<BrokenLink> for Diagnostic {
+    fn from(link: BrokenLink) -> Self {
+        Diagnostic { severity: Severity::Warning, file: link.file, message: format!("Broken link to {}", link.url) }
+    }
+}
+
+/// The flattened file tree: each Dir is followed by its contents and then a CloseDir. Files and
+/// dirs carry their last-modified time, if the filesystem would tell us.
 #[derive(Clone, Debug)]
 pub enum TreeEntry {
-    File(PathBuf),
-    Dir(PathBuf),
+    File(PathBuf, Option<SystemTime>),
+    Dir(PathBuf, Option<SystemTime>),
     CloseDir
 }
 
+impl TreeEntry {
+    pub fn modified(&self) -> Option<SystemTime> {
+        match self {
+            TreeEntry::File(_, m) | Dir(_, m) => *m,
+            CloseDir => None
+        }
+    }
+
+    fn sort_name(&self) -> String {
+        match self {
+            TreeEntry::File(p, _) | Dir(p, _) => utils::label_for_path(p).to_lowercase(),
+            CloseDir => String::new()
+        }
+    }
+}
+
+/// The orders we can show the tree in. Sorting only reorders entries within a directory.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TreeSort {
+    Name, NewestFirst, OldestFirst
+}
+
+/// Compare two sibling entries. Ties (and missing mtimes) fall back to the name.
+pub fn compare_entries(a: &TreeEntry, b: &TreeEntry, sort: TreeSort) -> Ordering {
+    let by_time = match sort {
+        TreeSort::Name => Ordering::Equal,
+        TreeSort::NewestFirst => b.modified().cmp(&a.modified()),
+        TreeSort::OldestFirst => a.modified().cmp(&b.modified())
+    };
+    by_time.then_with(|| a.sort_name().cmp(&b.sort_name()))
+}
+
+/// Reorder a tree (as produced by `read_tree`) so each directory's children are in the given order
+pub fn sort_tree(tree: &[TreeEntry], sort: TreeSort) -> Vec<TreeEntry> {
+    // Sort the contents of one dir (not including its Dir / CloseDir) into out
+    fn sort_level(entries: &[TreeEntry], sort: TreeSort, out: &mut Vec<TreeEntry>) {
+        // Group each child with everything inside it, so we can move whole subtrees around
+        let mut groups: Vec<&[TreeEntry]> = vec![];
+        let mut i = 0;
+        while i < entries.len() {
+            let start = i;
+            let mut depth = 0;
+            loop {
+                match entries[i] {
+                    Dir(..) => depth += 1,
+                    CloseDir => depth -= 1,
+                    TreeEntry::File(..) => {}
+                }
+                i += 1;
+                if depth <= 0 || i >= entries.len() { break }
+            }
+            groups.push(&entries[start..i]);
+        }
+
+        groups.sort_by(|a, b| compare_entries(&a[0], &b[0], sort));
+        for group in groups {
+            out.push(group[0].clone());
+            if let Dir(..) = group[0] {
+                sort_level(&group[1..group.len() - 1], sort, out);
+                out.push(CloseDir);
+            }
+        }
+    }
+
+    let mut sorted = vec![];
+    sort_level(tree, sort, &mut sorted);
+    sorted
+}
+
+/// Just the parts of a tree (as produced by `read_tree`) whose paths under the root contain the
+/// query, ignoring case, along with the dirs they're in. The root dir always stays.
+pub fn filter_tree(tree: &[TreeEntry], root: &Path, query: &str) -> Vec<TreeEntry> {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() { return tree.to_vec() }
+    let matches = |path: &Path| path.strip_prefix(root).unwrap_or(path).to_string_lossy().to_lowercase().contains(&query);
+
+    // Each open dir, whether it matched, and what's been kept inside it so far
+    let mut open: Vec<(&TreeEntry, bool, Vec<TreeEntry>)> = vec![];
+    let mut filtered = vec![];
+    for entry in tree {
+        match entry {
+            Dir(path, _) => open.push((entry, matches(path), vec![])),
+            TreeEntry::File(path, _) if matches(path) => match open.last_mut() {
+                Some((_, _, kept)) => kept.push(entry.clone()),
+                None => filtered.push(entry.clone())
+            },
+            TreeEntry::File(..) => {}
+            CloseDir => {
+                let Some((dir, matched, kept)) = open.pop() else { continue };
+                if !matched && kept.is_empty() && !open.is_empty() { continue }
+                let parent = match open.last_mut() { Some((_, _, kept)) => kept, None => &mut filtered };
+                parent.push(dir.clone());
+                parent.extend(kept);
+                parent.push(CloseDir);
+            }
+        }
+    }
+    filtered
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum FileType {
     File, Dir
@@ -54,12 +245,68 @@ pub enum FileType {
 pub struct Site {
     pub tree: Vec<TreeEntry>,
     pub root: PathBuf,
+    pub config: SiteConfig,
+    /// Built the first time it's needed, rather than every time the site is reopened
+    image_index: OnceLock<ImageIndex>,
+}
+
+/// Content hashes of the images in the image dir, so pasting an image we already have can reuse it.
+/// Reading every image is slow, so a site only builds one once something's pasted.
+#[derive(Default, Debug)]
+pub struct ImageIndex(HashMap<u64, Vec<PathBuf>>);
+
+impl ImageIndex {
+    pub fn build(image_dir: &Path) -> Self {
+        let mut index = HashMap::<u64, Vec<PathBuf>>::new();
+        for entry in image_dir.read_dir().into_iter().flatten().flatten() {
+            let path = entry.path();
+            if !path.is_file() { continue }
+            if let Ok(bytes) = fs::read(&path) {
+                index.entry(content_hash(&bytes)).or_default().push(path)
+            }
+        }
+        Self(index)
+    }
+
+    /// An image with exactly these contents, if we have one
+    pub fn find(&self, bytes: &[u8]) -> Option<&PathBuf> {
+        // Hashes can collide, so make sure it's really the same
+        self.0.get(&content_hash(bytes))?.iter().find(|path| fs::read(path).is_ok_and(|b| b == bytes))
+    }
+}
+
+fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Extensions of the files that count as images when they're dropped on the window
+const IMAGE_EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "gif", "webp", "svg", "avif"];
+
+/// Whether a file looks like an image, going by its name
+pub fn is_image_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// The markdown for showing an image in the site
+pub fn image_link(path: &Path, root: &Path) -> String {
+    let url = path.strip_prefix(root).unwrap_or(path).to_string_lossy().replace('\\', "/");
+    format!("![](/{})", url.trim_start_matches('/'))
 }
 
 impl Site {
     pub fn open(root: &Path) -> Result<Self, FleenError> {
         match root.try_exists() {
-            Ok(true) => Ok(Self { root: root.to_path_buf(), tree: read_tree(root)? }),
+            Ok(true) => {
+                let config = SiteConfig::load(root)?;
+                Ok(Self {
+                    root: root.to_path_buf(),
+                    tree: read_tree(root, &config)?,
+                    image_index: OnceLock::new(),
+                    config
+                })
+            }
             _ => Err(RootDirNonexistence(root.to_path_buf()))
         }
     }
@@ -79,121 +326,470 @@ impl Site {
             }
         }
     }
+
+    /// How many files in the tree go into a build, leaving out the skipped ones like layouts
+    /// and scripts
+    pub fn renderable_count(&self) -> usize {
+        self.tree.iter().filter(|entry| match entry {
+            TreeEntry::File(path, _) => !renderer::skipped_path(path.strip_prefix(&self.root).unwrap_or(path).to_path_buf()),
+            _ => false
+        }).count()
+    }
+
+    /// Hashes of the images already in the image dir
+    pub fn image_index(&self) -> &ImageIndex {
+        self.image_index.get_or_init(|| ImageIndex::build(&self.config.image_path(&self.root)))
+    }
+
+    /// Ensure neither the target nor src dirs are ancestors of the other. The configured
+    /// output dir is the exception, since it's never part of the build.
+    fn check_target(&self, target: &Path) -> Result<(), FleenError> {
+        let nested_output = self.config.nested_output_dir(&self.root).is_some_and(|dir| self.root.join(dir) == target);
+        if self.root.ancestors().any(|a| a == target) ||
+            (target.ancestors().any(|a| a == self.root) && !nested_output) {
+            return Err(TargetDir)
+        }
+        Ok(())
+    }
 }
 
-// TODO: This is panicky as hell, make it return a Result
-fn read_tree(root: &Path) -> Result<Vec<TreeEntry>, FleenError> {
+/// The whole site as a flattened tree. A dir we can't read is an error, but a file with a name
+/// that isn't UTF-8 is just left out: nothing else in Fleen could do anything with it anyway.
+fn read_tree(root: &Path, config: &SiteConfig) -> Result<Vec<TreeEntry>, FleenError> {
     let mut entries = vec![];
 
-    fn visit_dir(dir: &Path, entries: &mut Vec<TreeEntry>) {
-        for entry in dir.read_dir().unwrap() {
-            let path = entry.unwrap().path();
-            if path.file_name().unwrap().to_str().unwrap().starts_with('.') { continue }
+    fn visit_dir(dir: &Path, root: &Path, config: &SiteConfig, entries: &mut Vec<TreeEntry>) -> Result<(), FleenError> {
+        let io_error = |e: io::Error| FleenError::FileIo(dir.to_string_lossy().to_string(), e.to_string());
+        for entry in dir.read_dir().map_err(io_error)? {
+            let path = entry.map_err(io_error)?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else { continue };
+            if name.starts_with('.') { continue }
+            if config.excludes(root, path.strip_prefix(root).unwrap_or(&path)) { continue }
+            let modified = path.metadata().and_then(|m| m.modified()).ok();
             if path.is_file() {
-                entries.push(TreeEntry::File(path))
+                entries.push(TreeEntry::File(path, modified))
             } else if path.is_dir() {
-                entries.push(Dir(path.clone()));
-                visit_dir(&path, entries);
+                entries.push(Dir(path.clone(), modified));
+                visit_dir(&path, root, config, entries)?;
                 entries.push(CloseDir)
             }
         }
+        Ok(())
     }
 
-    entries.push(Dir(root.to_path_buf()));
-    visit_dir(root, &mut entries);
+    entries.push(Dir(root.to_path_buf(), root.metadata().and_then(|m| m.modified()).ok()));
+    visit_dir(root, root, config, &mut entries)?;
     entries.push(CloseDir);
 
     Ok(entries)
 }
 
+/// Every file and dir in the site, relative to the root, with each dir ahead of what's in it.
+/// Like `read_tree`, a dir we can't read is an error and a name that isn't UTF-8 is left out.
+fn source_paths(root: &Path, config: &SiteConfig) -> Result<Vec<PathBuf>, FleenError> {
+    let mut sources = vec![];
+
+    // Dir is the directory path within the app root, like "assets". File is the filename (or
+    // child dir name) within the dir, so root+dir+file is an absolute path
+    fn visit_dir(dir: &Path, root: &Path, config: &SiteConfig, sources: &mut Vec<PathBuf>) -> Result<(), FleenError> {
+        let io_error = |e: io::Error| FleenError::FileIo(root.join(dir).to_string_lossy().to_string(), e.to_string());
+        for entry in root.join(dir).read_dir().map_err(io_error)? {
+            let name = entry.map_err(io_error)?.file_name();
+            let Some(file) = name.to_str().map(PathBuf::from) else { continue };
+            if config.excludes(root, &dir.join(&file)) { continue }
+            sources.push(dir.join(&file));
+            if root.join(dir).join(&file).is_dir() {
+                // root + dir + file is a child directory, so we want to recurse into dir + file
+                visit_dir(&dir.join(&file), root, config, sources)?
+            }
+        }
+        Ok(())
+    }
+    visit_dir(Path::new(""), root, config, &mut sources)?;
+    Ok(sources)
+}
+
 pub trait SiteActions: Deref<Target=Site> + Clone {
-    fn build_site(&self, target: &Path) -> Result<(), FleenError> {
-        // Ensure neither the target nor src dirs are ancestors of the other
-        if self.root.ancestors().any(|a| a == target) ||
-            target.ancestors().any(|a| a == self.root) {
-            return Err(TargetDir)
+    /// Build the site into a directory on disk. See `build_site`.
+    fn build_to_dir(&self, target: &Path, strict: bool) -> Result<Vec<Diagnostic>, FleenError> {
+        self.check_target(target)?;
+        self.build_site(&mut FsSink::new(target).with_retries(self.config.fs_retries), strict)
+    }
+
+    /// Keep a build of the site in `target` up to date as the site changes, until the returned
+    /// handle is dropped. See `WatchBuild`. The target has to be somewhere `build_to_dir` would
+    /// build to; a build anywhere else in the site would look like a change to the site, and
+    /// set off another build, forever.
+    fn watch_build(&self, target: &Path, strict: bool) -> Result<WatchBuild, FleenError> {
+        self.check_target(target)?;
+        Ok(WatchBuild::start(&self.root, target, strict))
+    }
+
+    /// Build the site into the sink, returning any warnings. In strict mode, warnings
+    /// fail the build just like errors do.
+    fn build_site(&self, sink: &mut dyn OutputSink, strict: bool) -> Result<Vec<Diagnostic>, FleenError> {
+        // Don't touch the target at all if we already know the build will fail
+        let (errors, mut warnings): (Vec<_>, Vec<_>) = self.validate().into_iter().partition(|d| d.severity == Severity::Error);
+        if !errors.is_empty() {
+            return Err(FleenError::Invalid(errors))
+        } else if strict && !warnings.is_empty() {
+            return Err(FleenError::Invalid(warnings))
         }
 
-        // Clear the target directory first:
-        for entry in fs::read_dir(target)? {
-            let entry = entry?;
-            if entry.file_type()?.is_dir() {
-                fs::remove_dir_all(entry.path())?
-            } else {
-                fs::remove_file(entry.path())?
+        // Decide which actions we need to do to build the site, stamped with where it came from
+        let info = BuildInfo::for_site(&self.root);
+        let config = SiteConfig { build_id: Some(info.build_id.clone()), ..self.config.clone() };
+        let compiled = compile_sources(&self.root, &config)?;
+
+        // Plus the files we make ourselves
+        let mut generated = vec![(PathBuf::from("build-info.json"), info.to_json())];
+
+        // A sitemap and a feed both need absolute urls, so we can only make them if we know where
+        // the site lives, and only if the site hasn't got its own
+        if let Some(base_url) = &self.config.base_url && !sitemap::has_sitemap(compiled.iter().map(|(_, output)| output)) {
+            generated.push((PathBuf::from("sitemap.xml"), sitemap::sitemap(compiled.iter().map(|(_, output)| output), base_url)));
+        }
+        if let Some(base_url) = &self.config.base_url && !feed::has_feed(compiled.iter().map(|(_, output)| output)) {
+            let items = feed::feed_items(&compiled, &self.root)?;
+            let title = self.config.feed_title.as_ref().or(self.config.title.as_ref()).unwrap_or(base_url);
+            if !items.is_empty() {
+                generated.push((PathBuf::from("feed.xml"), feed::rss(items, title, base_url)));
             }
         }
+        let favicon = self.config.favicon_output();
 
-        // Decide which actions we need to do to build the site
-        let actions = self.compile()?;
+        // Layouts and html pages can have broken links too, which validating the sources can't
+        // see. Links in markdown that it already warned about aren't worth saying twice.
+        let mut made: Vec<PathBuf> = generated.iter().map(|(path, _)| path.clone()).collect();
+        made.extend(favicon.iter().map(|(_, name)| PathBuf::from(name)));
+        for diagnostic in check_links(&compiled, &self.root, &self.config, &made).into_iter().map(Diagnostic::from) {
+            if !warnings.contains(&diagnostic) { warnings.push(diagnostic) }
+        }
+        if strict && !warnings.is_empty() {
+            return Err(FleenError::Invalid(warnings))
+        }
+
+        // Clear the target first, except for anything we were told to keep:
+        sink.clear(&self.config.preserve)?;
 
         // And then do them!
-        for action in actions.into_iter() {
-            action.file_operation(&self.root, target)?;
+        for (_, action) in compiled.iter() {
+            action.file_operation(&self.root, sink)?;
         }
-        Ok(())
+        for (path, contents) in generated {
+            sink.write(&path, contents.as_bytes())?;
+        }
+
+        // A favicon from elsewhere in the site gets copied to where browsers look for it
+        if let Some((source, name)) = favicon {
+            sink.copy(&self.root.join(&source), Path::new(&name))
+                .map_err(|e| FleenError::FileIo(source.to_string_lossy().to_string(), e.to_string()))?;
+        }
+        Ok(warnings)
     }
 
     fn compile(&self) -> Result<Vec<RenderOutput>, FleenError> {
-        let mut sources = vec![]; // The list of renderoutputs we need to perform
-
-        // Traverse a directory
-        fn visit_dir(dir: &Path, root: &Path, sources: &mut Vec<RenderOutput>) -> Result<(), RenderError> {
-            // Root is the app root. Dir is the directory path within the app root, like "assets".
-            // File is the filename (or child dir name) within the dir, so, root+dir+file is an
-            // absolute path
-            for entry in root.join(dir).read_dir().unwrap() {
-                let file = PathBuf::from(entry.unwrap().file_name());
-                sources.push(renderer::file_render(dir.join(&file), root)?);
-                if root.join(dir).join(&file).is_dir() {
-                    // root + dir + file is a child directory, so we want to recurse...
-                    // into dir + file.
-                    visit_dir(&dir.join(&file), root, sources)?
+        compile_site(&self.root, &self.config)
+    }
+
+    /// How much a build would produce, without producing it
+    fn build_summary(&self) -> Result<BuildSummary, FleenError> {
+        let mut summary = BuildSummary::default();
+        for output in self.compile()? {
+            match output {
+                RenderOutput::Rendered(_, contents) => {
+                    summary.pages += 1;
+                    summary.bytes += contents.len() as u64
+                }
+                RenderOutput::RawFile(path) => {
+                    summary.assets += 1;
+                    summary.bytes += self.root.join(path).metadata().map(|m| m.len()).unwrap_or(0)
+                }
+                RenderOutput::Stylesheet(_, css) => {
+                    summary.assets += 1;
+                    summary.bytes += css.len() as u64
                 }
+                _ => {}
             }
-            Ok(())
         }
-        visit_dir(Path::new(""), &self.root, &mut sources)?;
-        Ok(sources)
+        if let Some((source, _)) = self.config.favicon_output() {
+            summary.assets += 1;
+            summary.bytes += self.root.join(source).metadata().map(|m| m.len()).unwrap_or(0)
+        }
+        Ok(summary)
     }
 
-    async fn build_and_deploy(&self) -> Result<String, FleenError> {
-        let output_dir = tempfile::tempdir().map_err(|_| TargetDir)?;
-        self.clone().build_site(output_dir.path())?; // Attempt to build the site somewhere
+    /// Every source file in the site (relative to the root) that isn't skipped
+    fn source_files(&self) -> Vec<PathBuf> {
+        fn visit_dir(dir: &Path, root: &Path, files: &mut Vec<PathBuf>) {
+            let Ok(entries) = root.join(dir).read_dir() else { return };
+            for entry in entries.flatten() {
+                let path = dir.join(entry.file_name());
+                if renderer::skipped_path(path.clone()) { continue }
+                if root.join(&path).is_dir() {
+                    visit_dir(&path, root, files)
+                } else {
+                    files.push(path)
+                }
+            }
+        }
+        let mut files = vec![];
+        visit_dir(Path::new(""), &self.root, &mut files);
+        files
+    }
+
+    /// Look for problems in the site that would break (or spoil) a build, without building it
+    fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        let error = |file: &Path, message: String| Diagnostic { severity: Severity::Error, file: file.to_path_buf(), message };
+        let warning = |file: &Path, message: String| Diagnostic { severity: Severity::Warning, file: file.to_path_buf(), message };
+        if let Some((source, _)) = self.config.favicon_output() && !self.root.join(&source).is_file() {
+            diagnostics.push(error(&source, "Configured favicon doesn't exist".to_string()))
+        }
+        if let Some(layout) = &self.config.default_layout && !self.root.join(layout).is_file() {
+            diagnostics.push(error(Path::new(layout), "Configured default layout doesn't exist".to_string()))
+        }
+
+        for source in self.source_files() {
+            let frontmatter = renderer::is_markdown_source(&source, &self.root).and_then(|markdown| {
+                if markdown { renderer::read_frontmatter(&source, &self.root) } else { Ok(None) }
+            });
+            match frontmatter {
+                Ok(Some(frontmatter)) => {
+                    // A missing default layout is reported once, above, rather than for every page
+                    if let Some(layout) = frontmatter.layout(&self.config) && layout != self.config.default_layout.as_deref().unwrap_or_default() && !self.root.join(layout).is_file() {
+                        diagnostics.push(error(&source, format!("Layout {} doesn't exist", layout)))
+                    }
+                    if let Some(page_type) = frontmatter.page_type() && frontmatter.layout(&self.config).is_none() && !frontmatter.no_layout() {
+                        diagnostics.push(warning(&source, format!("No layout for pages of type {}; add one to [layouts] in _config.toml", page_type)))
+                    }
+                    if frontmatter.draft() {
+                        diagnostics.push(warning(&source, "Page is a draft, so it won't be built".to_string()))
+                    } else if !frontmatter.published() && self.config.hidden_mode == HiddenMode::Omit {
+                        diagnostics.push(warning(&source, "Page isn't published, so it won't be built".to_string()))
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => diagnostics.push(error(&source, err.to_string()))
+            }
 
-        let deploy_script_path = self.root.join("_scripts/deploy.sh");
-        if !deploy_script_path.exists() {
-            Err(FleenError::ScriptMissing)
+            if let Some(message) = undersized_image(&self.root.join(&source), &self.config) {
+                diagnostics.push(warning(&source, message))
+            }
+
+            if renderer::is_markdown_source(&source, &self.root).unwrap_or(false) {
+                for url in renderer::find_links(&source, &self.root).unwrap_or_default() {
+                    if let Some(target) = resolve_link(&source, &url) && !output_exists(&self.root, &target) {
+                        diagnostics.push(warning(&source, format!("Broken link to {}", url)))
+                    }
+                }
+            }
+        }
+        diagnostics
+    }
+
+    /// Every page (relative to the root) with a link or image pointing at this file, which is
+    /// also relative to the root. Markdown pages are found by the html they become.
+    fn links_to(&self, target: &Path) -> Vec<PathBuf> {
+        let built = match target.file_name() {
+            Some(name) if name == "_index.md" => target.with_file_name("index.html"),
+            _ if target.extension().is_some_and(|e| e == "md") => target.with_extension("html"),
+            _ if target.extension().is_some_and(|e| e == "scss" || e == "sass") => target.with_extension("css"),
+            _ => target.to_path_buf()
+        };
+        let mut sources = self.source_files();
+        sources.sort();
+        sources.into_iter()
+            .filter(|source| renderer::is_markdown_source(source, &self.root).unwrap_or(false))
+            .filter(|source| {
+                renderer::find_links(source, &self.root).unwrap_or_default().iter()
+                    .filter_map(|url| resolve_link(source, url))
+                    .any(|linked| linked == built || linked == target)
+            })
+            .collect()
+    }
+
+    /// The command that will run the deploy script. In a dry run, that's `deploy-dryrun.sh` if
+    /// the site has one, and either way the script gets `FLEEN_DRY_RUN=1` so it can hold back.
+    /// It runs in the build output, and gets that and the site root as `$1` and `$2`.
+    fn deploy_command(&self, options: DeployOptions, output: &Path) -> Result<Command, FleenError> {
+        let dry_run_script = self.root.join("_scripts/deploy-dryrun.sh");
+        let script = if options.dry_run && dry_run_script.exists() {
+            dry_run_script
         } else {
-            let mut command = Command::new(deploy_script_path);
-            command.current_dir(output_dir.path()); // don't consume dir!
-            let output = command.output().map_err(|e| FleenError::DeployError(e.to_string()))?;
-            let status = command.status()?;
-
-            let output = String::from_utf8(output.stdout).unwrap_or("Error reading deploy script output".to_string());
-            if status.success() {
-                Ok(output)
+            self.root.join("_scripts/deploy.sh")
+        };
+        if !script.exists() { return Err(FleenError::ScriptMissing) }
+
+        // Scripts are told where everything is, so one script can deploy any site
+        let root = self.root.canonicalize().unwrap_or(self.root.clone());
+        let mut command = Command::new(script);
+        command.current_dir(output)
+            .args([output, &root])
+            .env("FLEEN_OUTPUT_DIR", output)
+            .env("FLEEN_SITE_ROOT", &root);
+        if options.dry_run {
+            command.env("FLEEN_DRY_RUN", "1");
+        }
+        Ok(command)
+    }
+
+    /// Build the site somewhere temporary and deploy it. The deploy's output is sent to `lines`
+    /// a line at a time as it happens, as well as being returned all together at the end. A
+    /// deploy script is stopped if it runs past the site's `deploy_timeout`, or once `cancel`
+    /// is set.
+    async fn build_and_deploy(&self, options: DeployOptions, lines: Sender<String>, cancel: Arc<AtomicBool>) -> Result<String, FleenError> {
+        let output_dir = tempfile::tempdir().map_err(|_| TargetDir)?;
+        self.clone().build_to_dir(output_dir.path(), options.strict)?; // Attempt to build the site somewhere
+        utils::allow_spawn("run the deploy")?;
+
+        if options.target == DeployTarget::GitBranch {
+            let git_deploy = self.config.git_deploy.as_ref().ok_or(FleenError::NoGitDeploy)?;
+            let output = if options.dry_run {
+                format!("Would commit the build to {} on {}", git_deploy.branch, git_deploy.repo)
+            } else {
+                git_deploy.deploy(output_dir.path())?
+            };
+            for line in output.lines() { let _ = lines.send(line.to_string()); }
+            return Ok(output)
+        }
+
+        let mut command = self.deploy_command(options, output_dir.path())?; // don't consume dir!
+        // The script leads its own process group, so stopping it can stop whatever it started too
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()
+            .map_err(|e| FleenError::DeployError(e.to_string()))?;
+        // Read both pipes at once, or a script that fills one while we wait on the other would hang
+        let stdout = child.stdout.take().map(|pipe| {
+            let lines = lines.clone();
+            std::thread::spawn(move || forward_lines(pipe, &lines))
+        });
+        let stderr = child.stderr.take().map(|pipe| {
+            let lines = lines.clone();
+            std::thread::spawn(move || forward_lines(pipe, &lines))
+        });
+
+        // Wait for it to finish, unless it takes too long or we're told to stop. Returning drops
+        // the output dir, so it's cleaned up either way.
+        let started = std::time::Instant::now();
+        let timeout = Some(std::time::Duration::from_secs(self.config.deploy_timeout)).filter(|t| !t.is_zero());
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(|e| FleenError::DeployError(e.to_string()))? { break status }
+            let stop = if cancel.load(std::sync::atomic::Ordering::Relaxed) {
+                Some(FleenError::DeployCancelled)
+            } else if timeout.is_some_and(|timeout| started.elapsed() > timeout) {
+                Some(FleenError::DeployTimedOut)
             } else {
-                Err(FleenError::DeployError(output))
+                None
+            };
+            if let Some(err) = stop {
+                kill_deploy(&mut child);
+                return Err(err)
             }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        };
+        let stdout = stdout.and_then(|reader| reader.join().ok()).unwrap_or_default();
+        let stderr = stderr.and_then(|reader| reader.join().ok()).unwrap_or_default();
+
+        let text = deploy_output(&stdout, &stderr);
+        if status.success() {
+            Ok(text)
+        } else {
+            Err(FleenError::DeployError(text))
         }
     }
 
-    /// Return whether the images/ directory actually exists for this site
+    /// Build the site in memory and compare each file with the copy at `base_url`
+    async fn compare_with_live(&self) -> Result<Vec<LiveDiff>, FleenError> {
+        let base_url = self.config.base_url.clone().ok_or(FleenError::NoBaseUrl)?;
+        let mut sink = InMemorySink::default();
+        self.build_site(&mut sink, false)?;
+        live_diff::compare_with_live(&sink.files, &base_url).await
+    }
+
+    /// Make a markdown page in `dir` (relative to the root) for every item in a feed, which can
+    /// be a url or a file. Returns the updated site and how many pages were made.
+    async fn import_feed(&self, source: &str, dir: &str) -> Result<(Site, usize), FleenError> {
+        let bytes = import::fetch_feed(source).await?;
+        let posts = import::parse_feed(&bytes)?;
+        let written = import::write_posts(&posts, &self.root.join(dir))?;
+        Ok((Site::open(&self.root)?, written.len()))
+    }
+
+    /// Render one page (relative to the root) into a temporary html file, to look at without the
+    /// server. The file is deleted when the returned path is dropped.
+    fn render_to_temp(&self, source: &Path) -> Result<tempfile::TempPath, FleenError> {
+        let contents = match renderer::file_render(source.to_path_buf(), &self.root, &self.config)? {
+            RenderOutput::Rendered(_, contents) | RenderOutput::Hidden(_, contents) | RenderOutput::Draft(_, contents) => contents,
+            _ => return Err(FleenError::NotAPage(source.to_path_buf()))
+        };
+        let mut file = tempfile::Builder::new().prefix("fleen-preview-").suffix(".html").tempfile()?;
+        file.write_all(contents.as_bytes())?;
+        Ok(file.into_temp_path())
+    }
+
+    /// Return whether the image dir actually exists for this site
     fn image_dir_exists(&self) -> bool {
-        self.root.join("images").is_dir()
+        self.config.image_path(&self.root).is_dir()
+    }
+
+    /// Save the clipboard's image into the image dir, returning the updated site and the image's path
+    fn paste_image(&self) -> Result<(Site, PathBuf), FleenError> {
+        self.paste_image_from(&SystemClipboard::open()?)
+    }
+
+    /// Save a clipboard's image into the image dir, and put a link to it on the clipboard in its place
+    fn paste_image_from(&self, clipboard: &dyn ImageClipboard) -> Result<(Site, PathBuf), FleenError> {
+        if !self.image_dir_exists() { return Err(FleenError::NoImageDir) }
+        let image = clipboard.image()?.ok_or(FleenError::NoClipboardImage)?;
+        let (site, path) = self.save_image(&convert_pasted_image(image, &self.config)?)?;
+        let _ = clipboard.set_text(image_link(&path, &self.root));
+        Ok((site, path))
     }
 
-    fn paste_image(&self) -> Result<Site, FleenError> {
-        let c = clipboard_rs::ClipboardContext::new().map_err(|_| FleenError::NoClipboardImage)?;
+    /// Copy an image file (like one dropped on the window) into the image dir, and put a link to it on
+    /// the clipboard if there is one. Like a pasted image, it keeps its format.
+    fn add_image_file(&self, file: &Path, clipboard: Option<&dyn ImageClipboard>) -> Result<(Site, PathBuf), FleenError> {
         if !self.image_dir_exists() { return Err(FleenError::NoImageDir) }
-        let img = c.get_image().map_err(|_| FleenError::NoClipboardImage)?;
-        let target_path = utils::unique_image_name(&self.root.join("images"))?;
-        img.save_to_path(target_path.to_str().unwrap()).map_err(|e| FleenError::FileCreate(target_path.clone(), e.to_string()))?;
-        let new_tree = read_tree(&self.root)?;
-        let uri = format!("/images/{}", target_path.file_name().unwrap().to_str().unwrap());
-        let _ = c.set_text(format!("![]({})", uri));
-        Ok(Site { root: self.root.to_path_buf(), tree: new_tree })
+        let bytes = fs::read(file).map_err(|e| FleenError::FileIo(file.to_string_lossy().to_string(), e.to_string()))?;
+        let extension = file.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+        let (site, path) = self.save_image_as(&bytes, extension.as_deref())?;
+        if let Some(clipboard) = clipboard { let _ = clipboard.set_text(image_link(&path, &self.root)); }
+        Ok((site, path))
+    }
+
+    /// Copy a page from elsewhere into the site, in `parent` (or the dir it's in, if it's a
+    /// file), keeping its name. Returns the updated site and where the page went.
+    fn import_page(&self, file: &Path, parent: Option<&String>) -> Result<(Site, PathBuf), FleenError> {
+        let mut target = parent.map(PathBuf::from).unwrap_or(self.root.clone());
+        while target.is_file() { target.pop(); }
+        target.push(file.file_name().ok_or(FleenError::NotAPage(file.to_path_buf()))?);
+        fs::File::create_new(&target).and_then(|_| fs::copy(file, &target)).map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => FleenError::FileExists(target.clone()),
+            _ => FleenError::FileCreate(target.clone(), err.to_string())
+        })?;
+        Ok((Site::open(&self.root)?, target))
+    }
+
+    /// Save image data into the image dir under a new name (see `SiteConfig::image_name`), with
+    /// an extension to match its format (png if we can't tell), unless we already have that exact
+    /// image, in which case we use the one we've got. Returns the updated site and the image's path.
+    fn save_image(&self, bytes: &[u8]) -> Result<(Site, PathBuf), FleenError> {
+        self.save_image_as(bytes, None)
+    }
+
+    /// `save_image`, but with the extension to use if we already know it
+    fn save_image_as(&self, bytes: &[u8], extension: Option<&str>) -> Result<(Site, PathBuf), FleenError> {
+        if let Some(existing) = self.image_index().find(bytes) {
+            return Ok((Site::open(&self.root)?, existing.clone()))
+        }
+        let extension = extension
+            .or_else(|| image::guess_format(bytes).ok().and_then(|format| format.extensions_str().first().copied()))
+            .unwrap_or("png");
+        let target_path = utils::unique_image_name(&self.config.image_path(&self.root), &self.config.image_name, extension)?;
+        fs::write(&target_path, bytes).map_err(|e| FleenError::FileCreate(target_path.clone(), e.to_string()))?;
+        Ok((Site::open(&self.root)?, target_path))
     }
 
     fn create_page(&self, file_type: FileType, name: &str, parent: Option<&String>) -> Result<Site, FleenError> {
@@ -223,11 +819,35 @@ pub trait SiteActions: Deref<Target=Site> + Clone {
             FileType::Dir => fs::create_dir(target.clone())
         }.map_err(|err| FleenError::FileCreate(target.clone(), err.to_string()))?;
 
-        let new_tree = read_tree(&self.root)?;
+        let new_site = Site::open(&self.root)?;
         if file_type == FileType::File {
-            utils::open_filename(target.to_string_lossy().as_ref())?
+            match utils::open_filename(target.to_string_lossy().as_ref()) {
+                // The page still got made, so that's fine
+                Ok(_) | Err(FleenError::SafeMode(_)) => {}
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(new_site)
+    }
+
+    /// Make a new layout in `_layouts/`, starting from the same template new sites get. Returns
+    /// the updated site and the new layout's path.
+    fn create_layout(&self, name: &str) -> Result<(Site, PathBuf), FleenError> {
+        let name = name.trim();
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(FleenError::InvalidName(name.to_string()))
+        }
+        let name = if name.ends_with(".html") { name.to_string() } else { format!("{}.html", name) };
+
+        let dir = self.root.join("_layouts");
+        let target = dir.join(name);
+        if target.exists() {
+            return Err(FleenError::FileExists(target))
         }
-        Ok(Site { root: self.root.to_path_buf(), tree: new_tree })
+        fs::create_dir_all(&dir).map_err(|err| FleenError::FileCreate(dir.clone(), err.to_string()))?;
+        fs::write(&target, include_str!("../templates/default_layout.html"))
+            .map_err(|err| FleenError::FileCreate(target.clone(), err.to_string()))?;
+        Ok((Site::open(&self.root)?, target))
     }
 
     fn rename_page(&self, target: &String, new_name: &str) -> Result<Site, FleenError> {
@@ -235,18 +855,406 @@ pub trait SiteActions: Deref<Target=Site> + Clone {
         let mut new_path = path.clone();
         new_path.set_file_name(new_name);
         fs::rename(path, new_path).map_err(|err| FleenError::FileIo(target.clone(), err.to_string()))?;
-        Ok(Site { root: self.root.to_path_buf(), tree: read_tree(&self.root)? })
+        Site::open(&self.root)
     }
 
-    fn delete_page(&self, path: &String) -> Result<Site, FleenError> {
-        let target = PathBuf::from(path);
-        if target.is_dir() {
-            fs::remove_dir_all(target)
+    /// Copy a file, or a dir and everything in it, to a new name next to it. Returns the updated
+    /// site and the copy's path.
+    fn duplicate_page(&self, path: &String) -> Result<(Site, PathBuf), FleenError> {
+        let source = PathBuf::from(path);
+        let target = utils::copy_name(&source);
+        if source.is_dir() {
+            fs::create_dir(&target).and_then(|_| utils::copy_dir(&source, &target))
         } else {
-            fs::remove_file(target)
-        }.map_err(|err| FleenError::FileIo(path.clone(), err.to_string()))?;
-        Ok(Site { root: self.root.clone(), tree: read_tree(&self.root)? })
+            // Only copy into a file we made, in case something else took the name in the meantime
+            fs::File::create_new(&target).and_then(|_| fs::copy(&source, &target)).map(|_| ())
+        }.map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => FleenError::FileExists(target.clone()),
+            _ => FleenError::FileCreate(target.clone(), err.to_string())
+        })?;
+        Ok((Site::open(&self.root)?, target))
+    }
+
+    /// Rename several files at once. Every target is checked for collisions before anything
+    /// is renamed, so a bad pattern won't leave the site half-renamed.
+    fn batch_rename(&self, targets: &[String], pattern: &RenamePattern) -> Result<Site, FleenError> {
+        let paths: Vec<PathBuf> = targets.iter().map(PathBuf::from).collect();
+        let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+        let plan = plan_batch_rename(&paths, pattern, &today);
+        if let Some(bad) = plan.iter().find(|p| p.collision) {
+            return Err(FleenError::RenameCollision(bad.to.clone()))
+        }
+
+        for PlannedRename { from, to, .. } in plan.into_iter().filter(|p| p.from != p.to) {
+            fs::rename(&from, &to).map_err(|err| FleenError::FileIo(from.to_string_lossy().to_string(), err.to_string()))?;
+        }
+        Site::open(&self.root)
+    }
+
+    /// Find image links that point at images which have since moved, and where each one
+    /// moved to. An image is found again by its file name, so if no image (or more than one)
+    /// has that name, the link goes in the report as unresolved instead.
+    fn plan_image_link_fixes(&self) -> ImageLinkReport {
+        let files = self.source_files();
+        let mut report = ImageLinkReport::default();
+        for source in files.iter() {
+            if !renderer::is_markdown_source(source, &self.root).unwrap_or(false) { continue }
+            for url in renderer::find_image_links(source, &self.root).unwrap_or_default() {
+                let Some(target) = resolve_link(source, &url) else { continue };
+                if output_exists(&self.root, &target) || report.fixes.iter().any(|f| f.file == *source && f.from == url) { continue }
+                let candidates: Vec<&PathBuf> = files.iter().filter(|f| target.file_name().is_some_and(|name| f.file_name() == Some(name))).collect();
+                match candidates.as_slice() {
+                    [found] => report.fixes.push(LinkFix {
+                        file: source.clone(),
+                        from: url.clone(),
+                        to: format!("/{}", found.to_string_lossy().replace('\\', "/"))
+                    }),
+                    [] => report.unresolved.push(Diagnostic {
+                        severity: Severity::Warning, file: source.clone(), message: format!("No image named like {}", url)
+                    }),
+                    _ => report.unresolved.push(Diagnostic {
+                        severity: Severity::Warning, file: source.clone(), message: format!("Several images could be {}", url)
+                    })
+                }
+            }
+        }
+        report
+    }
+
+    /// Rewrite image links as planned by `plan_image_link_fixes`
+    fn apply_link_fixes(&self, fixes: &[LinkFix]) -> Result<Site, FleenError> {
+        let mut files: Vec<&PathBuf> = fixes.iter().map(|f| &f.file).collect();
+        files.dedup();
+        for file in files {
+            let path = self.root.join(file);
+            let contents = fs::read_to_string(&path).map_err(|e| FleenError::FileIo(file.to_string_lossy().to_string(), e.to_string()))?;
+            let rewritten = renderer::rewrite_image_links(&contents, file, |url| {
+                fixes.iter().find(|f| f.file == *file && f.from == url).map(|f| f.to.clone())
+            })?;
+            fs::write(&path, rewritten).map_err(|e| FleenError::FileIo(file.to_string_lossy().to_string(), e.to_string()))?;
+        }
+        Site::open(&self.root)
+    }
+
+    /// Publish or unpublish a page (relative to the root), touching nothing else in its frontmatter
+    fn set_published(&self, source: &Path, published: bool) -> Result<Site, FleenError> {
+        self.set_frontmatter_field(&[source.to_path_buf()], "published", Some(published.into()))
+    }
+
+    /// Set one frontmatter key on several pages (relative to the root) at once, or remove it
+    /// with None. Every file is edited before any is written, so a page with broken frontmatter
+    /// stops the whole thing rather than leaving it half done.
+    fn set_frontmatter_field(&self, sources: &[PathBuf], key: &str, value: Option<toml_edit::Value>) -> Result<Site, FleenError> {
+        let mut edits = vec![];
+        for source in sources {
+            let path = self.root.join(source);
+            let contents = fs::read_to_string(&path).map_err(|e| FleenError::FileIo(path.to_string_lossy().to_string(), e.to_string()))?;
+            if renderer::has_yaml_frontmatter(&contents) {
+                return Err(RenderError::YamlFrontmatterEdit(source.clone()).into())
+            }
+            let edited = renderer::edit_frontmatter(&contents, key, value.clone())
+                .map_err(|e| RenderError::FrontmatterEdit(e, source.clone()))?;
+            edits.push((path, edited));
+        }
+        for (path, edited) in edits {
+            fs::write(&path, edited).map_err(|e| FleenError::FileIo(path.to_string_lossy().to_string(), e.to_string()))?;
+        }
+        Site::open(&self.root)
+    }
+
+    /// Move a file or folder to the trash, or delete it outright if `permanently`, or if there's no trash
+    fn delete_page(&self, path: &String, permanently: bool) -> Result<Site, FleenError> {
+        remove_file_or_dir(path, permanently)?;
+        Site::open(&self.root)
+    }
+}
+
+/// Whether deleting things puts them in the trash, where they can be got back from
+pub const CAN_TRASH: bool = cfg!(can_trash);
+
+fn remove_file_or_dir(path: &String, permanently: bool) -> Result<(), FleenError> {
+    let target = Path::new(path);
+    if CAN_TRASH && !permanently {
+        #[cfg(can_trash)]
+        return trash::delete(target).map_err(|err| FleenError::TrashFailed(path.clone(), err.to_string()));
+    }
+    if target.is_dir() { fs::remove_dir_all(target) } else { fs::remove_file(target) }.map_err(|err| FleenError::FileIo(path.clone(), err.to_string()))
+}
+
+/// Stop a deploy script. On unix that's its whole process group, so an rsync or scp it started
+/// doesn't carry on without it; elsewhere we can only stop the script itself.
+fn kill_deploy(child: &mut std::process::Child) {
+    #[cfg(unix)]
+    if let Ok(group) = i32::try_from(child.id()) {
+        // Safe: kill just sends a signal, and the group is ours, since the script leads it
+        unsafe { libc::kill(-group, libc::SIGKILL); }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Send each line from a pipe on as it comes, and return everything that came through it
+fn forward_lines(pipe: impl io::Read, lines: &Sender<String>) -> Vec<u8> {
+    let mut all = vec![];
+    let mut reader = io::BufReader::new(pipe);
+    let mut line = vec![];
+    while matches!(io::BufRead::read_until(&mut reader, b'\n', &mut line), Ok(n) if n > 0) {
+        let _ = lines.send(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string());
+        all.append(&mut line);
+    }
+    all
+}
+
+/// What a deploy script said, for showing to the user. Anything on stderr goes after stdout,
+/// since when a script fails that's usually where the reason is.
+fn deploy_output(stdout: &[u8], stderr: &[u8]) -> String {
+    let (stdout, stderr) = (String::from_utf8_lossy(stdout), String::from_utf8_lossy(stderr));
+    match (stdout.trim().is_empty(), stderr.trim().is_empty()) {
+        (_, true) => stdout.to_string(),
+        (true, false) => stderr.to_string(),
+        (false, false) => format!("{}\n{}", stdout.trim_end(), stderr)
+    }
+}
+
+/// Every output the site's sources make, with the given config
+fn compile_site(root: &Path, config: &SiteConfig) -> Result<Vec<RenderOutput>, FleenError> {
+    Ok(compile_sources(root, config)?.into_iter().map(|(_, output)| output).collect())
+}
+
+/// Every source file with what it renders to
+fn compile_sources(root: &Path, config: &SiteConfig) -> Result<Vec<(PathBuf, RenderOutput)>, FleenError> {
+    // Rendering is the slow part, so find everything first and then render it all at once.
+    // The outputs come back in the same order, so each dir is still ahead of its contents,
+    // and the first error is the one from the earliest file.
+    let rendered: Vec<Result<(PathBuf, RenderOutput), RenderError>> = source_paths(root, config)?.into_par_iter()
+        .map(|source| Ok((source.clone(), renderer::file_render(source, root, config)?)))
+        .collect();
+    Ok(rendered.into_iter().collect::<Result<Vec<_>, _>>()?)
+}
+
+/// What a build would produce: rendered pages, files copied as-is, and how big it all is
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BuildSummary {
+    pub pages: usize,
+    pub assets: usize,
+    pub bytes: u64
+}
+
+impl Display for BuildSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} pages, {} assets, {}", self.pages, self.assets, utils::format_size(self.bytes))
+    }
+}
+
+/// How to go about a build and deploy
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct DeployOptions {
+    /// Fail the build on warnings, not just errors
+    pub strict: bool,
+    /// Run the deploy script in a mode where it shouldn't actually deploy anything
+    pub dry_run: bool,
+    pub target: DeployTarget
+}
+
+/// The ways we know how to deploy a site
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum DeployTarget {
+    /// Run `_scripts/deploy.sh` in the build dir
+    #[default]
+    Script,
+    /// Commit the build to the branch set up in `[git_deploy]`
+    GitBranch
+}
+
+/// Shrink and re-encode a pasted image, as the site's settings ask. With the default settings
+/// it's left exactly as it was.
+pub fn convert_pasted_image(bytes: Vec<u8>, config: &SiteConfig) -> Result<Vec<u8>, FleenError> {
+    if config.max_image_width.is_none() && config.max_image_height.is_none() && config.paste_format == PasteFormat::Keep {
+        return Ok(bytes)
+    }
+    let original = image::guess_format(&bytes).map_err(|e| FleenError::ImageConvert(e.to_string()))?;
+    let image = image::load_from_memory_with_format(&bytes, original).map_err(|e| FleenError::ImageConvert(e.to_string()))?;
+    let (max_width, max_height) = (config.max_image_width.unwrap_or(u32::MAX), config.max_image_height.unwrap_or(u32::MAX));
+    let too_big = image.width() > max_width || image.height() > max_height;
+    let format = match config.paste_format {
+        PasteFormat::Keep => original,
+        PasteFormat::Png => image::ImageFormat::Png,
+        PasteFormat::Jpeg => image::ImageFormat::Jpeg,
+        PasteFormat::Webp => image::ImageFormat::WebP
+    };
+    // Already the right size and format, so there's nothing to gain by re-encoding it
+    if !too_big && format == original { return Ok(bytes) }
+
+    let image = if too_big { image.resize(max_width, max_height, image::imageops::FilterType::Lanczos3) } else { image };
+    // Jpegs can't be transparent
+    let image = if format == image::ImageFormat::Jpeg { image::DynamicImage::ImageRgb8(image.to_rgb8()) } else { image };
+    let mut converted = io::Cursor::new(vec![]);
+    image.write_to(&mut converted, format).map_err(|e| FleenError::ImageConvert(e.to_string()))?;
+    Ok(converted.into_inner())
+}
+
+/// If this is an image smaller than the configured minimum size, say so. Only the image's
+/// header is read, so this is cheap enough to run over every file.
+pub fn undersized_image(path: &Path, config: &SiteConfig) -> Option<String> {
+    if config.min_image_width.is_none() && config.min_image_height.is_none() { return None }
+    let (width, height) = image::ImageReader::open(path).ok()?.with_guessed_format().ok()?.into_dimensions().ok()?;
+    let (min_width, min_height) = (config.min_image_width.unwrap_or(0), config.min_image_height.unwrap_or(0));
+    if width < min_width || height < min_height {
+        Some(format!("Image is {}x{}, smaller than the minimum {}x{}", width, height, min_width, min_height))
+    } else {
+        None
+    }
+}
+
+/// Turn a url in a page into the path (relative to the site root) it points at in the built site.
+/// External urls, other schemes like `mailto:`, and pure fragments like `#top`, give None:
+/// there's nothing for us to check.
+pub fn resolve_link(from: &Path, url: &str) -> Option<PathBuf> {
+    let has_scheme = url.split_once(':').is_some_and(|(scheme, _)|
+        !scheme.is_empty() && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)));
+    if has_scheme || url.starts_with("//") || url.starts_with('#') {
+        return None
+    }
+    let url = url.split(['#', '?']).next().unwrap_or_default();
+    let mut resolved = PathBuf::new();
+    let base = if url.starts_with('/') { Path::new("") } else { from.parent().unwrap_or(Path::new("")) };
+    for component in base.join(url.trim_start_matches('/')).components() {
+        match component {
+            std::path::Component::Normal(part) => resolved.push(part),
+            // Pop a dir off; if there's nothing to pop, this leaves the site, which can't go anywhere good
+            std::path::Component::ParentDir if !resolved.pop() => return Some(PathBuf::from("..")),
+            _ => {}
+        }
+    }
+    if url.ends_with('/') || url.is_empty() { resolved.push("index.html") }
+    Some(resolved)
+}
+
+/// Whether the built site would contain something at this (relative) path
+pub fn output_exists(root: &Path, target: &Path) -> bool {
+    if renderer::skipped_path(target.to_path_buf()) { return false }
+    let source = root.join(target);
+    if source.is_dir() {
+        output_exists(root, &target.join("index.html"))
+    } else if source.is_file() {
+        // Markdown files only exist in the output as html, and stylesheets as css
+        target.extension().is_none_or(|e| e != "md" && e != "scss" && e != "sass")
+    } else {
+        // Made from something else, found the same way the renderer finds it: a page's markdown,
+        // a section's `_index.md`, or a stylesheet
+        matches!(renderer::resolve_source(target, root), Ok(ResolvedSource::MarkdownFallback(_) | ResolvedSource::StylesheetFallback(_)))
+    }
+}
+
+/// Check every link and image in the built html pages against what the build will contain: the
+/// site's own files, plus the ones (like `feed.xml`) made alongside them. External links aren't
+/// checked. A broken link in a layout is in every page that uses it, but it's reported once,
+/// against the layout, since that's where it needs fixing.
+pub fn check_links(compiled: &[(PathBuf, RenderOutput)], root: &Path, config: &SiteConfig, made: &[PathBuf]) -> Vec<BrokenLink> {
+    let mut layout_links: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    let mut broken = vec![];
+    for (source, output) in compiled {
+        // Plain html pages are copied as they are, so their links are in the source
+        let (path, html) = match output {
+            RenderOutput::Rendered(path, html) if path.extension().is_some_and(|e| e == "html") => (path, Cow::Borrowed(html)),
+            RenderOutput::RawFile(path) if path.extension().is_some_and(|e| e == "html") =>
+                (path, Cow::Owned(fs::read_to_string(root.join(path)).unwrap_or_default())),
+            _ => continue
+        };
+        let layout = match output {
+            RenderOutput::Rendered(..) => page_layout(source, root, config),
+            _ => None
+        };
+        for url in renderer::html_links(&html) {
+            if let Some(target) = resolve_link(path, &url) && !made.contains(&target) && !output_exists(root, &target) {
+                let in_layout = layout.as_ref().filter(|layout| layout_links.entry(layout.to_path_buf())
+                    .or_insert_with(|| renderer::html_links(&fs::read_to_string(root.join(layout)).unwrap_or_default()))
+                    .contains(&url));
+                let link = BrokenLink { file: in_layout.unwrap_or(source).clone(), url };
+                if !broken.contains(&link) { broken.push(link) }
+            }
+        }
+    }
+    broken
+}
+
+/// The layout a page is rendered in, if it has one
+fn page_layout(source: &Path, root: &Path, config: &SiteConfig) -> Option<PathBuf> {
+    let frontmatter = renderer::read_frontmatter(source, root).ok()?.unwrap_or_default();
+    frontmatter.layout(config).map(PathBuf::from)
+}
+
+/// A broken image link, and what it should say instead
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkFix {
+    /// The page the link is in, relative to the root
+    pub file: PathBuf,
+    pub from: String,
+    pub to: String
+}
+
+/// What `plan_image_link_fixes` found: links it can fix, and links it can't
+#[derive(Clone, Debug, Default)]
+pub struct ImageLinkReport {
+    pub fixes: Vec<LinkFix>,
+    pub unresolved: Vec<Diagnostic>
+}
+
+/// How a batch rename turns each old file name into a new one
+#[derive(Clone, Debug)]
+pub enum RenamePattern {
+    /// A template like `{date}-{name}`. Understands `{name}` (the whole file name), `{stem}`,
+    /// `{ext}`, `{date}` (today, as YYYY-MM-DD) and `{n}` (1-based position in the selection)
+    Template(String),
+    /// Replace every occurrence of the first string with the second
+    Replace(String, String)
+}
+
+/// One step of a batch rename, for previewing before we commit to it
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedRename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// The new name is empty, shared with another file in the batch, or already exists on disk
+    pub collision: bool
+}
+
+/// Figure out the new file name for a single file in a batch
+pub fn expand_rename_pattern(pattern: &RenamePattern, file_name: &str, date: &str, n: usize) -> String {
+    match pattern {
+        RenamePattern::Template(template) => {
+            let path = Path::new(file_name);
+            let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+            let ext = path.extension().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+            template.replace("{name}", file_name)
+                .replace("{stem}", &stem)
+                .replace("{ext}", &ext)
+                .replace("{date}", date)
+                .replace("{n}", &n.to_string())
+        }
+        RenamePattern::Replace(find, replace) if !find.is_empty() => file_name.replace(find, replace),
+        RenamePattern::Replace(_, _) => file_name.to_string()
+    }
+}
+
+/// Work out where every file in a batch rename would end up, flagging collisions
+pub fn plan_batch_rename(paths: &[PathBuf], pattern: &RenamePattern, date: &str) -> Vec<PlannedRename> {
+    let mut plan: Vec<PlannedRename> = paths.iter().enumerate().map(|(i, from)| {
+        let name = from.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let new_name = expand_rename_pattern(pattern, &name, date, i + 1);
+        let invalid = new_name.is_empty() || new_name.contains(['/', '\\']);
+        let to = from.with_file_name(new_name);
+        // Renaming onto a file that exists is only okay if it's the same file
+        let collision = invalid || (to != *from && to.exists());
+        PlannedRename { from: from.clone(), to, collision }
+    }).collect();
+
+    for i in 0..plan.len() {
+        if plan.iter().enumerate().any(|(j, other)| i != j && other.to == plan[i].to) {
+            plan[i].collision = true
+        }
     }
+    plan
 }
 
 impl SiteActions for &Site {}
@@ -255,6 +1263,7 @@ impl SiteActions for Arc<Site> {}
 ////////////////////////////////////////////
 
 #[cfg(test)]
+#[allow(clippy::ptr_arg)] // The helpers take what the renderer hands back
 mod tests {
     use super::*;
 
@@ -286,6 +1295,7 @@ mod tests {
         // Rendered files that are hidden for whatever reason
         assert!(find_rendered_index(&actions,"hidden.html").is_none());
         assert!(find_rendered_index(&actions,"_skipped.html").is_none());
+        assert!(find_rendered_index(&actions,"draft.html").is_none());
 
         // The original md files are not reproduced:
         assert!(find_rendered_index(&actions,"index.md").is_none());
@@ -306,4 +1316,861 @@ mod tests {
         // But not hidden ones:
         assert!(find_raw_index(&actions, "_layouts/post.html").is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_rename_pattern() {
+        let date_prefix = RenamePattern::Template("{date}-{name}".to_string());
+        assert_eq!(expand_rename_pattern(&date_prefix, "post.md", "2025-06-01", 1), "2025-06-01-post.md");
+
+        let numbered = RenamePattern::Template("{stem}-{n}.{ext}".to_string());
+        assert_eq!(expand_rename_pattern(&numbered, "photo.png", "2025-06-01", 3), "photo-3.png");
+
+        let replace = RenamePattern::Replace("draft".to_string(), "final".to_string());
+        assert_eq!(expand_rename_pattern(&replace, "draft-post.md", "2025-06-01", 1), "final-post.md");
+    }
+
+    #[test]
+    fn test_delete_permanently() {
+        let (dir, site) = temp_site(&[("posts/a.md", "A"), ("b.md", "B")]);
+        let posts = dir.path().join("posts").to_string_lossy().to_string();
+        let site = (&site).delete_page(&posts, true).unwrap();
+        assert!(!dir.path().join("posts").exists());
+        (&site).delete_page(&dir.path().join("b.md").to_string_lossy().to_string(), true).unwrap();
+        assert!(!dir.path().join("b.md").exists());
+    }
+
+    #[test]
+    fn test_batch_rename_collisions() {
+        let names: Vec<PathBuf> = ["a.md", "b.md", "c.txt"].iter().map(|n| PathBuf::from("./testdata/dir").join(n)).collect();
+
+        // Every file gets a distinct name, so nothing collides
+        let plan = plan_batch_rename(&names, &RenamePattern::Template("{date}-{name}".to_string()), "2025-06-01");
+        assert!(plan.iter().all(|p| !p.collision));
+        assert_eq!(plan[2].to, PathBuf::from("./testdata/dir/2025-06-01-c.txt"));
+
+        // Two of the files would both become post.md
+        let plan = plan_batch_rename(&names, &RenamePattern::Template("post.{ext}".to_string()), "2025-06-01");
+        assert!(plan[0].collision && plan[1].collision);
+        assert!(!plan[2].collision);
+
+        // Renaming onto a file that's already there
+        let plan = plan_batch_rename(&names[0..1], &RenamePattern::Replace("a".to_string(), "subdir".to_string()), "2025-06-01");
+        assert!(plan[0].collision);
+    }
+
+    #[test]
+    fn test_preserve_on_rebuild() {
+        let mut app = Site::open(&PathBuf::from("./testdata")).unwrap();
+        app.config.preserve = vec!["CNAME".to_string()];
+        let target = tempfile::tempdir().unwrap();
+        fs::write(target.path().join("CNAME"), "example.com").unwrap();
+        fs::write(target.path().join("stale.html"), "old build").unwrap();
+
+        (&app).build_to_dir(target.path(), false).unwrap();
+        assert!(target.path().join("CNAME").exists()); // Preserved
+        assert!(!target.path().join("stale.html").exists()); // Cleared
+        assert!(target.path().join("index.html").exists()); // And the site was still built
+    }
+
+    #[test]
+    fn test_render_to_temp() {
+        let app = Site::open(&PathBuf::from("./testdata")).unwrap();
+        let path = (&app).render_to_temp(Path::new("index.md")).unwrap();
+        assert_eq!(path.extension().unwrap(), "html");
+        assert!(fs::read_to_string(&path).unwrap().contains("Pest Toast"));
+
+        // Gone once we're done with it
+        let file = path.to_path_buf();
+        drop(path);
+        assert!(!file.exists());
+
+        assert!(matches!((&app).render_to_temp(Path::new("raw.txt")), Err(FleenError::NotAPage(_))));
+    }
+
+    #[test]
+    fn test_links_to() {
+        let (_dir, site) = temp_site(&[
+            ("index.md", "See [the post](posts/hello.html)"),
+            ("posts/other.md", "Also [this one](hello.html#top), and ![a pic](/images/cat.png)"),
+            ("posts/hello.md", "Hello, [home](/)"),
+            ("about.md", "Nothing to see here")
+        ]);
+        assert_eq!((&site).links_to(Path::new("posts/hello.md")), vec![PathBuf::from("index.md"), PathBuf::from("posts/other.md")]);
+        assert_eq!((&site).links_to(Path::new("images/cat.png")), vec![PathBuf::from("posts/other.md")]);
+        assert_eq!((&site).links_to(Path::new("index.md")), vec![PathBuf::from("posts/hello.md")]);
+        assert!((&site).links_to(Path::new("about.md")).is_empty());
+    }
+
+    #[test]
+    fn test_set_frontmatter_field() {
+        let (dir, site) = temp_site(&[
+            ("one.md", "+++\ntitle = \"One\" # the first\ntags = [\"a\"]\n+++\nOne"),
+            ("two.md", "+++\ntitle = \"Two\"\nauthor = \"Someone\"\n+++\nTwo"),
+            ("three.md", "No frontmatter yet")
+        ]);
+        let sources = ["one.md", "two.md", "three.md"].map(PathBuf::from);
+        let site = (&site).set_frontmatter_field(&sources, "author", Some("Me".into())).unwrap();
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("one.md"), "+++\ntitle = \"One\" # the first\ntags = [\"a\"]\nauthor = \"Me\"\n+++\nOne");
+        assert_eq!(read("two.md"), "+++\ntitle = \"Two\"\nauthor = \"Me\"\n+++\nTwo");
+        assert_eq!(read("three.md"), "+++\nauthor = \"Me\"\n+++\nNo frontmatter yet");
+
+        // And taking it back out leaves the rest alone
+        (&site).set_frontmatter_field(&sources[0..2], "author", None).unwrap();
+        assert_eq!(read("one.md"), "+++\ntitle = \"One\" # the first\ntags = [\"a\"]\n+++\nOne");
+        assert_eq!(read("two.md"), "+++\ntitle = \"Two\"\n+++\nTwo");
+
+        // YAML frontmatter is read-only; we don't bolt a toml block on top of it
+        fs::write(dir.path().join("yaml.md"), "---\ntitle: Yaml\n---\nHi").unwrap();
+        let result = (&site).set_frontmatter_field(&[PathBuf::from("yaml.md")], "author", Some("Me".into()));
+        assert!(matches!(result, Err(FleenError::RenderError(RenderError::YamlFrontmatterEdit(_)))));
+        assert_eq!(read("yaml.md"), "---\ntitle: Yaml\n---\nHi");
+    }
+
+    #[test]
+    fn test_build_hidden_pages() {
+        let mut app = Site::open(&PathBuf::from("./testdata")).unwrap();
+        let mut sink = InMemorySink::default();
+        (&app).build_site(&mut sink, false).unwrap();
+        assert!(!sink.files.contains_key(Path::new("hidden.html")));
+
+        app.config.hidden_mode = HiddenMode::Noindex;
+        (&app).build_site(&mut sink, false).unwrap();
+        assert!(sink.text("hidden.html").unwrap().contains("<meta name=\"robots\" content=\"noindex\">"));
+        assert!(!sink.text("not_hidden.html").unwrap().contains("noindex"));
+    }
+
+    #[test]
+    fn test_sort_tree_by_mtime() {
+        let at = |secs| Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs));
+        let file = |name: &str, secs| TreeEntry::File(PathBuf::from(name), at(secs));
+        let tree = vec![
+            Dir(PathBuf::from("site"), at(0)),
+            file("site/b.md", 300),
+            Dir(PathBuf::from("site/posts"), at(200)),
+            file("site/posts/old.md", 10),
+            file("site/posts/new.md", 20),
+            CloseDir,
+            file("site/a.md", 100),
+            CloseDir
+        ];
+
+        assert_eq!(compare_entries(&tree[1], &tree[6], TreeSort::NewestFirst), Ordering::Less);
+        assert_eq!(compare_entries(&tree[1], &tree[6], TreeSort::OldestFirst), Ordering::Greater);
+        assert_eq!(compare_entries(&tree[1], &tree[6], TreeSort::Name), Ordering::Greater);
+
+        let names = |t: Vec<TreeEntry>| t.iter().map(|e| match e {
+            TreeEntry::File(p, _) | Dir(p, _) => utils::label_for_path(p),
+            CloseDir => "/".to_string()
+        }).collect::<Vec<_>>().join(" ");
+
+        // Newest first within each directory, with the posts dir keeping its children
+        assert_eq!(names(sort_tree(&tree, TreeSort::NewestFirst)), "site b.md posts new.md old.md / a.md /");
+        assert_eq!(names(sort_tree(&tree, TreeSort::OldestFirst)), "site a.md posts old.md new.md / b.md /");
+        assert_eq!(names(sort_tree(&tree, TreeSort::Name)), "site a.md b.md posts new.md old.md / /");
+    }
+
+    #[test]
+    fn test_filter_tree() {
+        let file = |name: &str| TreeEntry::File(PathBuf::from(name), None);
+        let tree = vec![
+            Dir(PathBuf::from("site"), None),
+            file("site/about.md"),
+            Dir(PathBuf::from("site/posts"), None),
+            file("site/posts/Hello.md"),
+            Dir(PathBuf::from("site/posts/drafts"), None),
+            file("site/posts/drafts/more.md"),
+            CloseDir,
+            CloseDir,
+            Dir(PathBuf::from("site/images"), None),
+            file("site/images/a.png"),
+            CloseDir,
+            CloseDir
+        ];
+        let names = |t: Vec<TreeEntry>| t.iter().map(|e| match e {
+            TreeEntry::File(p, _) | Dir(p, _) => utils::label_for_path(p),
+            CloseDir => "/".to_string()
+        }).collect::<Vec<_>>().join(" ");
+        let root = Path::new("site");
+
+        // Matches keep the dirs they're in, and a matching dir keeps everything in it
+        assert_eq!(names(filter_tree(&tree, root, "hello")), "site posts Hello.md / /");
+        assert_eq!(names(filter_tree(&tree, root, "Drafts")), "site posts drafts more.md / / /");
+        assert_eq!(names(filter_tree(&tree, root, ".md")), "site about.md posts Hello.md drafts more.md / / /");
+        // The root's own name doesn't count, so this finds nothing
+        assert_eq!(names(filter_tree(&tree, root, "site")), "site /");
+        assert_eq!(filter_tree(&tree, root, " ").len(), tree.len());
+    }
+
+    // Make a site in a temp dir out of some (path, contents) pairs
+    fn temp_site(files: &[(&str, &str)]) -> (tempfile::TempDir, Site) {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let site = Site::open(dir.path()).unwrap();
+        (dir, site)
+    }
+
+    #[test]
+    fn test_missing_layout() {
+        let (_dir, site) = temp_site(&[
+            ("_layouts/post.html", "<html>$content</html>"),
+            ("good.md", "+++\nlayout = \"_layouts/post.html\"\n+++\nFine"),
+            ("posts/bad.md", "+++\nlayout = \"_layouts/missing.html\"\n+++\nBroken"),
+            ("raw.txt", "layout = \"_layouts/missing.html\""),
+        ]);
+
+        let diagnostics = (&site).validate();
+        assert_eq!(diagnostics, vec![Diagnostic {
+            severity: Severity::Error,
+            file: PathBuf::from("posts/bad.md"),
+            message: "Layout _layouts/missing.html doesn't exist".to_string()
+        }]);
+
+        // And the build refuses to start
+        let target = tempfile::tempdir().unwrap();
+        assert!(matches!((&site).build_to_dir(target.path(), false), Err(FleenError::Invalid(d)) if d == diagnostics));
+
+        // The testdata site builds, though
+        let app = Site::open(&PathBuf::from("./testdata")).unwrap();
+        assert!((&app).validate().iter().all(|d| d.severity == Severity::Warning));
+    }
+
+    #[test]
+    fn test_resolve_link() {
+        let from = Path::new("posts/first.md");
+        assert_eq!(resolve_link(from, "second.html"), Some(PathBuf::from("posts/second.html")));
+        assert_eq!(resolve_link(from, "../images/a.png#x"), Some(PathBuf::from("images/a.png")));
+        assert_eq!(resolve_link(from, "/about/"), Some(PathBuf::from("about/index.html")));
+        assert_eq!(resolve_link(from, "../../etc/passwd"), Some(PathBuf::from("..")));
+        assert_eq!(resolve_link(from, "https://example.com/"), None);
+        assert_eq!(resolve_link(from, "#heading"), None);
+        assert_eq!(resolve_link(from, "tel:+15555550100"), None);
+    }
+
+    #[test]
+    fn test_strict_build() {
+        let (_dir, site) = temp_site(&[
+            ("index.md", "[About](about.html) and [nowhere](missing.html) and [elsewhere](https://example.com)"),
+            ("about.md", "About"),
+        ]);
+
+        // Not strict: it builds, but tells us about the broken link
+        let target = tempfile::tempdir().unwrap();
+        let warnings = (&site).build_to_dir(target.path(), false).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message, "Broken link to missing.html");
+        assert!(target.path().join("index.html").exists());
+
+        // Strict: the same warning fails the build
+        let target = tempfile::tempdir().unwrap();
+        let result = (&site).build_to_dir(target.path(), true);
+        assert!(matches!(result, Err(FleenError::Invalid(d)) if d == warnings));
+        assert!(!target.path().join("index.html").exists());
+    }
+
+    #[test]
+    fn test_build_checks_links() {
+        let (_dir, site) = temp_site(&[
+            ("_config.toml", "base_url = \"https://example.com\"\n"),
+            ("_layouts/main.html", "<a href=\"/\">Home</a> <a href=\"/sitemap.xml\">Map</a> <a href=\"/archive/\">Archive</a> $content"),
+            ("index.md", "+++\nlayout = \"_layouts/main.html\"\n+++\n[nowhere](missing.html)"),
+            ("other.md", "+++\nlayout = \"_layouts/main.html\"\n+++\nAlso here"),
+            ("about.html", "<img src=\"me.png\"> <a href=\"mailto:me@example.com\">Mail</a> <a href=\"https://example.com/x\">X</a>"),
+        ]);
+        let mut sink = InMemorySink::default();
+        let warnings = (&site).build_site(&mut sink, false).unwrap();
+        let mut messages: Vec<String> = warnings.iter().map(|d| format!("{}: {}", d.file.display(), d.message)).collect();
+        messages.sort();
+        // The layout's broken link is in both pages, but it only needs fixing once
+        assert_eq!(messages, vec![
+            "_layouts/main.html: Broken link to /archive/",
+            "about.html: Broken link to me.png",
+            "index.md: Broken link to missing.html",
+        ]);
+        assert!(sink.text("about.html").is_some());
+
+        let compiled = compile_sources(&site.root, &site.config).unwrap();
+        assert_eq!(check_links(&compiled, &site.root, &site.config, &[PathBuf::from("sitemap.xml")]).len(), 3);
+    }
+
+    #[test]
+    fn test_section_index_links() {
+        let (_dir, site) = temp_site(&[
+            ("index.md", "[Posts](posts/) and [also posts](posts/index.html)"),
+            ("posts/_index.md", "+++\ntitle = \"Posts\"\n+++\n"),
+            ("posts/a.md", "A"),
+        ]);
+        assert!(output_exists(&site.root, Path::new("posts")));
+        assert!(output_exists(&site.root, Path::new("posts/index.html")));
+        assert!((&site).validate().is_empty());
+        let target = tempfile::tempdir().unwrap();
+        assert!((&site).build_to_dir(target.path(), true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_configured_favicon() {
+        let icon = include_bytes!("../templates/favicon.png");
+        let (dir, mut site) = temp_site(&[
+            ("_layouts/page.html", "<html><head></head><body>$content</body></html>"),
+            ("index.md", "+++\nlayout = \"_layouts/page.html\"\n+++\nHello")
+        ]);
+        fs::create_dir(dir.path().join("_assets")).unwrap();
+        fs::write(dir.path().join("_assets/icon.png"), icon).unwrap();
+        site.config.favicon = Some("_assets/icon.png".to_string());
+
+        let target = tempfile::tempdir().unwrap();
+        (&site).build_to_dir(target.path(), false).unwrap();
+        assert_eq!(fs::read(target.path().join("favicon.png")).unwrap(), icon);
+        // And pages link to it
+        assert!(fs::read_to_string(target.path().join("index.html")).unwrap().contains("<link rel=\"icon\" href=\"/favicon.png\">"));
+
+        // Pointing at a file that isn't there is caught before building
+        site.config.favicon = Some("_assets/missing.ico".to_string());
+        assert!(matches!((&site).build_to_dir(target.path(), false), Err(FleenError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_scaffolded_favicon() {
+        let dir = tempfile::tempdir().unwrap();
+        Site::create(dir.path()).unwrap();
+        fs::write(dir.path().join("index.md"), "+++\nlayout = \"_layouts/default.html\"\n+++\nHi").unwrap();
+        let target = tempfile::tempdir().unwrap();
+        (&Site::open(dir.path()).unwrap()).build_to_dir(target.path(), false).unwrap();
+        assert!(target.path().join("favicon.png").exists());
+        assert!(fs::read_to_string(target.path().join("index.html")).unwrap().contains("<link rel=\"icon\" href=\"/favicon.png\">"));
+    }
+
+    #[test]
+    fn test_dry_run_command() {
+        let env = |command: &Command| command.get_envs()
+            .find(|(k, _)| *k == "FLEEN_DRY_RUN")
+            .and_then(|(_, v)| v.map(|v| v.to_string_lossy().to_string()));
+        let (dir, site) = temp_site(&[("_scripts/deploy.sh", "echo deploying")]);
+        let dry_run = DeployOptions { dry_run: true, ..DeployOptions::default() };
+
+        // A real deploy runs deploy.sh with no dry run flag, in the output, told where things are
+        let output = tempfile::tempdir().unwrap();
+        let command = (&site).deploy_command(DeployOptions::default(), output.path()).unwrap();
+        assert_eq!(command.get_program(), dir.path().join("_scripts/deploy.sh"));
+        assert_eq!(env(&command), None);
+        let root = dir.path().canonicalize().unwrap();
+        assert_eq!(command.get_args().collect::<Vec<_>>(), vec![output.path().as_os_str(), root.as_os_str()]);
+        assert_eq!(command.get_current_dir(), Some(output.path()));
+        assert!(command.get_envs().any(|(k, v)| k == "FLEEN_SITE_ROOT" && v == Some(root.as_os_str())));
+
+        // With no dry run script, a dry run uses the normal script but tells it so
+        let command = (&site).deploy_command(dry_run, output.path()).unwrap();
+        assert_eq!(command.get_program(), dir.path().join("_scripts/deploy.sh"));
+        assert_eq!(env(&command), Some("1".to_string()));
+
+        // And if there is one, it's used instead
+        fs::write(dir.path().join("_scripts/deploy-dryrun.sh"), "echo pretending").unwrap();
+        let command = (&site).deploy_command(dry_run, output.path()).unwrap();
+        assert_eq!(command.get_program(), dir.path().join("_scripts/deploy-dryrun.sh"));
+        assert_eq!(env(&command), Some("1".to_string()));
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_deploy_output() {
+        let (dir, site) = temp_site(&[("index.md", "Hi")]);
+        let script = dir.path().join("_scripts/deploy.sh");
+        fs::create_dir(dir.path().join("_scripts")).unwrap();
+        let write_script = |body: &str| {
+            fs::write(&script, format!("#!/bin/sh\necho run >> {}\n{}", dir.path().join("runs.txt").display(), body)).unwrap();
+            let mut permissions = fs::metadata(&script).unwrap().permissions();
+            std::os::unix::fs::PermissionsExt::set_mode(&mut permissions, 0o755);
+            fs::set_permissions(&script, permissions).unwrap();
+        };
+
+        // Failures say why, from stderr
+        write_script("echo trying\necho 'no route to host' >&2\nexit 1");
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let result = (&site).build_and_deploy(DeployOptions::default(), sender, Arc::default()).await;
+        assert!(matches!(result, Err(FleenError::DeployError(message)) if message == "trying\nno route to host\n"));
+        // Each line came through on its own as well
+        let mut lines: Vec<String> = receiver.try_iter().collect();
+        lines.sort();
+        assert_eq!(lines, vec!["no route to host", "trying"]);
+
+        // And the script only ran once
+        write_script("echo done");
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        assert_eq!((&site).build_and_deploy(DeployOptions::default(), sender, Arc::default()).await.unwrap(), "done\n");
+        assert_eq!(fs::read_to_string(dir.path().join("runs.txt")).unwrap(), "run\nrun\n");
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_deploy_timeout() {
+        let (dir, mut site) = temp_site(&[("index.md", "Hi")]);
+        let script = dir.path().join("_scripts/deploy.sh");
+        let output_path = dir.path().join("output.txt");
+        fs::create_dir(dir.path().join("_scripts")).unwrap();
+        fs::write(&script, format!("#!/bin/sh
+echo \"$1\" > {}
+(sleep 2; echo late > {}) &
+sleep 10", output_path.display(), dir.path().join("late.txt").display())).unwrap();
+        let mut permissions = fs::metadata(&script).unwrap().permissions();
+        std::os::unix::fs::PermissionsExt::set_mode(&mut permissions, 0o755);
+        fs::set_permissions(&script, permissions).unwrap();
+
+        // A script that runs too long is stopped, and the build it was given is cleaned up
+        site.config.deploy_timeout = 1;
+        let started = std::time::Instant::now();
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let result = (&site).build_and_deploy(DeployOptions::default(), sender, Arc::default()).await;
+        assert!(matches!(result, Err(FleenError::DeployTimedOut)));
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+        let output = fs::read_to_string(&output_path).unwrap();
+        assert!(!Path::new(output.trim()).exists());
+        // Along with everything it started
+        tokio::time::sleep(std::time::Duration::from_millis(2500).saturating_sub(started.elapsed())).await;
+        assert!(!dir.path().join("late.txt").exists());
+
+        // Cancelling stops it the same way
+        site.config.deploy_timeout = 0;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let flag = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+            flag.store(true, std::sync::atomic::Ordering::Relaxed);
+        });
+        let result = (&site).build_and_deploy(DeployOptions::default(), sender, cancel).await;
+        assert!(matches!(result, Err(FleenError::DeployCancelled)));
+        let output = fs::read_to_string(&output_path).unwrap();
+        assert!(!Path::new(output.trim()).exists());
+    }
+
+    #[test]
+    fn test_undersized_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tiny, large) = (dir.path().join("tiny.png"), dir.path().join("large.jpg"));
+        image::RgbImage::new(4, 3).save(&tiny).unwrap();
+        image::RgbImage::new(1300, 700).save(&large).unwrap();
+        let mut config = SiteConfig::default();
+
+        // No minimum configured, no warnings
+        assert_eq!(undersized_image(&tiny, &config), None);
+
+        config.min_image_width = Some(1200);
+        config.min_image_height = Some(600);
+        assert_eq!(undersized_image(&tiny, &config), Some("Image is 4x3, smaller than the minimum 1200x600".to_string()));
+        assert_eq!(undersized_image(&large, &config), None);
+
+        // Not an image at all
+        assert_eq!(undersized_image(Path::new("./testdata/raw.txt"), &config), None);
+    }
+
+    #[test]
+    fn test_convert_pasted_image() {
+        let mut png = io::Cursor::new(vec![]);
+        image::RgbaImage::new(400, 200).write_to(&mut png, image::ImageFormat::Png).unwrap();
+        let png = png.into_inner();
+        let mut config = SiteConfig::default();
+
+        // Left alone by default, or if it's already small enough
+        assert_eq!(convert_pasted_image(png.clone(), &config).unwrap(), png);
+        config.max_image_width = Some(400);
+        assert_eq!(convert_pasted_image(png.clone(), &config).unwrap(), png);
+
+        // Shrunk to fit, keeping its shape
+        config.max_image_width = Some(100);
+        let shrunk = convert_pasted_image(png.clone(), &config).unwrap();
+        assert_eq!(image::guess_format(&shrunk).unwrap(), image::ImageFormat::Png);
+        let shrunk = image::load_from_memory(&shrunk).unwrap();
+        assert_eq!((shrunk.width(), shrunk.height()), (100, 50));
+
+        // And re-encoded if asked, transparency or not
+        config.paste_format = PasteFormat::Jpeg;
+        let jpeg = convert_pasted_image(png.clone(), &config).unwrap();
+        assert_eq!(image::guess_format(&jpeg).unwrap(), image::ImageFormat::Jpeg);
+        config.paste_format = PasteFormat::Webp;
+        config.max_image_width = None;
+        let webp = convert_pasted_image(png.clone(), &config).unwrap();
+        assert_eq!(image::guess_format(&webp).unwrap(), image::ImageFormat::WebP);
+        let webp = image::load_from_memory(&webp).unwrap();
+        assert_eq!((webp.width(), webp.height()), (400, 200));
+
+        assert!(matches!(convert_pasted_image(b"not an image".to_vec(), &config), Err(FleenError::ImageConvert(_))));
+    }
+
+    #[test]
+    fn test_parallel_compile() {
+        let mut files = vec![("_layouts/page.html".to_string(), "<main>$content</main>".to_string())];
+        for dir in 0..10 {
+            for n in 0..30 {
+                files.push((format!("section{}/page{}.md", dir, n), format!("+++\nlayout = \"_layouts/page.html\"\n+++\n# Page {} {}", dir, n)));
+            }
+            files.push((format!("section{}/style.css", dir), "p {}".to_string()));
+        }
+        let files: Vec<(&str, &str)> = files.iter().map(|(path, contents)| (path.as_str(), contents.as_str())).collect();
+        let (_dir, site) = temp_site(&files);
+
+        let serial: Vec<RenderOutput> = source_paths(&site.root, &site.config).unwrap().into_iter()
+            .map(|source| renderer::file_render(source, &site.root, &site.config).unwrap())
+            .collect();
+        let parallel = (&site).compile().unwrap();
+        assert_eq!(parallel, serial);
+        assert_eq!(parallel.iter().filter(|o| matches!(o, RenderOutput::Rendered(..))).count(), 300);
+
+        // Every dir comes before what's in it
+        for (i, output) in parallel.iter().enumerate() {
+            let RenderOutput::Dir(dir) = output else { continue };
+            assert!(parallel[..i].iter().all(|o| !matches!(o, RenderOutput::Rendered(p, _) | RenderOutput::RawFile(p) if p.starts_with(dir))));
+        }
+
+        // A bad page still fails the whole thing
+        fs::write(site.root.join("section3/page7.md"), "+++\nlayout = [\n+++\nBroken").unwrap();
+        assert!(matches!((&site).compile(), Err(FleenError::RenderError(RenderError::FrontmatterParse(_, path))) if path == Path::new("section3/page7.md")));
+    }
+
+    #[test]
+    fn test_compile_missing_dir() {
+        let (dir, site) = temp_site(&[("posts/a.md", "A")]);
+        // Gone between opening the site and building it: an error, not a panic
+        fs::remove_dir_all(dir.path()).unwrap();
+        assert!(matches!((&site).compile(), Err(FleenError::FileIo(..))));
+    }
+
+    #[test]
+    fn test_build_in_memory() {
+        let app = Site::open(&PathBuf::from("./testdata")).unwrap();
+        let mut sink = InMemorySink::default();
+        (&app).build_site(&mut sink, false).unwrap();
+
+        let files: Vec<&str> = sink.files.keys().map(|p| p.to_str().unwrap()).collect();
+        assert_eq!(files, vec![
+            "build-info.json", "dir/.keep", "dir/subdir.html", "feed.xml", "index.html", "mixed.html",
+            "nolayout.html", "not_hidden.html", "plain.html", "raw.txt"
+        ]);
+        assert_eq!(sink.dirs.iter().collect::<Vec<_>>(), vec![Path::new("dir")]);
+        assert!(sink.text("index.html").unwrap().contains("Pest Toast"));
+        assert_eq!(sink.text("raw.txt"), Some(fs::read_to_string("./testdata/raw.txt").unwrap().as_str()));
+
+        // Rebuilding clears out anything that wasn't preserved
+        sink.write(Path::new("stale.html"), b"old").unwrap();
+        sink.write(Path::new("CNAME"), b"example.com").unwrap();
+        let app = Site { config: SiteConfig { preserve: vec!["CNAME".to_string()], ..SiteConfig::default() }, ..app };
+        (&app).build_site(&mut sink, false).unwrap();
+        assert!(!sink.files.contains_key(Path::new("stale.html")));
+        assert_eq!(sink.text("CNAME"), Some("example.com"));
+    }
+
+    #[test]
+    fn test_duplicate_page() {
+        let (dir, site) = temp_site(&[
+            ("posts/first.md", "# First"),
+            ("posts/first-copy.md", "# Already copied"),
+            ("posts/images/a.png", "png")
+        ]);
+        let first = dir.path().join("posts/first.md").to_string_lossy().to_string();
+        let (site, copy) = (&site).duplicate_page(&first).unwrap();
+        assert_eq!(copy, dir.path().join("posts/first-copy-2.md"));
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "# First");
+        assert!(site.tree.iter().any(|e| matches!(e, TreeEntry::File(p, _) if *p == copy)));
+
+        // Dirs are copied with everything in them
+        let posts = dir.path().join("posts").to_string_lossy().to_string();
+        let (_, copy) = (&site).duplicate_page(&posts).unwrap();
+        assert_eq!(copy, dir.path().join("posts-copy"));
+        assert_eq!(fs::read_to_string(copy.join("first-copy-2.md")).unwrap(), "# First");
+        assert_eq!(fs::read_to_string(copy.join("images/a.png")).unwrap(), "png");
+    }
+
+    #[test]
+    fn test_renderable_count() {
+        let (_dir, site) = temp_site(&[
+            ("index.md", "# Home"),
+            ("posts/first.md", "# First"),
+            ("style.css", "body {}"),
+            ("_layouts/default.html", "$content"),
+            ("_scripts/deploy.sh", "echo hi")
+        ]);
+        assert_eq!(site.renderable_count(), 3);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_tree_skips_unreadable_names() {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.md"), "# Home").unwrap();
+        fs::write(dir.path().join(std::ffi::OsStr::from_bytes(b"bad\xff.md")), "# Bad").unwrap();
+        let site = Site::open(dir.path()).unwrap();
+        let files: Vec<&PathBuf> = site.tree.iter().filter_map(|e| match e { TreeEntry::File(p, _) => Some(p), _ => None }).collect();
+        assert_eq!(files, vec![&dir.path().join("index.md")]);
+    }
+
+    #[test]
+    fn test_nested_output_dir() {
+        let (dir, site) = temp_site(&[
+            ("_config.toml", "output_dir = \"public\""),
+            ("index.md", "# Home"),
+            ("public/index.html", "<h1>Old build</h1>"),
+            ("_fleen/state.toml", "")
+        ]);
+        let in_tree = |site: &Site, name: &str| site.tree.iter().any(|e| matches!(e, TreeEntry::File(p, _) | Dir(p, _) if p.starts_with(dir.path().join(name))));
+        assert!(in_tree(&site, "index.md"));
+        assert!(!in_tree(&site, "public"));
+        assert!(!in_tree(&site, "_fleen"));
+        let outputs = (&site).compile().unwrap();
+        assert!(outputs.iter().all(|o| !matches!(o, RenderOutput::Rendered(p, _) | RenderOutput::RawFile(p) | RenderOutput::Dir(p) if p.starts_with("public") || p.starts_with("_fleen"))));
+
+        // Building into it is fine, and the build doesn't end up inside itself
+        (&site).build_to_dir(&dir.path().join("public"), false).unwrap();
+        assert!(dir.path().join("public/index.html").is_file());
+        assert!(!dir.path().join("public/public").exists());
+        assert!(matches!((&site).build_to_dir(&dir.path().join("elsewhere"), false), Err(TargetDir)));
+        // Nor will watching, which would rebuild every time it saw its own build
+        assert!(matches!((&site).watch_build(&dir.path().join("elsewhere"), false), Err(TargetDir)));
+    }
+
+    #[test]
+    fn test_build_id() {
+        let (dir, site) = temp_site(&[
+            ("_layouts/default.html", "<footer>Build $build_id</footer>$content"),
+            ("index.md", "+++\nlayout = \"_layouts/default.html\"\n+++\nHi")
+        ]);
+        let build = |site: &Site| {
+            let mut sink = InMemorySink::default();
+            site.build_site(&mut sink, false).unwrap();
+            let info = sink.text("build-info.json").unwrap().to_string();
+            let id = info.lines().find_map(|l| l.trim().strip_prefix("\"build_id\": \"")).unwrap().trim_end_matches("\",").to_string();
+            assert!(info.contains("\"built_at\": \"2"));
+            assert_eq!(sink.text("index.html").unwrap(), format!("<footer>Build {}</footer><p>Hi</p>", id));
+            (id, info)
+        };
+
+        // Not a repo, so it's a timestamp
+        let (id, info) = build(&site);
+        assert!(info.contains("\"source\": \"timestamp\""));
+        assert_eq!(id.len(), 14);
+
+        // In a repo it's the commit
+        let git = |args: &[&str]| Command::new("git").args(args).current_dir(dir.path()).output().unwrap();
+        git(&["init", "-q"]);
+        git(&["add", "-A"]);
+        git(&["-c", "user.name=Test", "-c", "user.email=test@localhost", "commit", "-q", "-m", "Site"]);
+        let sha = String::from_utf8(git(&["rev-parse", "--short", "HEAD"]).stdout).unwrap();
+        let (id, info) = build(&site);
+        assert_eq!(id, sha.trim());
+        assert!(info.contains("\"source\": \"git\""));
+
+        // And building the same commit again gives the same file
+        assert_eq!(build(&site).1, info);
+    }
+
+    #[test]
+    fn test_build_feed() {
+        let (dir, site) = temp_site(&[
+            ("index.md", "Home"),
+            ("posts/first.md", "+++\ntitle = \"First\"\ndate = 2024-01-01\n+++\nOne"),
+            ("posts/second.md", "+++\ntitle = \"Second\"\ndate = \"2024-02-01\"\n+++\nTwo"),
+            ("posts/unpublished.md", "+++\ntitle = \"Shh\"\ndate = 2024-03-01\npublished = false\n+++\nShh")
+        ]);
+        let mut sink = InMemorySink::default();
+        (&site).build_site(&mut sink, false).unwrap();
+        assert!(sink.text("feed.xml").is_none()); // No base url, no feed
+
+        fs::write(dir.path().join("_config.toml"), "base_url = \"https://example.com\"\nfeed_title = \"Posts\"\nhidden_mode = \"noindex\"").unwrap();
+        let site = Site::open(dir.path()).unwrap();
+        (&site).build_site(&mut sink, false).unwrap();
+        let feed = sink.text("feed.xml").unwrap();
+        assert!(feed.contains("<title>Posts</title>"));
+        let second = feed.find("<link>https://example.com/posts/second.html</link>").unwrap();
+        let first = feed.find("<link>https://example.com/posts/first.html</link>").unwrap();
+        assert!(second < first); // Newest first
+        assert!(!feed.contains("Shh") && !feed.contains("index"));
+
+        // A site with a feed of its own keeps it
+        fs::write(dir.path().join("feed.xml"), "<rss/>").unwrap();
+        (&site).build_site(&mut sink, false).unwrap();
+        assert_eq!(sink.text("feed.xml"), Some("<rss/>"));
+    }
+
+    #[test]
+    fn test_build_sitemap() {
+        let (dir, site) = temp_site(&[("index.md", "Home"), ("posts/first.md", "One"), ("hidden.md", "+++\npublished = false\n+++\nShh")]);
+        let mut sink = InMemorySink::default();
+        (&site).build_site(&mut sink, false).unwrap();
+        assert!(sink.text("sitemap.xml").is_none()); // No base url, no sitemap
+
+        fs::write(dir.path().join("_config.toml"), "base_url = \"https://example.com\"").unwrap();
+        let site = Site::open(dir.path()).unwrap();
+        (&site).build_site(&mut sink, false).unwrap();
+        let sitemap = sink.text("sitemap.xml").unwrap();
+        assert!(sitemap.contains("<loc>https://example.com/</loc>") && sitemap.contains("<loc>https://example.com/posts/first.html</loc>"));
+        assert!(!sitemap.contains("hidden"));
+    }
+
+    #[test]
+    fn test_no_empty_dirs() {
+        let (_dir, site) = temp_site(&[
+            ("index.md", "Home"),
+            ("drafts/_notes.md", "Skipped"),
+            ("posts/2024/one.md", "One")
+        ]);
+        let target = tempfile::tempdir().unwrap();
+        (&site).build_to_dir(target.path(), false).unwrap();
+        assert!(!target.path().join("drafts").exists());
+        assert!(target.path().join("posts/2024/one.html").is_file());
+
+        let mut sink = InMemorySink::default();
+        (&site).build_site(&mut sink, false).unwrap();
+        assert_eq!(sink.dirs.iter().collect::<Vec<_>>(), vec![Path::new("posts"), Path::new("posts/2024")]);
+    }
+
+    #[test]
+    fn test_fix_moved_image_links() {
+        let (_dir, site) = temp_site(&[
+            ("index.md", "# Hi\n\n![A photo](/images/photo.png \"title\")\n\n![Lost](/images/gone.png)\n\n![Fine](/images/2024/other.png)\n"),
+            ("images/2024/photo.png", "not really a png"),
+            ("images/2024/other.png", "not really a png")
+        ]);
+        let report = (&site).plan_image_link_fixes();
+        assert_eq!(report.fixes, vec![LinkFix {
+            file: PathBuf::from("index.md"),
+            from: "/images/photo.png".to_string(),
+            to: "/images/2024/photo.png".to_string()
+        }]);
+        assert_eq!(report.unresolved.len(), 1);
+        assert!(report.unresolved[0].message.contains("gone.png"));
+
+        let site = (&site).apply_link_fixes(&report.fixes).unwrap();
+        assert_eq!(fs::read_to_string(site.root.join("index.md")).unwrap(),
+                   "# Hi\n\n![A photo](/images/2024/photo.png \"title\")\n\n![Lost](/images/gone.png)\n\n![Fine](/images/2024/other.png)\n");
+        assert!((&site).plan_image_link_fixes().fixes.is_empty());
+    }
+
+    #[test]
+    fn test_create_layout() {
+        let (_dir, site) = temp_site(&[("index.md", "Hi")]);
+        let (site, path) = (&site).create_layout("post").unwrap();
+        assert_eq!(path, site.root.join("_layouts/post.html"));
+        let layout = fs::read_to_string(&path).unwrap();
+        assert!(layout.starts_with("<!DOCTYPE html>"));
+        assert!(layout.contains("<title>$title</title>"));
+        assert!(layout.contains("$content"));
+
+        assert!(matches!((&site).create_layout("post.html"), Err(FleenError::FileExists(_))));
+        assert!(matches!((&site).create_layout("../escape"), Err(FleenError::InvalidName(_))));
+        assert!(matches!((&site).create_layout(" "), Err(FleenError::InvalidName(_))));
+    }
+
+    #[test]
+    fn test_duplicate_image_paste() {
+        let (dir, site) = temp_site(&[("images/.keep", "")]);
+        let png = include_bytes!("../templates/favicon.png");
+        let (site, first) = (&site).save_image(png).unwrap();
+        let (site, second) = (&site).save_image(png).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(image_link(&first, &site.root), image_link(&second, &site.root));
+        assert_eq!(fs::read_dir(dir.path().join("images")).unwrap().count(), 2); // The image and .keep
+
+        // Anything else is a new image
+        let (_, third) = (&site).save_image(b"something else").unwrap();
+        assert_ne!(first, third);
+    }
+
+    /// A clipboard that's either missing or holding some image
+    struct FakeClipboard(Option<Option<Vec<u8>>>, std::cell::RefCell<String>);
+
+    impl ImageClipboard for FakeClipboard {
+        fn image(&self) -> Result<Option<Vec<u8>>, FleenError> {
+            self.0.clone().ok_or(FleenError::ClipboardUnavailable("no display".to_string()))
+        }
+
+        fn set_text(&self, text: String) -> Result<(), FleenError> {
+            *self.1.borrow_mut() = text;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_clipboard_failures() {
+        let (_dir, site) = temp_site(&[("images/.keep", "")]);
+        let missing = FakeClipboard(None, Default::default());
+        assert!(matches!((&site).paste_image_from(&missing), Err(FleenError::ClipboardUnavailable(_))));
+        let empty = FakeClipboard(Some(None), Default::default());
+        assert!(matches!((&site).paste_image_from(&empty), Err(FleenError::NoClipboardImage)));
+
+        let png = include_bytes!("../templates/favicon.png").to_vec();
+        let full = FakeClipboard(Some(Some(png)), Default::default());
+        let (_, path) = (&site).paste_image_from(&full).unwrap();
+        assert_eq!(*full.1.borrow(), image_link(&path, &site.root));
+        assert_eq!(path.extension().unwrap(), "png");
+
+        // Other formats are kept as they are, under a name to match
+        let mut jpeg = std::io::Cursor::new(vec![]);
+        image::RgbImage::new(4, 3).write_to(&mut jpeg, image::ImageFormat::Jpeg).unwrap();
+        let full = FakeClipboard(Some(Some(jpeg.get_ref().clone())), Default::default());
+        let (_, path) = (&site).paste_image_from(&full).unwrap();
+        assert_eq!(path.extension().unwrap(), "jpg");
+        assert_eq!(fs::read(&path).unwrap(), *jpeg.get_ref());
+        assert!(full.1.borrow().ends_with(".jpg)"));
+    }
+
+    #[test]
+    fn test_configured_image_dir() {
+        let (dir, site) = temp_site(&[
+            ("_config.toml", "image_dir = \"assets/pics\"\nimage_name = \"{date}-shot\""),
+            ("assets/pics/.keep", "")
+        ]);
+        let (site, first) = (&site).save_image(b"one").unwrap();
+        let (site, second) = (&site).save_image(b"two").unwrap();
+        let today = chrono::Local::now().format("%Y-%m-%d");
+        assert_eq!(first, dir.path().join(format!("assets/pics/{}-shot.png", today)));
+        assert_eq!(second, dir.path().join(format!("assets/pics/{}-shot-2.png", today)));
+        assert_eq!(image_link(&second, &site.root), format!("![](/assets/pics/{}-shot-2.png)", today));
+
+        // No dir, no pasting
+        fs::remove_dir_all(dir.path().join("assets/pics")).unwrap();
+        assert!(!(&Site::open(dir.path()).unwrap()).image_dir_exists());
+    }
+
+    #[test]
+    fn test_dropped_files() {
+        let (dir, site) = temp_site(&[("images/.keep", ""), ("posts/first.md", "One")]);
+        let outside = tempfile::tempdir().unwrap();
+        let (drawing, page) = (outside.path().join("Drawing.SVG"), outside.path().join("second.md"));
+        fs::write(&drawing, "<svg></svg>").unwrap();
+        fs::write(&page, "Two").unwrap();
+        assert!(is_image_file(&drawing) && !is_image_file(&page));
+
+        // Images go in images/, keeping their format, and the link goes on the clipboard
+        let clipboard = FakeClipboard(Some(None), Default::default());
+        let (site, path) = (&site).add_image_file(&drawing, Some(&clipboard)).unwrap();
+        assert_eq!(path.extension().unwrap(), "svg");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<svg></svg>");
+        assert_eq!(*clipboard.1.borrow(), image_link(&path, &site.root));
+
+        // Pages go next to the selected file, once
+        let selected = dir.path().join("posts/first.md").to_string_lossy().to_string();
+        let (site, path) = (&site).import_page(&page, Some(&selected)).unwrap();
+        assert_eq!(path, dir.path().join("posts/second.md"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Two");
+        assert!(matches!((&site).import_page(&page, Some(&selected)), Err(FleenError::FileExists(_))));
+    }
+
+    #[test]
+    fn test_build_summary() {
+        let (_dir, site) = temp_site(&[
+            ("index.md", "# Home"),
+            ("posts/one.md", "One"),
+            ("posts/draft.md", "+++\ndraft = true\n+++\nNot yet"),
+            ("style.css", "body {}"),
+            ("images/cat.png", "0123456789"),
+            ("_layouts/post.html", "$content")
+        ]);
+        let summary = (&site).build_summary().unwrap();
+        assert_eq!((summary.pages, summary.assets), (2, 2));
+        let pages = "<h1 id=\"home\">Home</h1>".len() + "<p>One</p>".len();
+        assert_eq!(summary.bytes, (pages + "body {}".len() + 10) as u64);
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use chrono::{DateTime, FixedOffset};
+use serde::Deserialize;
+use crate::fleen_app::FleenError;
+use crate::utils;
+
+/// Deploying by committing the built site to a branch, gh-pages style. Set up in
+/// `_config.toml` under `[git_deploy]`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct GitDeploy {
+    /// Anything `git push` understands: a url, a path, a remote name won't do though
+    pub repo: String,
+    pub branch: String,
+    /// The commit message; `{date}` is replaced with the current date and time
+    pub message: String
+}
+
+impl Default for GitDeploy {
+    fn default() -> Self {
+        Self { repo: String::new(), branch: "gh-pages".to_string(), message: "Site build {date}".to_string() }
+    }
+}
+
+impl GitDeploy {
+    /// Commit the contents of `output` to the branch, replacing whatever was there, and push it.
+    /// A branch that doesn't exist yet gets created. Returns git's output, for the console.
+    pub fn deploy(&self, output: &Path) -> Result<String, FleenError> {
+        let work = tempfile::tempdir()?;
+        let mut log = String::new();
+        let mut git = |args: &[&str]| run_git(work.path(), args, &mut log);
+
+        git(&["init", "-q"])?;
+        git(&["remote", "add", "origin", &self.repo])?;
+        // If the branch is already there we build on top of it, so it keeps its history
+        if git(&["fetch", "-q", "--depth", "1", "origin", &self.branch]).is_ok() {
+            git(&["checkout", "-q", "-b", &self.branch, "FETCH_HEAD"])?;
+        } else {
+            git(&["checkout", "-q", "--orphan", &self.branch])?;
+        }
+        if git(&["config", "user.email"]).is_err() {
+            git(&["config", "user.name", "Fleen"])?;
+            git(&["config", "user.email", "fleen@localhost"])?;
+        }
+
+        // Swap the old build for the new one
+        for entry in fs::read_dir(work.path())?.flatten() {
+            if entry.file_name() == ".git" { continue }
+            if entry.file_type()?.is_dir() { fs::remove_dir_all(entry.path())? } else { fs::remove_file(entry.path())? }
+        }
+        utils::copy_dir(output, work.path())?;
+        git(&["add", "-A"])?;
+
+        if git(&["diff", "--cached", "--quiet"]).is_ok() && git(&["rev-parse", "--verify", "-q", "HEAD"]).is_ok() {
+            log.push_str("Nothing changed since the last deploy\n");
+            return Ok(log)
+        }
+        let message = self.message.replace("{date}", &chrono::Local::now().format("%Y-%m-%d %H:%M").to_string());
+        let mut git = |args: &[&str]| run_git(work.path(), args, &mut log);
+        git(&["commit", "-q", "-m", &message])?;
+        git(&["push", "-q", "origin", &self.branch])?;
+        log.push_str(&format!("Pushed to {} on {}\n", self.branch, self.repo));
+        Ok(log)
+    }
+}
+
+/// The short hash and time of the commit a dir is checked out at, if it's in a git repo at all
+/// and we're allowed to run git to find out
+pub fn head_commit(dir: &Path) -> Option<(String, DateTime<FixedOffset>)> {
+    utils::allow_spawn("run git").ok()?;
+    let output = Command::new("git").args(["log", "-1", "--format=%h %cI"]).current_dir(dir).output().ok()?;
+    if !output.status.success() { return None }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (sha, time) = stdout.trim().split_once(' ')?;
+    Some((sha.to_string(), DateTime::parse_from_rfc3339(time).ok()?))
+}
+
+/// Run git in a dir, keeping its output in the log. Failing commands are errors.
+///
+/// Pushing authenticates however the user's git already does, through the ssh agent, a
+/// credential helper, or their own `core.sshCommand`. There's no terminal for git to ask for a
+/// password on, so it's told not to try, and gets no stdin to read one from either: a push that
+/// needs one fails instead of hanging the deploy forever.
+fn run_git(dir: &Path, args: &[&str], log: &mut String) -> Result<(), FleenError> {
+    let output = Command::new("git").env("GIT_TERMINAL_PROMPT", "0").stdin(Stdio::null())
+        .args(args).current_dir(dir).output()
+        .map_err(|e| FleenError::DeployError(format!("Can't run git: {}", e)))?;
+    log.push_str(&String::from_utf8_lossy(&output.stdout));
+    log.push_str(&String::from_utf8_lossy(&output.stderr));
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(FleenError::DeployError(format!("git {} failed:\n{}", args.join(" "), String::from_utf8_lossy(&output.stderr))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git_output(repo: &Path, args: &[&str]) -> String {
+        let output = Command::new("git").args(args).current_dir(repo).output().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn test_deploy_to_branch() {
+        let remote = tempfile::tempdir().unwrap();
+        git_output(remote.path(), &["init", "-q", "--bare"]);
+        let build = tempfile::tempdir().unwrap();
+        fs::create_dir(build.path().join("dir")).unwrap();
+        fs::write(build.path().join("index.html"), "<p>One</p>").unwrap();
+        fs::write(build.path().join("dir/page.html"), "<p>Page</p>").unwrap();
+
+        // The first deploy has to create the branch
+        let settings = GitDeploy { repo: remote.path().to_string_lossy().to_string(), message: "Deploy {date}".to_string(), ..GitDeploy::default() };
+        settings.deploy(build.path()).unwrap();
+        assert_eq!(git_output(remote.path(), &["show", "gh-pages:index.html"]), "<p>One</p>");
+        assert_eq!(git_output(remote.path(), &["show", "gh-pages:dir/page.html"]), "<p>Page</p>");
+
+        // The next one builds on it, and files that went away are removed
+        fs::write(build.path().join("index.html"), "<p>Two</p>").unwrap();
+        fs::remove_file(build.path().join("dir/page.html")).unwrap();
+        settings.deploy(build.path()).unwrap();
+        assert_eq!(git_output(remote.path(), &["show", "gh-pages:index.html"]), "<p>Two</p>");
+        assert_eq!(git_output(remote.path(), &["ls-tree", "-r", "--name-only", "gh-pages"]), "index.html\n");
+        let log = git_output(remote.path(), &["log", "--format=%s", "gh-pages"]);
+        assert_eq!(log.lines().count(), 2);
+        assert!(log.lines().all(|line| line.starts_with("Deploy 2")));
+
+        // Deploying the same thing again doesn't make an empty commit
+        assert!(settings.deploy(build.path()).unwrap().contains("Nothing changed"));
+        assert_eq!(git_output(remote.path(), &["log", "--format=%s", "gh-pages"]).lines().count(), 2);
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::LazyLock;
+use syntect::html::{line_tokens_to_classed_spans, ClassStyle};
+use syntect::parsing::{ParseState, ScopeStack, ScopeStackOp, SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+/// Loading these is slow, so it's only done once, the first time a site wants highlighting
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+
+/// Highlight every fenced code block that names a language we know, by wrapping its tokens in
+/// spans classed by scope (`keyword`, `string` and so on) for the site's css to color. Blocks
+/// in other languages, or none, are left as they were.
+pub fn highlight_code_blocks(html: &str) -> String {
+    let mut result = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("<pre><code class=\"language-") {
+        let lang_start = start + "<pre><code class=\"language-".len();
+        let Some(open_end) = rest[lang_start..].find("\">").map(|n| lang_start + n + 2) else { break };
+        let Some(close) = rest[open_end..].find("</code></pre>").map(|n| open_end + n) else { break };
+        result.push_str(&rest[..open_end]);
+
+        let code = &rest[open_end..close];
+        let highlighted = SYNTAXES.find_syntax_by_token(&rest[lang_start..open_end - 2])
+            .and_then(|syntax| highlight(&unescape(code), syntax));
+        result.push_str(highlighted.as_deref().unwrap_or(code));
+        rest = &rest[close..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Each line closes whatever spans it opened, and reopens the ones still going from the line
+/// before, so lines can be split apart (to number them) without breaking the html
+fn highlight(code: &str, syntax: &SyntaxReference) -> Option<String> {
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut result = String::new();
+    for line in LinesWithEndings::from(code) {
+        let reopen: Vec<(usize, ScopeStackOp)> = stack.as_slice().iter().map(|scope| (0, ScopeStackOp::Push(*scope))).collect();
+        let (reopened, _) = line_tokens_to_classed_spans("", &reopen, ClassStyle::Spaced, &mut ScopeStack::new()).ok()?;
+        let ops = state.parse_line(line, &SYNTAXES).ok()?;
+        let (spans, _) = line_tokens_to_classed_spans(line, &ops, ClassStyle::Spaced, &mut stack).ok()?;
+        result.push_str(&reopened);
+        result.push_str(&spans.replace('\n', ""));
+        result.push_str(&"</span>".repeat(stack.len()));
+        if line.ends_with('\n') { result.push('\n') }
+    }
+    Some(result)
+}
+
+// Code comes to us already escaped by the markdown renderer, and syntect escapes it again
+fn unescape(code: &str) -> String {
+    code.replace("&quot;", "\"").replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_code_blocks() {
+        let html = "<p>Hi</p>\n<pre><code class=\"language-rust\">/* a &amp;\nb */ let x = &quot;&lt;&quot;;\n</code></pre>\n<pre><code class=\"language-nonsense\">x &lt; y\n</code></pre>\n<pre><code>plain\n</code></pre>";
+        let highlighted = highlight_code_blocks(html);
+        let (rust, rest) = highlighted.split_once("</code></pre>").unwrap();
+        assert!(rust.starts_with("<p>Hi</p>\n<pre><code class=\"language-rust\"><span class=\"source rust\">"));
+        // The comment's spans are closed at the end of its first line and reopened on the next
+        let lines: Vec<&str> = rust.split('\n').skip(1).collect();
+        assert!(lines[0].contains("<span class=\"comment block rust\">") && lines[0].contains("a &amp;"));
+        assert_eq!(lines[0].matches("<span").count(), lines[0].matches("</span>").count());
+        assert!(lines[1].starts_with("<span class=\"source rust\"><span class=\"comment block rust\">b"));
+        assert!(lines[1].contains("&lt;"));
+        assert_eq!(lines[1].matches("<span").count(), lines[1].matches("</span>").count());
+
+        // Unknown languages and unlabeled blocks are left alone
+        assert_eq!(rest, "\n<pre><code class=\"language-nonsense\">x &lt; y\n</code></pre>\n<pre><code>plain\n</code></pre>");
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+use crate::config::SiteConfig;
+
+/// Decides when to auto-deploy: some time after the site stops changing. Every change restarts
+/// the countdown, so we never deploy while someone is still saving files.
+pub struct IdleDeploy {
+    delay: Duration,
+    /// The site's fingerprint as of the last check, so we can tell when it changes
+    seen: Option<SystemTime>,
+    /// When the site last changed, if there's a deploy waiting to happen
+    changed_at: Option<Instant>
+}
+
+impl IdleDeploy {
+    pub fn new(delay: Duration) -> Self {
+        Self { delay, seen: None, changed_at: None }
+    }
+
+    /// Feed in the site's current fingerprint. The first one we see is just the starting point;
+    /// anything different after that (re)starts the countdown.
+    pub fn observe(&mut self, fingerprint: Option<SystemTime>, now: Instant) {
+        match self.seen {
+            None => self.seen = fingerprint,
+            Some(seen) if fingerprint != Some(seen) => {
+                self.seen = fingerprint;
+                self.changed_at = Some(now)
+            }
+            Some(_) => {}
+        }
+    }
+
+    /// How long until the deploy fires, if one is waiting
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.changed_at.map(|changed| self.delay.saturating_sub(now.duration_since(changed)))
+    }
+
+    /// Whether it's time to deploy. This only says yes once per batch of changes.
+    pub fn due(&mut self, now: Instant) -> bool {
+        if self.remaining(now).is_some_and(|r| r.is_zero()) {
+            self.changed_at = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Forget about the waiting deploy. Later changes will start a new countdown.
+    pub fn cancel(&mut self) {
+        self.changed_at = None
+    }
+}
+
+/// The newest modification time of anything in the site, which changes whenever a file is
+/// saved, added or removed. Fleen's own `_fleen` dir and a nested output dir don't count, or
+/// every build would look like a change, and neither do deploy scripts, which aren't part of
+/// the site.
+pub fn site_fingerprint(root: &Path, config: &SiteConfig) -> Option<SystemTime> {
+    fn visit(dir: &Path, root: &Path, config: &SiteConfig, latest: &mut Option<SystemTime>) {
+        let Ok(entries) = dir.read_dir() else { return };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_name() == "_scripts" || entry.file_name().to_string_lossy().starts_with('.') { continue }
+            if path.strip_prefix(root).is_ok_and(|source| config.excludes(root, source)) { continue }
+            let Ok(metadata) = entry.metadata() else { continue };
+            if let Ok(modified) = metadata.modified() {
+                *latest = (*latest).max(Some(modified))
+            }
+            if metadata.is_dir() { visit(&path, root, config, latest) }
+        }
+    }
+    let mut latest = root.metadata().and_then(|m| m.modified()).ok();
+    visit(root, root, config, &mut latest);
+    latest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule_and_cancel() {
+        let start = Instant::now();
+        let secs = |n| start + Duration::from_secs(n);
+        let v1 = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let v2 = v1 + Duration::from_secs(1);
+        let v3 = v2 + Duration::from_secs(1);
+
+        let mut idle = IdleDeploy::new(Duration::from_secs(10));
+        idle.observe(Some(v1), start);
+        assert_eq!(idle.remaining(start), None); // The starting state isn't a change
+
+        idle.observe(Some(v2), secs(1));
+        assert_eq!(idle.remaining(secs(5)), Some(Duration::from_secs(6)));
+
+        // Another save restarts the countdown
+        idle.observe(Some(v3), secs(8));
+        assert!(!idle.due(secs(11)));
+        assert!(idle.due(secs(18)));
+        assert!(!idle.due(secs(19))); // Only once
+
+        // Cancelling drops the deploy, but a new change schedules another
+        idle.observe(Some(v1), secs(20));
+        idle.cancel();
+        assert!(!idle.due(secs(40)));
+        idle.observe(Some(v2), secs(41));
+        assert!(idle.due(secs(51)));
+    }
+
+    #[test]
+    fn test_fingerprint_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.md"), "hi").unwrap();
+        let before = site_fingerprint(dir.path(), &SiteConfig::default());
+        assert!(before.is_some());
+
+        let later = SystemTime::now() + Duration::from_secs(60);
+        std::fs::File::options().write(true).open(dir.path().join("index.md")).unwrap().set_modified(later).unwrap();
+        assert_eq!(site_fingerprint(dir.path(), &SiteConfig::default()), Some(later));
+    }
+}
@@ -0,0 +1,150 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use crate::fleen_app::FleenError;
+use crate::utils::slugify;
+
+/// One item from a feed, turned into something we can write out as a markdown page
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedPost {
+    pub title: Option<String>,
+    pub date: Option<DateTime<Utc>>,
+    /// Where the item lived on the old site
+    pub link: Option<String>,
+    /// The item's content, converted to markdown
+    pub body: String
+}
+
+impl ImportedPost {
+    /// A file name for the post, like `2024-05-01-hello-world.md`
+    pub fn file_name(&self) -> String {
+        let slug = slugify(self.title.as_deref().unwrap_or_default());
+        let slug = if slug.is_empty() { "post".to_string() } else { slug };
+        match self.date {
+            Some(date) => format!("{}-{}.md", date.format("%Y-%m-%d"), slug),
+            None => format!("{}.md", slug)
+        }
+    }
+
+    /// The whole page: frontmatter with whatever the feed told us, then the body
+    pub fn to_markdown(&self) -> String {
+        // Formatting through toml::Value takes care of quoting and escaping
+        let field = |key: &str, value: String| format!("{} = {}\n", key, toml::Value::from(value));
+        let mut frontmatter = field("title", self.title.clone().unwrap_or("Untitled".to_string()));
+        if let Some(date) = self.date {
+            frontmatter += &field("date", date.format("%Y-%m-%d").to_string());
+        }
+        if let Some(link) = &self.link {
+            frontmatter += &field("original_link", link.clone());
+        }
+        format!("+++\n{}+++\n{}\n", frontmatter, self.body.trim_end())
+    }
+}
+
+/// Parse an RSS, Atom or JSON feed. Items missing a title, date or content still come
+/// through; they just lack that part.
+pub fn parse_feed(bytes: &[u8]) -> Result<Vec<ImportedPost>, FleenError> {
+    let feed = feed_rs::parser::parse(bytes).map_err(|e| FleenError::Import(e.to_string()))?;
+    Ok(feed.entries.into_iter().map(|entry| {
+        let html = entry.content.and_then(|c| c.body)
+            .or(entry.summary.map(|s| s.content))
+            .unwrap_or_default();
+        ImportedPost {
+            title: entry.title.map(|t| t.content.trim().to_string()).filter(|t| !t.is_empty()),
+            date: entry.published.or(entry.updated),
+            link: entry.links.first().map(|l| l.href.clone()),
+            body: htmd::convert(&html).unwrap_or(html)
+        }
+    }).collect())
+}
+
+/// Read a feed from a url or a local file
+pub async fn fetch_feed(source: &str) -> Result<Vec<u8>, FleenError> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let response = reqwest::get(source).await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| FleenError::Import(e.to_string()))?;
+        Ok(response.bytes().await.map_err(|e| FleenError::Import(e.to_string()))?.to_vec())
+    } else {
+        fs::read(source).map_err(|e| FleenError::FileIo(source.to_string(), e.to_string()))
+    }
+}
+
+/// Write each post into dir, never overwriting anything. Returns the paths written.
+pub fn write_posts(posts: &[ImportedPost], dir: &Path) -> Result<Vec<PathBuf>, FleenError> {
+    fs::create_dir_all(dir).map_err(|e| FleenError::FileCreate(dir.to_path_buf(), e.to_string()))?;
+    let mut written = vec![];
+    for post in posts {
+        let name = post.file_name();
+        let mut path = dir.join(&name);
+        let mut n = 2;
+        while path.exists() {
+            path = dir.join(format!("{}-{}.md", name.trim_end_matches(".md"), n));
+            n += 1;
+        }
+        fs::write(&path, post.to_markdown()).map_err(|e| FleenError::FileCreate(path.clone(), e.to_string()))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSS: &str = r#"<?xml version="1.0"?>
+<rss version="2.0"><channel>
+  <title>Old blog</title>
+  <item>
+    <title>Hello, World!</title>
+    <link>https://old.example.com/hello</link>
+    <pubDate>Wed, 01 May 2024 10:00:00 GMT</pubDate>
+    <description>&lt;p&gt;My &lt;em&gt;first&lt;/em&gt; post&lt;/p&gt;</description>
+  </item>
+  <item>
+    <title>Second thoughts</title>
+    <pubDate>Thu, 02 May 2024 10:00:00 GMT</pubDate>
+  </item>
+  <item>
+    <description>No title or date here</description>
+  </item>
+</channel></rss>"#;
+
+    #[test]
+    fn test_parse_feed() {
+        let posts = parse_feed(RSS.as_bytes()).unwrap();
+        assert_eq!(posts.len(), 3);
+        assert_eq!(posts.iter().map(|p| p.title.as_deref()).collect::<Vec<_>>(), vec![Some("Hello, World!"), Some("Second thoughts"), None]);
+        assert_eq!(posts.iter().map(|p| p.date.map(|d| d.format("%Y-%m-%d").to_string())).collect::<Vec<_>>(),
+                   vec![Some("2024-05-01".to_string()), Some("2024-05-02".to_string()), None]);
+
+        assert_eq!(posts[0].body, "My *first* post");
+        assert_eq!(posts[0].file_name(), "2024-05-01-hello-world.md");
+        assert_eq!(posts[2].file_name(), "post.md");
+        assert_eq!(posts[0].to_markdown(),
+                   "+++\ntitle = \"Hello, World!\"\ndate = \"2024-05-01\"\noriginal_link = \"https://old.example.com/hello\"\n+++\nMy *first* post\n");
+    }
+
+    #[test]
+    fn test_parse_json_feed() {
+        let json = r#"{"version": "https://jsonfeed.org/version/1.1", "title": "Old blog", "items": [
+            {"id": "1", "title": "From JSON", "date_published": "2023-12-25T08:00:00Z", "content_html": "<h2>Hi</h2>"}
+        ]}"#;
+        let posts = parse_feed(json.as_bytes()).unwrap();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].title.as_deref(), Some("From JSON"));
+        assert_eq!(posts[0].file_name(), "2023-12-25-from-json.md");
+        assert_eq!(posts[0].body, "## Hi");
+    }
+
+    #[test]
+    fn test_write_posts() {
+        let dir = tempfile::tempdir().unwrap();
+        let posts = parse_feed(RSS.as_bytes()).unwrap();
+        write_posts(&posts, dir.path()).unwrap();
+        // Importing again doesn't clobber anything
+        let again = write_posts(&posts[0..1], dir.path()).unwrap();
+        assert_eq!(again, vec![dir.path().join("2024-05-01-hello-world-2.md")]);
+        assert!(dir.path().join("post.md").is_file());
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use crate::fleen_app::FleenError;
+
+/// How a built file compares to what's on the live site
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LiveStatus {
+    Unchanged,
+    Changed,
+    /// The live site doesn't have it at all
+    New
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LiveDiff {
+    pub path: PathBuf,
+    pub status: LiveStatus
+}
+
+/// Fetch the live copy of every built file and see which ones a deploy would change. We can
+/// only ask about files we know of, so anything the live site has that we don't never shows up.
+pub async fn compare_with_live(files: &BTreeMap<PathBuf, Vec<u8>>, base_url: &str) -> Result<Vec<LiveDiff>, FleenError> {
+    let client = reqwest::Client::new();
+    let base = base_url.trim_end_matches('/');
+    let mut diffs = vec![];
+    for (path, contents) in files {
+        let url = format!("{}/{}", base, path.to_string_lossy().replace('\\', "/"));
+        let response = client.get(&url).send().await.map_err(|e| FleenError::LiveSite(url.clone(), e.to_string()))?;
+        let status = if response.status() == reqwest::StatusCode::NOT_FOUND {
+            LiveStatus::New
+        } else {
+            let response = response.error_for_status().map_err(|e| FleenError::LiveSite(url.clone(), e.to_string()))?;
+            let live = response.bytes().await.map_err(|e| FleenError::LiveSite(url.clone(), e.to_string()))?;
+            if live.as_ref() == contents.as_slice() { LiveStatus::Unchanged } else { LiveStatus::Changed }
+        };
+        diffs.push(LiveDiff { path: path.clone(), status });
+    }
+    Ok(diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::routing::get;
+
+    #[tokio::test]
+    async fn test_compare_with_live() {
+        let app = Router::new()
+            .route("/index.html", get(|| async { "<p>Same</p>" }))
+            .route("/posts/edited.html", get(|| async { "<p>Before</p>" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let files = BTreeMap::from([
+            (PathBuf::from("index.html"), b"<p>Same</p>".to_vec()),
+            (PathBuf::from("posts/edited.html"), b"<p>After</p>".to_vec()),
+            (PathBuf::from("posts/brand_new.html"), b"<p>Hi</p>".to_vec())
+        ]);
+        let diffs = compare_with_live(&files, &base_url).await.unwrap();
+        assert_eq!(diffs, vec![
+            LiveDiff { path: PathBuf::from("index.html"), status: LiveStatus::Unchanged },
+            LiveDiff { path: PathBuf::from("posts/brand_new.html"), status: LiveStatus::New },
+            LiveDiff { path: PathBuf::from("posts/edited.html"), status: LiveStatus::Changed }
+        ]);
+    }
+}
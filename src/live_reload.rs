@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use axum::body::Body;
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+use crate::config::SiteConfig;
+use crate::idle_deploy::{site_fingerprint, IdleDeploy};
+
+/// How often to look at the site for changes
+const SCAN_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the site has to sit still after a change before pages reload. Saving a page can be
+/// several writes in a row, and we only want to reload once, after the last.
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+/// Asks the server for the site's version every second, and reloads when it changes. Polling
+/// rather than holding a connection open means it never ties up one of the server's slots.
+const SCRIPT: &str = "<script>(function () {
+  var seen = null;
+  setInterval(function () {
+    fetch('/_fleen/reload').then(function (r) { return r.text() }).then(function (version) {
+      if (seen !== null && version !== seen) location.reload();
+      seen = version;
+    }).catch(function () {});
+  }, 1000);
+})();</script>";
+
+/// Where the live reload script asks for the site's version
+pub const RELOAD_PATH: &str = "/_fleen/reload";
+
+/// Add live reload to the server's routes: a `/_fleen/reload` route with the site's current
+/// version, and the script that watches it in every html page
+pub fn with_live_reload(router: Router, version: Arc<AtomicU64>) -> Router {
+    router
+        .route(RELOAD_PATH, get(move || async move { version.load(Ordering::Relaxed).to_string() }))
+        .layer(axum::middleware::map_response(add_script))
+}
+
+/// Count up the version whenever the site changes and then settles down. Runs forever.
+pub async fn watch_for_changes(root: PathBuf, version: Arc<AtomicU64>) {
+    let config = SiteConfig::load(&root).unwrap_or_default();
+    let mut changes = IdleDeploy::new(SETTLE_TIME);
+    loop {
+        let now = Instant::now();
+        changes.observe(site_fingerprint(&root, &config), now);
+        if changes.due(now) {
+            version.fetch_add(1, Ordering::Relaxed);
+        }
+        tokio::time::sleep(SCAN_INTERVAL).await
+    }
+}
+
+async fn add_script(response: Response) -> Response {
+    let is_html = response.headers().get(CONTENT_TYPE).is_some_and(|t| t.as_bytes().starts_with(b"text/html"));
+    if !is_html || !response.status().is_success() { return response }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty())
+    };
+    let html = match String::from_utf8(bytes.to_vec()) {
+        Ok(html) => inject_script(&html),
+        Err(_) => return Response::from_parts(parts, Body::from(bytes))
+    };
+    // The length changed, and axum will work out the new one
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(html))
+}
+
+/// Put the reload script at the end of the body, or the end of the page if it hasn't got one
+fn inject_script(html: &str) -> String {
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(end) => format!("{}{}{}", &html[..end], SCRIPT, &html[end..]),
+        None => format!("{}{}", html, SCRIPT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::SystemTime;
+    use axum::http::Request;
+    use tower::ServiceExt;
+    use crate::server::routes;
+
+    #[test]
+    fn test_inject_script() {
+        assert_eq!(inject_script("<html><body><p>Hi</p></BODY></html>"), format!("<html><body><p>Hi</p>{}</BODY></html>", SCRIPT));
+        assert_eq!(inject_script("<p>Hi</p>"), format!("<p>Hi</p>{}", SCRIPT));
+        // İ gets longer when lowercased, which mustn't move where the script goes
+        assert_eq!(inject_script("<body>İİİ</body>"), format!("<body>İİİ{}</body>", SCRIPT));
+    }
+
+    #[tokio::test]
+    async fn test_live_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.md"), "# Home").unwrap();
+        fs::write(dir.path().join("style.css"), "body {}").unwrap();
+        fs::create_dir(dir.path().join("_scripts")).unwrap();
+        fs::write(dir.path().join("_scripts/deploy.sh"), "").unwrap();
+        let version = Arc::new(AtomicU64::new(0));
+        let app = with_live_reload(routes(dir.path().to_path_buf()), version.clone());
+        let get = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap();
+                String::from_utf8(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+            }
+        };
+        // Pages get the script, and nothing else does
+        assert!(get("/").await.contains(SCRIPT));
+        assert_eq!(get("/style.css").await, "body {}");
+        assert_eq!(get("/_fleen/reload").await, "0");
+
+        let watcher = tokio::spawn(watch_for_changes(dir.path().to_path_buf(), version.clone()));
+        let touch = |name: &str, secs: u64| {
+            let time = SystemTime::now() + Duration::from_secs(secs);
+            fs::File::options().write(true).open(dir.path().join(name)).unwrap().set_modified(time).unwrap();
+        };
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // A burst of saves is one reload
+        touch("index.md", 60);
+        touch("index.md", 61);
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(get("/_fleen/reload").await, "1");
+
+        // Deploy scripts aren't the site
+        touch("_scripts/deploy.sh", 120);
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(version.load(Ordering::Relaxed), 1);
+        watcher.abort();
+    }
+}
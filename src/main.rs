@@ -1,31 +1,104 @@
+mod build_info;
+mod build_log;
+mod clipboard;
+mod config;
+mod console;
+mod feed;
 mod fleen_app;
+mod highlight;
+mod git_deploy;
+mod idle_deploy;
+mod import;
+mod live_diff;
+mod live_reload;
+mod notifications;
+mod output;
+mod preferences;
+mod preview;
+mod recent_sites;
 mod renderer;
 mod server;
 mod ui_ext;
 mod utils;
 mod site_ui;
+mod site_state;
+mod sitemap;
+mod watch_build;
 
+use std::path::Path;
+use std::time::Instant;
 use eframe::egui::{Button, Context, RichText};
 use eframe::{egui, Frame};
 use site_ui::SiteUi;
-use crate::fleen_app::{FleenError, Site};
+use crate::build_log::BuildLogEntry;
+use crate::fleen_app::{FleenError, Severity, Site, SiteActions};
+use crate::preferences::Preferences;
+use crate::recent_sites::RecentSites;
 use crate::ui_ext::{ButtonExtensions, UiExtensions};
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(headless(&args));
+    }
+
     let mut native_options = eframe::NativeOptions::default();
     native_options.viewport = native_options.viewport.with_icon(
         eframe::icon_data::from_png_bytes(include_bytes!("../icon/128x128@2x.png"))
             .expect("Failed to load icon")
     );
-    eframe::run_native("Fleen", native_options, Box::new(|_cc| {
-        Ok(Box::new(FleenUi(None, None)))
+    eframe::run_native("Fleen", native_options, Box::new(|cc| {
+        Preferences::path().map(|path| Preferences::load(&path)).unwrap_or_default().apply(&cc.egui_ctx);
+        let recent = RecentSites::path().map(|path| RecentSites::load(&path)).unwrap_or_default();
+        Ok(Box::new(FleenUi(None, None, recent)))
     })).expect("Error running application");
 }
 
-struct FleenUi(Option<SiteUi>, Option<FleenError>);
+/// Run without a GUI, for scripts and CI. Returns the exit code. Flags can go anywhere, so
+/// `fleen site --build out` is the same as `fleen --build site out`.
+fn headless(args: &[String]) -> i32 {
+    let strict = args.iter().any(|a| a == "--strict");
+    let mode = args.iter().map(String::as_str).find(|a| matches!(*a, "--build" | "--check"));
+    let positional: Vec<&String> = args.iter().filter(|a| !a.starts_with("--")).collect();
+    match (mode, positional.as_slice()) {
+        (Some("--build"), [site, target]) => {
+            let site = match Site::open(Path::new(site)) {
+                Ok(site) => site,
+                Err(err) => { eprintln!("{}", err); return 1 }
+            };
+            let started = Instant::now();
+            let result = (&site).build_to_dir(Path::new(target), strict || site.config.strict);
+            let _ = build_log::append(&site.root, &BuildLogEntry::new("build (command line)", started, &result));
+            match result {
+                Ok(warnings) => {
+                    for warning in warnings { eprintln!("{}", warning) }
+                    0
+                }
+                Err(err) => { eprintln!("{}", err); 1 }
+            }
+        }
+        (Some("--check"), [site]) => {
+            let site = match Site::open(Path::new(site)) {
+                Ok(site) => site,
+                Err(err) => { eprintln!("{}", err); return 1 }
+            };
+            // Same rule as a build: errors always fail, warnings only when strict
+            let diagnostics = (&site).validate();
+            for diagnostic in &diagnostics { eprintln!("{}", diagnostic) }
+            let strict = strict || site.config.strict;
+            if diagnostics.iter().any(|d| d.severity == Severity::Error || strict) { 1 } else { 0 }
+        }
+        _ => {
+            eprintln!("Usage: fleen [--build <site dir> <target dir> | --check <site dir>] [--strict]");
+            2
+        }
+    }
+}
+
+struct FleenUi(Option<SiteUi>, Option<FleenError>, RecentSites);
 
-fn site_chooser(ctx: &Context, error: &Option<FleenError>) -> Result<Option<Site>, FleenError> {
+fn site_chooser(ctx: &Context, error: &Option<FleenError>, recent: &RecentSites) -> Result<Option<Site>, FleenError> {
     if let Some(err) = error {
         let message = format!("{}", err);
         egui::Window::new("Error").collapsible(false).resizable(false).show(ctx, |ui| {
@@ -51,6 +124,19 @@ fn site_chooser(ctx: &Context, error: &Option<FleenError>) -> Result<Option<Site
             }
         }
 
+        if !recent.sites.is_empty() {
+            ui.separator();
+            ui.label("Recent sites");
+        }
+        for path in &recent.sites {
+            // Sites that have gone away stay on the list, in case they're on a drive that isn't plugged in
+            let button = ui.add_enabled(path.is_dir(), Button::new(path.to_string_lossy()))
+                .on_disabled_hover_text("This site isn't there anymore");
+            if button.clicked() {
+                return Site::open(path).map(Some)
+            }
+        }
+
         Ok(None)
     }).inner
 }
@@ -59,8 +145,14 @@ impl eframe::App for FleenUi {
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
         match &mut self.0 {
             None => {
-                match site_chooser(ctx, &self.1) {
-                    Ok(Some(site)) => { self.0 = Some(SiteUi::from(site))}
+                match site_chooser(ctx, &self.1, &self.2) {
+                    Ok(Some(site)) => {
+                        utils::set_safe_mode(site.config.safe_mode);
+                        // Not being able to remember the site isn't worth bothering anyone about
+                        self.2.add(&site.root);
+                        if let Some(path) = RecentSites::path() { let _ = self.2.save(&path); }
+                        self.0 = Some(SiteUi::from(site))
+                    }
                     Err(e) => { self.1 = Some(e) }
                     _ => {}
                 }
@@ -69,3 +161,47 @@ impl eframe::App for FleenUi {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(files: &[(&str, &str)], strict: bool) -> i32 {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, contents) in files {
+            std::fs::write(dir.path().join(name), contents).unwrap();
+        }
+        let mut args = vec!["--check".to_string(), dir.path().to_string_lossy().to_string()];
+        if strict { args.push("--strict".to_string()) }
+        headless(&args)
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check(&[("index.md", "[About](about.html)"), ("about.md", "Hi")], false), 0);
+        assert_eq!(check(&[("index.md", "+++\nlayout = \"_layouts/missing.html\"\n+++\nHi")], false), 1);
+
+        // A broken link is only a warning, so it fails strict checks
+        let broken = [("index.md", "[About](about.html)"), ("_config.toml", "strict = true")];
+        assert_eq!(check(&broken, false), 1);
+        assert_eq!(check(&broken[0..1], false), 0);
+        assert_eq!(check(&broken[0..1], true), 1);
+    }
+
+    #[test]
+    fn test_flags_anywhere() {
+        let (site, target) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        std::fs::write(site.path().join("index.md"), "Hi").unwrap();
+        let (site, target) = (site.path().to_string_lossy().to_string(), target.path().to_path_buf());
+        let target_arg = target.to_string_lossy().to_string();
+
+        assert_eq!(headless(&[site.clone(), "--build".to_string(), target_arg.clone(), "--strict".to_string()]), 0);
+        assert!(target.join("index.html").exists());
+        // Builds from the command line are logged like any other
+        let log = build_log::recent(Path::new(&site), 10);
+        assert_eq!(log.len(), 1);
+        assert!(log[0].contains("  build (command line)  ") && log[0].ends_with("  ok"));
+        assert_eq!(headless(&[site.clone(), "--check".to_string()]), 0);
+        assert_eq!(headless(&[site, "--strict".to_string()]), 2);
+    }
+}
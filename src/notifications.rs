@@ -0,0 +1,102 @@
+use std::time::{Duration, Instant};
+use eframe::egui;
+use eframe::egui::{Color32, Context};
+
+/// How much a notification matters, which decides its color
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToastKind {
+    Info,
+    Warning,
+    Error
+}
+
+struct Toast {
+    message: String,
+    kind: ToastKind,
+    expires: Instant
+}
+
+/// Short messages that show up in a corner for a few seconds and then go away on their own, for
+/// things worth mentioning but not worth a dialog
+#[derive(Default)]
+pub struct Notifications {
+    toasts: Vec<Toast>
+}
+
+impl Notifications {
+    /// How long a toast sticks around unless told otherwise
+    const DURATION: Duration = Duration::from_secs(3);
+
+    pub fn push(&mut self, message: impl Into<String>, kind: ToastKind, duration: Duration, now: Instant) {
+        self.toasts.push(Toast { message: message.into(), kind, expires: now + duration })
+    }
+
+    pub fn info(&mut self, message: impl Into<String>) {
+        self.push(message, ToastKind::Info, Self::DURATION, Instant::now())
+    }
+
+    pub fn warning(&mut self, message: impl Into<String>) {
+        self.push(message, ToastKind::Warning, Self::DURATION * 2, Instant::now())
+    }
+
+    pub fn error(&mut self, message: impl Into<String>) {
+        self.push(message, ToastKind::Error, Self::DURATION * 2, Instant::now())
+    }
+
+    /// Drop any toasts whose time is up
+    pub fn expire(&mut self, now: Instant) {
+        self.toasts.retain(|t| t.expires > now)
+    }
+
+    /// The toasts still showing, oldest first
+    pub fn current(&self) -> impl Iterator<Item = (&str, ToastKind)> {
+        self.toasts.iter().map(|t| (t.message.as_str(), t.kind))
+    }
+
+    /// Draw the toasts stacked in the bottom right corner, and make sure we wake up to hide them
+    pub fn show(&mut self, ctx: &Context) {
+        let now = Instant::now();
+        self.expire(now);
+        let Some(next) = self.toasts.iter().map(|t| t.expires).min() else { return };
+        ctx.request_repaint_after(next - now);
+
+        egui::Area::new(egui::Id::new("notifications"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-10.0, -10.0])
+            .order(egui::Order::Foreground)
+            .interactable(false)
+            .show(ctx, |ui| {
+                for (message, kind) in self.current() {
+                    let color = match kind {
+                        ToastKind::Info => Color32::DARK_GREEN,
+                        ToastKind::Warning => Color32::from_rgb(160, 110, 0),
+                        ToastKind::Error => Color32::DARK_RED
+                    };
+                    egui::Frame::popup(ui.style()).fill(color).show(ui, |ui| {
+                        ui.colored_label(Color32::WHITE, message)
+                    });
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_expire() {
+        let start = Instant::now();
+        let secs = |n| start + Duration::from_secs(n);
+        let mut notifications = Notifications::default();
+        notifications.push("Built", ToastKind::Info, Duration::from_secs(3), start);
+        notifications.push("Image is small", ToastKind::Warning, Duration::from_secs(6), secs(1));
+        assert_eq!(notifications.current().collect::<Vec<_>>(), vec![("Built", ToastKind::Info), ("Image is small", ToastKind::Warning)]);
+
+        notifications.expire(secs(2));
+        assert_eq!(notifications.current().count(), 2);
+        notifications.expire(secs(3));
+        assert_eq!(notifications.current().collect::<Vec<_>>(), vec![("Image is small", ToastKind::Warning)]);
+        notifications.expire(secs(7));
+        assert_eq!(notifications.current().count(), 0);
+    }
+}
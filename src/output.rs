@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Somewhere a built site goes. Paths are all relative to the root of the output. There's no
+/// way to make a directory by itself: they appear when something's put in them, so a build
+/// never has empty ones.
+pub trait OutputSink {
+    /// Empty the output, except for top-level entries with these names
+    fn clear(&mut self, preserve: &[String]) -> io::Result<()>;
+    /// Write a file, making any directories it needs
+    fn write(&mut self, path: &Path, contents: &[u8]) -> io::Result<()>;
+    /// Copy a file from the filesystem (an absolute path, or relative to the cwd) into the output
+    fn copy(&mut self, source: &Path, path: &Path) -> io::Result<()>;
+}
+
+/// Build into a directory on disk
+pub struct FsSink {
+    target: PathBuf,
+    /// How many more times to try an operation that failed because a file was briefly locked
+    retries: u32
+}
+
+impl FsSink {
+    pub fn new(target: &Path) -> Self {
+        Self { target: target.to_path_buf(), retries: 3 }
+    }
+
+    pub fn with_retries(self, retries: u32) -> Self {
+        Self { retries, ..self }
+    }
+}
+
+/// The longest we'll wait between tries, however many retries are configured
+const MAX_RETRY_DELAY_MS: u64 = 2000;
+
+/// How long to wait before a retry: doubling each time from 100ms, up to `MAX_RETRY_DELAY_MS`
+fn retry_delay(attempt: u32) -> std::time::Duration {
+    std::time::Duration::from_millis((50u64 << attempt.min(6)).min(MAX_RETRY_DELAY_MS))
+}
+
+/// Run a filesystem operation, trying again (after a short and growing wait) while it fails
+/// with an error `transient` says might go away by itself
+fn retry<T>(retries: u32, transient: fn(&io::Error) -> bool, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < retries && transient(&e) => {
+                attempt += 1;
+                std::thread::sleep(retry_delay(attempt))
+            }
+            result => return result
+        }
+    }
+}
+
+/// On Windows, virus scanners and the search indexer hold files open for a moment after we
+/// touch them, which makes deleting or rewriting them fail. Elsewhere nothing's transient.
+fn is_transient(error: &io::Error) -> bool {
+    // ERROR_ACCESS_DENIED, ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION
+    cfg!(windows) && matches!(error.raw_os_error(), Some(5 | 32 | 33))
+}
+
+impl FsSink {
+    /// Make sure the directory a file goes in exists, and return where the file goes
+    fn make_parents(&self, path: &Path) -> io::Result<PathBuf> {
+        let target = self.target.join(path);
+        if let Some(parent) = target.parent() { fs::create_dir_all(parent)? }
+        Ok(target)
+    }
+}
+
+impl OutputSink for FsSink {
+    fn clear(&mut self, preserve: &[String]) -> io::Result<()> {
+        for entry in fs::read_dir(&self.target)? {
+            let entry = entry?;
+            if preserve.iter().any(|p| entry.file_name() == p.as_str()) {
+                continue
+            } else if entry.file_type()?.is_dir() {
+                retry(self.retries, is_transient, || fs::remove_dir_all(entry.path()))?
+            } else {
+                retry(self.retries, is_transient, || fs::remove_file(entry.path()))?
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let target = self.make_parents(path)?;
+        retry(self.retries, is_transient, || fs::write(&target, contents))
+    }
+
+    fn copy(&mut self, source: &Path, path: &Path) -> io::Result<()> {
+        let target = self.make_parents(path)?;
+        retry(self.retries, is_transient, || fs::copy(source, &target))?;
+        Ok(())
+    }
+}
+
+/// Build into memory, for tests and for previewing a whole site without touching the disk
+#[derive(Default, Debug)]
+pub struct InMemorySink {
+    pub files: BTreeMap<PathBuf, Vec<u8>>,
+    pub dirs: BTreeSet<PathBuf>
+}
+
+#[cfg_attr(not(test), allow(dead_code))] // Only the tests read files back out as text, so far
+impl InMemorySink {
+    /// A file's contents as a string, if it exists and is utf-8
+    pub fn text(&self, path: impl AsRef<Path>) -> Option<&str> {
+        self.files.get(path.as_ref()).and_then(|c| std::str::from_utf8(c).ok())
+    }
+}
+
+impl OutputSink for InMemorySink {
+    fn clear(&mut self, preserve: &[String]) -> io::Result<()> {
+        let keep = |path: &Path| path.iter().next().is_some_and(|first| preserve.iter().any(|p| first == p.as_str()));
+        self.files.retain(|path, _| keep(path));
+        self.dirs.retain(|path| keep(path));
+        Ok(())
+    }
+
+    fn write(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let parents = path.ancestors().skip(1).filter(|p| !p.as_os_str().is_empty());
+        self.dirs.extend(parents.map(Path::to_path_buf));
+        self.files.insert(path.to_path_buf(), contents.to_vec());
+        Ok(())
+    }
+
+    fn copy(&mut self, source: &Path, path: &Path) -> io::Result<()> {
+        let contents = fs::read(source)?;
+        self.write(path, &contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry() {
+        let locked = |e: &io::Error| e.kind() == io::ErrorKind::ResourceBusy;
+        let mut attempts = 0;
+        let result = retry(3, locked, || {
+            attempts += 1;
+            if attempts < 3 { Err(io::Error::from(io::ErrorKind::ResourceBusy)) } else { Ok(attempts) }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Real errors aren't retried
+        let mut attempts = 0;
+        let result: io::Result<()> = retry(3, locked, || { attempts += 1; Err(io::Error::from(io::ErrorKind::NotFound)) });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(attempts, 1);
+
+        // And we give up eventually, with the error we got
+        let mut attempts = 0;
+        let result: io::Result<()> = retry(2, locked, || { attempts += 1; Err(io::Error::from(io::ErrorKind::ResourceBusy)) });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::ResourceBusy);
+        assert_eq!(attempts, 3);
+
+        // However long that takes, each wait is capped
+        assert_eq!(retry_delay(1).as_millis(), 100);
+        assert_eq!(retry_delay(5).as_millis(), 1600);
+        assert_eq!(retry_delay(6).as_millis(), 2000);
+        assert_eq!(retry_delay(100).as_millis(), 2000);
+    }
+}
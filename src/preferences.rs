@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+use eframe::egui::{Context, Theme};
+use serde::{Deserialize, Serialize};
+use crate::fleen_app::FleenError;
+use crate::utils;
+
+/// How Fleen itself is set up, rather than any one site, kept in `preferences.toml` in Fleen's
+/// config dir next to the recent sites
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Preferences {
+    /// Dark or light, or None to go along with the system
+    pub dark_mode: Option<bool>
+}
+
+impl Preferences {
+    /// Where the preferences live, if this platform has a config dir
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("fleen/preferences.toml"))
+    }
+
+    /// Load the preferences, or the defaults. See `utils::load_toml`.
+    pub fn load(path: &Path) -> Self {
+        utils::load_toml(path)
+    }
+
+    /// Save the preferences, so they're the same next launch
+    pub fn save(&self, path: &Path) -> Result<(), FleenError> {
+        utils::save_toml(self, path)
+    }
+
+    /// Switch egui to the chosen theme, if there is one and it isn't showing already
+    pub fn apply(&self, ctx: &Context) {
+        let Some(dark) = self.dark_mode else { return };
+        let theme = if dark { Theme::Dark } else { Theme::Light };
+        if ctx.theme() != theme { ctx.set_theme(theme) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preferences() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config/preferences.toml");
+        assert_eq!(Preferences::load(&path), Preferences::default());
+
+        let preferences = Preferences { dark_mode: Some(false) };
+        preferences.save(&path).unwrap();
+        assert_eq!(Preferences::load(&path), preferences);
+
+        let ctx = Context::default();
+        preferences.apply(&ctx);
+        assert_eq!(ctx.theme(), Theme::Light);
+        Preferences { dark_mode: Some(true) }.apply(&ctx);
+        assert_eq!(ctx.theme(), Theme::Dark);
+    }
+}
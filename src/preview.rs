@@ -0,0 +1,150 @@
+use std::path::Path;
+use crate::config::SiteConfig;
+use crate::renderer::{server_render, RenderError, RenderOutput};
+
+/// A piece of a rendered page, as the in-app preview shows it. egui can't show html, so pages
+/// are boiled down to their text, a block at a time.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Block {
+    Heading(u8, String),
+    Paragraph(String),
+    ListItem(String),
+    Code(String),
+    Rule
+}
+
+/// Tags whose contents never show up on the page
+const INVISIBLE: [&str; 4] = ["head", "script", "style", "template"];
+
+/// Tags that start or end a block of text
+const BLOCKS: [&str; 14] = ["p", "h1", "h2", "h3", "h4", "h5", "h6", "li", "pre", "div", "blockquote", "tr", "section", "article"];
+
+/// The blocks of text in some html, in order. Anything that isn't shown in a browser, like
+/// the head and scripts, is left out.
+pub fn html_blocks(html: &str) -> Vec<Block> {
+    let mut blocks = vec![];
+    let mut text = String::new();
+    let mut kind = "p".to_string();
+    let mut hidden: Option<String> = None;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            if hidden.is_none() { push_text(&mut text, rest, kind == "pre") }
+            break
+        };
+        if hidden.is_none() { push_text(&mut text, &rest[..start], kind == "pre") }
+        rest = &rest[start + 1..];
+        // Comments can have > in them, so they end at -->
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue
+        }
+        let end = rest.find('>').unwrap_or(rest.len());
+        let tag = &rest[..end];
+        rest = rest.get(end + 1..).unwrap_or_default();
+
+        let closing = tag.starts_with('/');
+        let name = tag.trim_start_matches('/').split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or_default().to_ascii_lowercase();
+        if let Some(invisible) = &hidden {
+            if closing && name == *invisible { hidden = None }
+            continue
+        }
+        match name.as_str() {
+            name if INVISIBLE.contains(&name) && !closing => hidden = Some(name.to_string()),
+            name if BLOCKS.contains(&name) => {
+                flush(&mut blocks, &mut text, &kind);
+                kind = if closing { "p".to_string() } else { name.to_string() };
+            }
+            "br" => text.push('\n'),
+            "hr" => {
+                flush(&mut blocks, &mut text, &kind);
+                blocks.push(Block::Rule)
+            }
+            "img" => if let Some(alt) = attribute(tag, "alt").filter(|alt| !alt.is_empty()) {
+                text.push_str(&format!("[{}]", unescape(alt)))
+            }
+            _ => {}
+        }
+    }
+    flush(&mut blocks, &mut text, &kind);
+    blocks
+}
+
+/// Render a markdown page (relative to the root) the way the dev server would, layout and all,
+/// and boil it down to blocks
+pub fn preview_page(source: &Path, root: &Path, config: &SiteConfig) -> Result<Vec<Block>, RenderError> {
+    // Pages are rendered by asking for the html they become
+    let page = match source.file_name() {
+        Some(name) if name == "_index.md" => source.with_file_name("index.html"),
+        _ => source.with_extension("html")
+    };
+    match server_render(page, root, config)? {
+        RenderOutput::Rendered(_, html) | RenderOutput::Hidden(_, html) | RenderOutput::Draft(_, html) => Ok(html_blocks(&html)),
+        _ => Ok(vec![])
+    }
+}
+
+/// Add some text from between tags. Outside of `<pre>`, runs of whitespace are just one space,
+/// like a browser shows them.
+fn push_text(text: &mut String, raw: &str, preformatted: bool) {
+    let raw = unescape(raw);
+    if preformatted {
+        text.push_str(&raw);
+        return
+    }
+    for (n, word) in raw.split(char::is_whitespace).enumerate() {
+        if n > 0 && !text.ends_with([' ', '\n']) && !text.is_empty() { text.push(' ') }
+        text.push_str(word);
+    }
+}
+
+fn flush(blocks: &mut Vec<Block>, text: &mut String, kind: &str) {
+    let content = std::mem::take(text);
+    if kind == "pre" {
+        let code = content.trim_end_matches('\n');
+        if !code.is_empty() { blocks.push(Block::Code(code.to_string())) }
+        return
+    }
+    let content = content.trim();
+    if content.is_empty() { return }
+    let content = content.to_string();
+    blocks.push(match kind.strip_prefix('h').and_then(|level| level.parse().ok()) {
+        Some(level) => Block::Heading(level, content),
+        None if kind == "li" => Block::ListItem(content),
+        None => Block::Paragraph(content)
+    })
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    tag[start..].split('"').next()
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&#39;", "'").replace("&nbsp;", " ").replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_blocks() {
+        let html = concat!(
+            "<!DOCTYPE html>\n<html><head><title>Hidden</title><style>p { color: red }</style></head>\n",
+            "<body><nav>Home</nav>\n<h1 id=\"hi\">Hello &amp; welcome</h1>\n<p>Some <em>nice</em>\n  text<br>and more</p>\n",
+            "<!-- <p>not this</p> -->\n<ul>\n<li>One</li>\n<li>Two <img src=\"a.png\" alt=\"cat\"></li>\n</ul>\n<hr />\n",
+            "<pre><code class=\"language-rust\">let x = 1 &lt; 2;\n  y();\n</code></pre>\n<script>alert(1)</script></body></html>"
+        );
+        assert_eq!(html_blocks(html), vec![
+            Block::Paragraph("Home".to_string()),
+            Block::Heading(1, "Hello & welcome".to_string()),
+            Block::Paragraph("Some nice text\nand more".to_string()),
+            Block::ListItem("One".to_string()),
+            Block::ListItem("Two [cat]".to_string()),
+            Block::Rule,
+            Block::Code("let x = 1 < 2;\n  y();".to_string()),
+        ]);
+    }
+}
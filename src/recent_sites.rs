@@ -0,0 +1,62 @@
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::fleen_app::FleenError;
+use crate::utils;
+
+/// How many sites we remember
+const MAX_RECENT: usize = 8;
+
+/// The sites opened lately, newest first, kept in `recent_sites.toml` in Fleen's config dir
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct RecentSites {
+    pub sites: Vec<PathBuf>
+}
+
+impl RecentSites {
+    /// Where the list lives, if this platform has a config dir
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("fleen/recent_sites.toml"))
+    }
+
+    /// Load the list, or an empty one. See `utils::load_toml`.
+    pub fn load(path: &Path) -> Self {
+        utils::load_toml(path)
+    }
+
+    /// Save the list, so the next launch can offer it
+    pub fn save(&self, path: &Path) -> Result<(), FleenError> {
+        utils::save_toml(self, path)
+    }
+
+    /// Put a site at the top of the list, dropping the oldest if there are too many
+    pub fn add(&mut self, root: &Path) {
+        let root = root.canonicalize().unwrap_or(root.to_path_buf());
+        self.sites.retain(|site| *site != root);
+        self.sites.insert(0, root);
+        self.sites.truncate(MAX_RECENT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_sites() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config/recent_sites.toml");
+        assert_eq!(RecentSites::load(&path), RecentSites::default());
+
+        let mut recent = RecentSites::default();
+        for n in 0..10 { recent.add(Path::new(&format!("/sites/{}", n))) }
+        recent.add(Path::new("/sites/5"));
+        assert_eq!(recent.sites.len(), 8);
+        assert_eq!(recent.sites[0], PathBuf::from("/sites/5"));
+        assert_eq!(recent.sites[1], PathBuf::from("/sites/9"));
+        assert!(!recent.sites.contains(&PathBuf::from("/sites/1")));
+
+        recent.save(&path).unwrap();
+        assert_eq!(RecentSites::load(&path), recent);
+    }
+}
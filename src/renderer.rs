@@ -1,11 +1,20 @@
 use std::{fs, io};
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::io::Error;
 use std::path::{Path, PathBuf};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
 use markdown::message::Message;
 use markdown::{Constructs, Options, ParseOptions};
 use markdown::mdast::Node;
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
 use thiserror::Error;
+use crate::config::{HiddenMode, SiteConfig};
+use crate::highlight::highlight_code_blocks;
+use crate::output::OutputSink;
+use crate::sitemap;
+use crate::utils::slugify;
 
 /// The things we might return from trying to render a file
 #[derive(Clone, PartialEq, Debug)]
@@ -14,54 +23,180 @@ pub enum RenderOutput {
     Rendered(PathBuf, String),
     /// Hidden output should be returned by the test server but not rendered to a file
     Hidden(PathBuf, String),
+    /// A draft is never rendered to a file, and the test server only returns it if drafts are on
+    Draft(PathBuf, String),
     /// The raw contents of the given (relative) path.
     RawFile(PathBuf),
+    /// Css compiled from a `.scss` or `.sass` file, and the `.css` path it goes to
+    Stylesheet(PathBuf, String),
     /// No contents; this file should not be output / test server should return 404
     NoOutput,
-    /// This is a directory; the test server won't return anything but we need to mkdir it
+    /// This is a directory; the test server won't return anything, and a build only makes it if
+    /// something gets put in it
     Dir(PathBuf)
 }
 
 impl RenderOutput {
-    pub fn file_operation(&self, root: &Path, target: &Path) -> Result<(), io::Error> {
+    pub fn file_operation(&self, root: &Path, sink: &mut dyn OutputSink) -> Result<(), io::Error> {
         match self {
-            RenderOutput::Rendered(path, contents) => {
-                fs::write(target.join(path), contents)
+            RenderOutput::Rendered(path, contents) | RenderOutput::Stylesheet(path, contents) => {
+                sink.write(path, contents.as_bytes())
             }
-            RenderOutput::Hidden(_, _) | RenderOutput::NoOutput => Ok(()), // Don't do anything!
+            // Don't do anything! Dirs get made by the sink as files land in them.
+            RenderOutput::Hidden(_, _) | RenderOutput::Draft(_, _) | RenderOutput::NoOutput | RenderOutput::Dir(_) => Ok(()),
             RenderOutput::RawFile(path) => {
-                fs::copy(root.join(path), target.join(path))?;
-                Ok(())
-            }
-            RenderOutput::Dir(path) => {
-                fs::create_dir(target.join(path))
+                sink.copy(&root.join(path), path)
             }
         }
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 pub struct Frontmatter {
     layout: Option<String>,
+    /// What kind of page this is, which picks its layout from the config's `[layouts]`
+    #[serde(rename = "type")]
+    page_type: Option<String>,
     title: Option<String>,
-    published: Option<bool>
+    /// When the page was written: an ISO-8601 date like `2024-05-01`, or a date and time.
+    /// Unquoted TOML dates work too.
+    #[serde(default, deserialize_with = "date_string")]
+    date: Option<String>,
+    published: Option<bool>,
+    /// Only meaningful for html files: whether to run the body through markdown
+    markdown: Option<bool>,
+    /// Where the page officially lives, if that's somewhere other than here
+    canonical: Option<String>,
+    /// Still being written: not built, and not even previewed unless drafts are turned on
+    draft: Option<bool>,
+    /// What the preview server says the page is, like `text/plain; charset=utf-8`. A charset
+    /// in here is also the one the page declares in its head.
+    content_type: Option<String>,
+    /// Only for `_index.md`: which pages beside it to list, as names or `*` patterns
+    pages: Option<Vec<String>>,
+    /// Where the page goes in listings: lower weights first, and weighted pages before the rest.
+    /// Can be negative.
+    #[serde(alias = "order")]
+    weight: Option<i64>,
+    /// Only for `_index.md`: the frontmatter key to order the list by. Without one, it's by
+    /// weight, then date, then file name.
+    sort_by: Option<String>,
+    /// Only for `_index.md`: list in descending order, like newest first
+    reverse: Option<bool>,
+    /// Set to false to leave the headings without ids, and `$toc` empty
+    toc: Option<bool>,
+    /// Not frontmatter: rendering fills this in with the list of headings, for `$toc`
+    #[serde(skip)]
+    toc_html: String,
+    /// Everything else, like `author` or `description`, for layouts to use as `$author` and so on
+    #[serde(flatten)]
+    extra: HashMap<String, toml::Value>
 }
 
 impl Frontmatter {
-    fn apply_layout(self, content: String, filename: PathBuf, root: &Path) -> Result<RenderOutput, RenderError> {
-        let title = self.title.unwrap_or_default();
-        let wrapped = if let Some(layout) = self.layout {
+    /// The layout the page uses: the one it names, or else the one for its type, or else the
+    /// site's default. A page can say `layout = "none"` (or `""`) to have no layout at all.
+    pub fn layout<'a>(&'a self, config: &'a SiteConfig) -> Option<&'a str> {
+        if self.no_layout() { return None }
+        self.layout.as_deref()
+            .or_else(|| config.layouts.get(self.page_type.as_ref()?).map(String::as_str))
+            .or(config.default_layout.as_deref())
+    }
+
+    /// Whether the page asks to be left without a layout
+    pub fn no_layout(&self) -> bool {
+        matches!(self.layout.as_deref(), Some("" | "none"))
+    }
+
+    pub fn page_type(&self) -> Option<&str> {
+        self.page_type.as_deref()
+    }
+
+    pub fn published(&self) -> bool {
+        self.published != Some(false)
+    }
+
+    pub fn draft(&self) -> bool {
+        self.draft == Some(true)
+    }
+
+    /// The page's date, if it has one. Frontmatter with a date we can't read never gets this far.
+    fn date(&self) -> Option<NaiveDateTime> {
+        parse_date(self.date.as_ref()?)
+    }
+
+    /// The charset from the content type, if it gives one
+    fn charset(&self) -> Option<&str> {
+        let (_, charset) = self.content_type.as_deref()?.split_once("charset=")?;
+        charset.split(';').next().map(|c| c.trim().trim_matches('"'))
+    }
+
+    /// Wrap up some rendered content according to whether it's a draft or published
+    fn output(&self, path: PathBuf, content: String) -> RenderOutput {
+        if self.draft() {
+            RenderOutput::Draft(path, content)
+        } else if self.published() {
+            RenderOutput::Rendered(path, content)
+        } else {
+            RenderOutput::Hidden(path, content)
+        }
+    }
+
+    /// The `$variables` a page's layout (or template) can use, other than `$content`
+    fn variables(&self, config: &SiteConfig) -> Vec<(&str, String)> {
+        let mut variables = vec![
+            ("title", self.title.clone().unwrap_or_default()),
+            ("site_title", config.title.clone().unwrap_or_default()),
+            ("toc", self.toc_html.clone()),
+            ("date", self.date().map(|d| d.format("%B %-d, %Y").to_string()).unwrap_or_default()),
+            // The preview server isn't building anything, so it has no id to give
+            ("build_id", config.build_id.clone().unwrap_or("preview".to_string())),
+        ];
+        // `$content` is always the page's body, whatever the frontmatter says
+        variables.extend(self.extra.iter().filter(|(name, _)| *name != "content").map(|(name, value)| (name.as_str(), match value {
+            toml::Value::String(s) => s.clone(),
+            other => other.to_string()
+        })));
+        variables
+    }
+
+    /// The page's canonical url: the one it asks for, or where it'll be under the site's base_url
+    fn canonical_url(&self, filename: &Path, config: &SiteConfig) -> Option<String> {
+        if let Some(canonical) = &self.canonical { return Some(canonical.clone()) }
+        let base = config.base_url.as_ref()?.trim_end_matches('/');
+        Some(sitemap::page_url(base, &filename.with_extension("html")))
+    }
+
+    /// Wrap the content in the page's layout. Whatever `$name` the layout uses that the page
+    /// doesn't define becomes `unknown(name)`, or stays as it is if that's `None`.
+    fn apply_layout(self, content: String, filename: PathBuf, root: &Path, config: &SiteConfig, unknown: fn(&str) -> Option<String>) -> Result<RenderOutput, RenderError> {
+        let wrapped = if let Some(layout) = self.layout(config) {
             let absolute_layout = root.join(layout);
             let layout = fs::read_to_string(absolute_layout.clone()).map_err(|e| RenderError::FileRead(e, absolute_layout))?;
-            layout.replace("$title", title.as_str()).replace("$content", content.as_str())
+            let layout = if config.inject_meta { ensure_head_metas(&layout, self.charset().unwrap_or("utf-8")) } else { layout };
+            let layout = match self.canonical_url(&filename, config) {
+                Some(url) if !head_contains(&layout, "rel=\"canonical\"") => {
+                    inject_into_head(&layout, &format!("<link rel=\"canonical\" href=\"{}\">", url.replace('&', "&amp;").replace('"', "&quot;")))
+                }
+                _ => layout
+            };
+            // A configured favicon is copied to the root of the build, and pages need to say so
+            let layout = match config.favicon_output() {
+                Some((_, name)) if !head_contains(&layout, "rel=\"icon\"") => {
+                    inject_into_head(&layout, &format!("<link rel=\"icon\" href=\"/{}\">", name.replace('"', "")))
+                }
+                _ => layout
+            };
+            // Content goes in last, so nothing in it gets mistaken for a variable
+            let variables = self.variables(config);
+            let layout = apply_conditionals(&layout, &variables);
+            replace_variables(&layout, |name| {
+                lookup(&variables, name).or_else(|| (name != "content").then(|| unknown(name)).flatten())
+            }).replace("$content", content.as_str())
         } else {
             content
         };
-        if let Some(false) = self.published {
-            Ok(RenderOutput::Hidden(filename.with_extension("html"), wrapped))
-        } else {
-            Ok(RenderOutput::Rendered(filename.with_extension("html"), wrapped))
-        }
+        Ok(self.output(filename.with_extension("html"), wrapped))
     }
 }
 
@@ -72,60 +207,237 @@ pub enum RenderError {
     #[error("Error parsing Markdown {1}: {0}")]
     MarkdownParse(Message, PathBuf),
     #[error("Error parsing frontmatter in {1}: {0}")]
-    FrontmatterParse(toml::de::Error, PathBuf)
+    FrontmatterParse(toml::de::Error, PathBuf),
+    #[error("Error compiling stylesheet {1}: {0}")]
+    Stylesheet(Box<grass::Error>, PathBuf),
+    #[error("Error parsing YAML frontmatter in {1}: {0}")]
+    YamlFrontmatterParse(serde_yaml::Error, PathBuf),
+    #[error("Can't read the date {0:?} in {1}; it should look like 2024-05-01 or 2024-05-01T09:30:00")]
+    InvalidDate(String, PathBuf),
+    #[error("Can't edit the frontmatter in {1}: {0}")]
+    FrontmatterEdit(toml_edit::TomlError, PathBuf),
+    #[error("Can't edit the frontmatter in {0}: only TOML (+++) frontmatter can be edited")]
+    YamlFrontmatterEdit(PathBuf)
 }
 
-/// Take a source file path (relative to the root) and the root path, and return a RenderOutput for it.
-/// This function is called for server output, which has different rules from file output.
-pub fn server_render(source: PathBuf, root: &Path) -> Result<RenderOutput, RenderError> {
-    let extension = source.extension().map(|o| o.to_str().unwrap());
-    if skipped_path(source.clone()) {
-        // Skipped path, nothing
-        Ok(RenderOutput::NoOutput)
-    } else if root.join(source.clone()).is_dir() {
-        // Dir, which matters for producing files
-        Ok(RenderOutput::Dir(source))
-    } else if let Ok(true) = fs::exists(root.join(source.clone())) {
+/// Replace every `$name` in the template with its value, leaving any we don't know alone
+fn substitute(template: &str, variables: &[(&str, String)]) -> String {
+    replace_variables(template, |name| lookup(variables, name))
+}
+
+fn lookup(variables: &[(&str, String)], name: &str) -> Option<String> {
+    variables.iter().find(|(n, _)| *n == name).map(|(_, value)| value.clone())
+}
+
+/// Go through each `$name` in the text, replacing it with whatever `replacement` says. The name
+/// is as long as it can be, so `$author_url` is never mistaken for `$author`.
+fn replace_variables(text: &str, replacement: impl Fn(&str) -> Option<String>) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(pos) = rest.find('$') {
+        result.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        let len = after.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after.len());
+        let name = &after[..len];
+        // Not a variable, like the `$` in `$(...)`, or one to leave as it is
+        let replaced = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_').then(|| replacement(name)).flatten();
+        match replaced {
+            Some(value) => result.push_str(&value),
+            None => {
+                result.push('$');
+                result.push_str(name)
+            }
+        }
+        rest = &after[len..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Keep each `{% if name %}...{% endif %}` block whose variable is set to something non-empty,
+/// and remove the others. Blocks don't nest; an `if` without an `endif` is left alone.
+fn apply_conditionals(template: &str, variables: &[(&str, String)]) -> String {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{%") {
+        let Some(tag_end) = rest[start..].find("%}").map(|n| start + n + 2) else { break };
+        let tag: Vec<&str> = rest[start + 2..tag_end - 2].split_whitespace().collect();
+        let endif = rest[tag_end..].find("{%").and_then(|n| {
+            let close = rest[tag_end + n..].find("%}")? + tag_end + n + 2;
+            (rest[tag_end + n + 2..close - 2].trim() == "endif").then_some((tag_end + n, close))
+        });
+        match (tag.as_slice(), endif) {
+            (["if", name], Some((body_end, close))) => {
+                result.push_str(&rest[..start]);
+                if variables.iter().any(|(n, value)| n == name && !value.is_empty()) {
+                    result.push_str(&rest[tag_end..body_end])
+                }
+                rest = &rest[close..];
+            }
+            _ => {
+                // Not one of ours, pass it through
+                result.push_str(&rest[..tag_end]);
+                rest = &rest[tag_end..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// What a source path (relative to the root) turns out to be, before deciding what to do with it
+#[derive(Clone, PartialEq, Debug)]
+pub enum ResolvedSource {
+    /// Underscored, or trying to escape the root
+    Skipped,
+    Dir,
+    /// A file that exists and gets passed through as-is
+    Raw,
+    /// A file that exists and gets rendered as markdown: a .md, or an html file that's opted in
+    Markdown,
+    /// An xml or json file that's opted in to having variables substituted
+    Template,
+    /// An html file that doesn't exist, but the markdown file it would be rendered from does
+    MarkdownFallback(PathBuf),
+    /// A directory's `_index.md`, which renders to its `index.html` with a list of the pages in it
+    SectionIndex,
+    /// A `.scss` or `.sass` file, which gets compiled to css
+    Stylesheet,
+    /// A css file that doesn't exist, but the stylesheet it would be compiled from does
+    StylesheetFallback(PathBuf),
+    Missing
+}
+
+/// Work out what a source path is. Both renderers start here, and then apply their own rules.
+pub fn resolve_source(source: &Path, root: &Path) -> Result<ResolvedSource, RenderError> {
+    let extension = source.extension().and_then(|o| o.to_str());
+    if let Some(index) = section_index_for(source, root) && index == source {
+        Ok(ResolvedSource::SectionIndex)
+    } else if skipped_path(source.to_path_buf()) {
+        Ok(ResolvedSource::Skipped)
+    } else if root.join(source).is_dir() {
+        Ok(ResolvedSource::Dir)
+    } else if let Ok(true) = fs::exists(root.join(source)) {
         match extension {
-            // Asked for a markdown file, but those become html, and we should request it as html:
-            Some("md") => Ok(RenderOutput::NoOutput),
-            // Not a markdown file, but it exists, return it raw
-            _ => Ok(RenderOutput::RawFile(source))
+            Some("md") => Ok(ResolvedSource::Markdown),
+            Some("html") if wants_markdown(source, root)? => Ok(ResolvedSource::Markdown),
+            Some("xml" | "json") if is_template(source, root)? => Ok(ResolvedSource::Template),
+            Some("scss" | "sass") => Ok(ResolvedSource::Stylesheet),
+            _ => Ok(ResolvedSource::Raw)
         }
     } else if matches!(extension, Some("html")) &&
         let Ok(true) = fs::exists(root.join(source.with_extension("md"))) {
-        // We asked for an html file which doesn't exist but a corresponding md file does, render it
-        render_as_markdown(source.with_extension("md"), root)
+        Ok(ResolvedSource::MarkdownFallback(source.with_extension("md")))
+    } else if matches!(extension, Some("css")) && let Some(sheet) = stylesheet_source(source, root) {
+        Ok(ResolvedSource::StylesheetFallback(sheet))
+    } else if source.file_name().is_some_and(|n| n == "index.html") && let Some(index) = section_index_for(source, root) {
+        Ok(ResolvedSource::MarkdownFallback(index))
     } else {
-        // Asked for something which doesn't exist and it's not the md -> html case, 404:
-        Ok(RenderOutput::NoOutput)
+        Ok(ResolvedSource::Missing)
     }
 }
 
-pub fn file_render(source: PathBuf, root: &Path) -> Result<RenderOutput, RenderError> {
-    let extension = source.extension().map(|o| o.to_str().unwrap());
-    if skipped_path(source.clone()) {
-        // Skipped path, nothing
-        Ok(RenderOutput::NoOutput)
-    } else if root.join(source.clone()).is_dir() {
+/// Take a source file path (relative to the root) and the root path, and return a RenderOutput for it.
+/// This function is called for server output, which has different rules from file output.
+pub fn server_render(source: PathBuf, root: &Path, config: &SiteConfig) -> Result<RenderOutput, RenderError> {
+    if config.excludes(root, &source) { return Ok(RenderOutput::NoOutput) }
+    match resolve_source(&source, root)? {
         // Dir, which matters for producing files
-        Ok(RenderOutput::Dir(source))
-    } else if let Ok(true) = fs::exists(root.join(source.clone())) {
-        match extension {
-            // Asked for a markdown file, render it
-            Some("md") => render_as_markdown(source.clone(), root),
-            // Not a markdown file, but it exists, return it raw
-            _ => Ok(RenderOutput::RawFile(source))
+        ResolvedSource::Dir => Ok(RenderOutput::Dir(source)),
+        // Asked for a markdown file, but those become html, and we should request it as html:
+        ResolvedSource::Markdown if source.extension().is_some_and(|e| e == "md") => Ok(RenderOutput::NoOutput),
+        ResolvedSource::Markdown => render_as_markdown(source, root, config),
+        ResolvedSource::Template => render_as_template(source, root, config),
+        ResolvedSource::Raw => Ok(RenderOutput::RawFile(source)),
+        // We asked for an html file which doesn't exist but a corresponding md file does, render it
+        ResolvedSource::MarkdownFallback(md) => render_as_markdown(md, root, config),
+        // Like markdown, it's asked for as the html it becomes
+        ResolvedSource::SectionIndex => Ok(RenderOutput::NoOutput),
+        // And stylesheets as the css they become
+        ResolvedSource::Stylesheet => Ok(RenderOutput::NoOutput),
+        ResolvedSource::StylesheetFallback(sheet) => render_as_stylesheet(sheet, root),
+        // Skipped, or asked for something which doesn't exist and it's not the md -> html case, 404:
+        ResolvedSource::Skipped | ResolvedSource::Missing => Ok(RenderOutput::NoOutput)
+    }
+}
+
+/// Put some made-up content in a layout, so it can be seen without a real page. Any `$variable`
+/// we don't know about is shown as `[variable]`, so it's easy to spot.
+pub fn preview_layout(layout: &Path, root: &Path, config: &SiteConfig) -> Result<String, RenderError> {
+    let frontmatter = Frontmatter {
+        layout: Some(layout.to_string_lossy().to_string()),
+        title: Some("Sample page title".to_string()),
+        ..Frontmatter::default()
+    };
+    let content = markdown::to_html_with_options(include_str!("../templates/sample_content.md"), &markdown_options())
+        .map_err(|e| RenderError::MarkdownParse(e, layout.to_path_buf()))?;
+    // Anything the layout uses that a real page would fill in shows up as `[name]`
+    match frontmatter.apply_layout(content, PathBuf::from("layout-preview.html"), root, config, |name| Some(format!("[{}]", name)))? {
+        RenderOutput::Rendered(_, html) | RenderOutput::Hidden(_, html) => Ok(html),
+        _ => unreachable!()
+    }
+}
+
+/// Whether this source file is one that gets rendered as markdown (rather than copied)
+pub fn is_markdown_source(source: &Path, root: &Path) -> Result<bool, RenderError> {
+    match source.extension().and_then(|e| e.to_str()) {
+        Some("md") => Ok(true),
+        Some("html") => wants_markdown(source, root),
+        _ => Ok(false)
+    }
+}
+
+pub fn file_render(source: PathBuf, root: &Path, config: &SiteConfig) -> Result<RenderOutput, RenderError> {
+    if config.excludes(root, &source) { return Ok(RenderOutput::NoOutput) }
+    let output = match resolve_source(&source, root)? {
+        // Dir, which matters for producing files
+        ResolvedSource::Dir => Ok(RenderOutput::Dir(source)),
+        // A markdown file (or an html file that wants to be one), render it
+        ResolvedSource::Markdown | ResolvedSource::SectionIndex => render_as_markdown(source, root, config),
+        ResolvedSource::Template => render_as_template(source, root, config),
+        ResolvedSource::Raw => Ok(RenderOutput::RawFile(source)),
+        ResolvedSource::Stylesheet => render_as_stylesheet(source, root),
+        // We only build files that exist; the md (or scss) file will get built on its own
+        ResolvedSource::Skipped | ResolvedSource::MarkdownFallback(_) | ResolvedSource::StylesheetFallback(_) |
+        ResolvedSource::Missing => Ok(RenderOutput::NoOutput)
+    }?;
+    // Only html has anywhere to put the meta; other hidden files are still left out of the build
+    match output {
+        RenderOutput::Hidden(path, contents) if config.hidden_mode == HiddenMode::Noindex && path.extension().is_some_and(|e| e == "html") => {
+            Ok(RenderOutput::Rendered(path, add_noindex(&contents)))
         }
+        output => Ok(output)
+    }
+}
+
+/// Ask search engines to leave a page alone. A page without a head gets the meta at the very
+/// top, which browsers put in the head for us.
+fn add_noindex(html: &str) -> String {
+    if head_contains(html, "name=\"robots\"") {
+        html.to_string()
+    } else if head_range(html).is_some() {
+        inject_into_head(html, NOINDEX_META)
     } else {
-        // Asked for something which doesn't exist:
-        Ok(RenderOutput::NoOutput)
+        format!("{}\n{}", NOINDEX_META, html)
     }
 }
 
+const NOINDEX_META: &str = "<meta name=\"robots\" content=\"noindex\">";
+
+/// Whether a page asks search engines to leave it alone, with a robots meta that says noindex.
+/// The word anywhere else in the head, like the title, doesn't count.
+pub fn is_noindex(html: &str) -> bool {
+    let Some((start, end)) = head_range(html) else { return html.starts_with(NOINDEX_META) };
+    let head = html[start..end].to_ascii_lowercase();
+    head.split("<meta").skip(1).any(|tag| {
+        let tag = tag.split('>').next().unwrap_or_default().replace(['"', '\''], "");
+        tag.split_whitespace().any(|attr| attr.trim_end_matches('/') == "name=robots") && tag.contains("noindex")
+    })
+}
+
 // If any element of the path starts with an underscore, we want to skip rendering it.
 // In addition, if a cheeky person has put .. in the path, just skip it (which will trigger a 404 from the dev server)
-fn skipped_path(source: PathBuf) -> bool {
+pub fn skipped_path(source: PathBuf) -> bool {
     source.iter().any(|el| {
         match el.to_str() {
             Some("..") => true,
@@ -135,28 +447,475 @@ fn skipped_path(source: PathBuf) -> bool {
     })
 }
 
+/// Parse just the frontmatter of a (markdown, or markdown-in-html) source file, without rendering it
+pub fn read_frontmatter(source: &Path, root: &Path) -> Result<Option<Frontmatter>, RenderError> {
+    let contents = fs::read_to_string(root.join(source)).map_err(|e| RenderError::FileRead(e, source.to_path_buf()))?;
+    let ast = markdown::to_mdast(contents.as_str(), &markdown_options().parse).map_err(|e| RenderError::MarkdownParse(e, source.to_path_buf()))?;
+    find_frontmatter(ast, source.to_path_buf())
+}
+
+/// The content type a source path's frontmatter asks to be served as, if it asks. Only pages
+/// and templates have frontmatter to ask with.
+pub fn declared_content_type(source: &Path, root: &Path) -> Option<String> {
+    let frontmatter = match resolve_source(source, root).ok()? {
+        ResolvedSource::Markdown => read_frontmatter(source, root).ok()??,
+        ResolvedSource::MarkdownFallback(md) => read_frontmatter(&md, root).ok()??,
+        ResolvedSource::Template => {
+            let contents = fs::read_to_string(root.join(source)).ok()?;
+            toml::from_str::<Frontmatter>(split_frontmatter(&contents)?.0).ok()?
+        }
+        _ => return None
+    };
+    frontmatter.content_type
+}
+
+/// Every link and image url in a markdown source file, in the order they appear
+pub fn find_links(source: &Path, root: &Path) -> Result<Vec<String>, RenderError> {
+    fn visit(node: &Node, links: &mut Vec<String>) {
+        match node {
+            Node::Link(link) => links.push(link.url.clone()),
+            Node::Image(image) => links.push(image.url.clone()),
+            Node::Definition(definition) => links.push(definition.url.clone()),
+            _ => {}
+        }
+        for child in node.children().into_iter().flatten() {
+            visit(child, links)
+        }
+    }
+
+    let contents = fs::read_to_string(root.join(source)).map_err(|e| RenderError::FileRead(e, source.to_path_buf()))?;
+    let ast = markdown::to_mdast(contents.as_str(), &markdown_options().parse).map_err(|e| RenderError::MarkdownParse(e, source.to_path_buf()))?;
+    let mut links = vec![];
+    visit(&ast, &mut links);
+    Ok(links)
+}
+
+/// Every link and image url in some built html: the `href` of each `<a>` and the `src` of each
+/// `<img>`, in the order they appear, with `&amp;` turned back into `&`
+pub fn html_links(html: &str) -> Vec<String> {
+    let mut links = vec![];
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let tag = &rest[..rest.find('>').unwrap_or(rest.len())];
+        let (name, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        let attribute = match name.to_ascii_lowercase().as_str() {
+            "a" => "href",
+            "img" => "src",
+            _ => continue
+        };
+        if let Some(url) = attribute_value(attributes, attribute) {
+            links.push(url.replace("&amp;", "&"))
+        }
+    }
+    links
+}
+
+/// The value of a quoted attribute in the inside of a tag, if it's there
+fn attribute_value<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attributes;
+    loop {
+        let start = rest.find(name)?;
+        let before = rest[..start].chars().next_back();
+        let after = rest[start + name.len()..].trim_start();
+        rest = &rest[start + name.len()..];
+        // Skip over longer names that end in this one, like data-href
+        if before.is_some_and(|c| !c.is_whitespace()) { continue }
+        let Some(value) = after.strip_prefix('=') else { continue };
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        return value[1..].split(quote).next()
+    }
+}
+
+/// The urls of every image in a markdown source
+pub fn find_image_links(source: &Path, root: &Path) -> Result<Vec<String>, RenderError> {
+    let contents = fs::read_to_string(root.join(source)).map_err(|e| RenderError::FileRead(e, source.to_path_buf()))?;
+    let ast = markdown::to_mdast(contents.as_str(), &markdown_options().parse).map_err(|e| RenderError::MarkdownParse(e, source.to_path_buf()))?;
+    let mut images = vec![];
+    collect_images(&ast, &mut images);
+    Ok(images.into_iter().map(|(url, _)| url.to_string()).collect())
+}
+
+/// Change the urls of images in some markdown. `rewrite` gets each url and returns its
+/// replacement, or None to leave it be. Everything else in the text is left exactly as it was.
+pub fn rewrite_image_links(contents: &str, source: &Path, rewrite: impl Fn(&str) -> Option<String>) -> Result<String, RenderError> {
+    let ast = markdown::to_mdast(contents, &markdown_options().parse).map_err(|e| RenderError::MarkdownParse(e, source.to_path_buf()))?;
+    let mut images = vec![];
+    collect_images(&ast, &mut images);
+
+    // Going backwards through the file means earlier offsets survive each replacement
+    let mut result = contents.to_string();
+    for (url, (start, end)) in images.into_iter().rev() {
+        let Some(new_url) = rewrite(url) else { continue };
+        // The url is whatever follows the `(` after the alt text; the title can't start with one
+        if let Some(offset) = contents[start..end].rfind(&format!("({}", url)) {
+            let url_start = start + offset + 1;
+            result.replace_range(url_start..url_start + url.len(), &new_url)
+        }
+    }
+    Ok(result)
+}
+
+fn collect_images<'a>(node: &'a Node, images: &mut Vec<(&'a str, (usize, usize))>) {
+    if let Node::Image(image) = node && let Some(position) = &image.position {
+        images.push((&image.url, (position.start.offset, position.end.offset)))
+    }
+    for child in node.children().into_iter().flatten() {
+        collect_images(child, images)
+    }
+}
+
+/// Every key in a source file's frontmatter with its value, for showing the user what we parsed.
+/// This includes keys we don't use, and fails the same way rendering would on bad frontmatter.
+pub fn frontmatter_fields(source: &Path, root: &Path) -> Result<Option<Fields>, RenderError> {
+    Ok(read_frontmatter_and_fields(source, root)?.map(|(_, fields)| fields))
+}
+
+/// Every field in a page's frontmatter, as its name and its value written out as text
+type Fields = Vec<(String, String)>;
+
+/// A page's frontmatter, and every field in it as text, from parsing it just the once
+fn read_frontmatter_and_fields(source: &Path, root: &Path) -> Result<Option<(Frontmatter, Fields)>, RenderError> {
+    let contents = fs::read_to_string(root.join(source)).map_err(|e| RenderError::FileRead(e, source.to_path_buf()))?;
+    let ast = markdown::to_mdast(contents.as_str(), &markdown_options().parse).map_err(|e| RenderError::MarkdownParse(e, source.to_path_buf()))?;
+    let Some(block) = frontmatter_block(&ast) else { return Ok(None) };
+    let table: toml::Table = block.parse(source)?;
+    let frontmatter = toml::Value::Table(table.clone()).try_into().map_err(|e| RenderError::FrontmatterParse(e, source.to_path_buf()))?;
+    let frontmatter = check_frontmatter(frontmatter, source)?;
+    Ok(Some((frontmatter, table.into_iter().map(|(key, value)| {
+        let value = match value {
+            toml::Value::String(s) => s,
+            other => other.to_string()
+        };
+        (key, value)
+    }).collect())))
+}
+
+// Html files are normally left alone, but if they start with frontmatter containing
+// `markdown = true` then we render them just like markdown files.
+fn wants_markdown(source: &Path, root: &Path) -> Result<bool, RenderError> {
+    let contents = fs::read_to_string(root.join(source)).map_err(|e| RenderError::FileRead(e, source.to_path_buf()))?;
+    if !contents.starts_with("+++") { return Ok(false) }
+    Ok(matches!(read_frontmatter(source, root)?, Some(Frontmatter { markdown: Some(true), .. })))
+}
+
+// Split a +++-delimited toml frontmatter block off the front of a file, if it has one
+fn split_frontmatter(contents: &str) -> Option<(&str, &str)> {
+    let rest = contents.strip_prefix("+++")?.trim_start_matches([' ', '\t']);
+    let rest = rest.strip_prefix("\r\n").or_else(|| rest.strip_prefix('\n'))?;
+    let end = rest.find("\n+++")?;
+    let body = &rest[end + 4..];
+    let body = body.split_once('\n').map(|(_, b)| b).unwrap_or_default();
+    Some((&rest[..end], body))
+}
+
+/// Set one key in a file's frontmatter, or remove it with None. Everything else in the
+/// frontmatter, comments and key order included, is left exactly as it was. A file without
+/// frontmatter gets some.
+pub fn edit_frontmatter(contents: &str, key: &str, value: Option<toml_edit::Value>) -> Result<String, toml_edit::TomlError> {
+    let (toml_str, body) = split_frontmatter(contents).unwrap_or(("", contents));
+    let mut doc: toml_edit::DocumentMut = toml_str.parse()?;
+    match value {
+        Some(value) => doc[key] = toml_edit::value(value),
+        None => { doc.remove(key); }
+    }
+    let doc = doc.to_string();
+    Ok(format!("+++\n{}\n+++\n{}", doc.trim_end_matches('\n'), body))
+}
+
+/// What a key is currently set to in a file's frontmatter, as it's written there
+pub fn frontmatter_field(contents: &str, key: &str) -> Option<String> {
+    let (toml_str, _) = split_frontmatter(contents)?;
+    let doc: toml_edit::DocumentMut = toml_str.parse().ok()?;
+    let mut value = doc.get(key)?.as_value()?.clone();
+    value.decor_mut().clear();
+    Some(value.to_string())
+}
+
+/// Turn what someone typed into a frontmatter value: anything that reads as TOML (`true`,
+/// `3`, `["a", "b"]`, `"quoted"`) is taken as that, and anything else is a plain string.
+/// Dates stay strings too, the way imported pages write them.
+pub fn parse_field_value(text: &str) -> toml_edit::Value {
+    let text = text.trim();
+    match text.parse::<toml_edit::Value>() {
+        Ok(value) if !value.is_datetime() => value,
+        _ => text.into()
+    }
+}
+
+// Non-markdown text files (feeds, manifests) are templates if they start with frontmatter
+fn is_template(source: &Path, root: &Path) -> Result<bool, RenderError> {
+    let contents = fs::read_to_string(root.join(source)).map_err(|e| RenderError::FileRead(e, source.to_path_buf()))?;
+    Ok(split_frontmatter(&contents).is_some())
+}
+
+/// The `.scss` or `.sass` file a css path would be compiled from, if there is one
+pub fn stylesheet_source(css: &Path, root: &Path) -> Option<PathBuf> {
+    ["scss", "sass"].iter().map(|ext| css.with_extension(ext)).find(|sheet| root.join(sheet).is_file())
+}
+
+// Compile a stylesheet to css. Its imports are found relative to it, and partials (which start
+// with an underscore) are skipped on their own, so they only show up through an import.
+fn render_as_stylesheet(source: PathBuf, root: &Path) -> Result<RenderOutput, RenderError> {
+    let css = grass::from_path(root.join(&source), &grass::Options::default())
+        .map_err(|e| RenderError::Stylesheet(e, source.clone()))?;
+    Ok(RenderOutput::Stylesheet(source.with_extension("css"), css))
+}
+
+// Substitute variables into a non-markdown template. The frontmatter is removed and the
+// rest of the file is otherwise left exactly as it was.
+fn render_as_template(source: PathBuf, root: &Path, config: &SiteConfig) -> Result<RenderOutput, RenderError> {
+    let contents = fs::read_to_string(root.join(&source)).map_err(|e| RenderError::FileRead(e, source.clone()))?;
+    let Some((toml_str, body)) = split_frontmatter(&contents) else { return Ok(RenderOutput::RawFile(source)) };
+    let frontmatter = parse_frontmatter(&FrontmatterBlock::Toml(toml_str), &source)?;
+    let rendered = substitute(body, &frontmatter.variables(config));
+    Ok(frontmatter.output(source, rendered))
+}
+
 // This gets called by `render` if the source path extension is md
-fn render_as_markdown(source: PathBuf, root: &Path) -> Result<RenderOutput, RenderError> {
+fn render_as_markdown(source: PathBuf, root: &Path, config: &SiteConfig) -> Result<RenderOutput, RenderError> {
     let absolute_source = root.join(source.clone());
     let contents = fs::read_to_string(absolute_source.clone()).map_err(|e| RenderError::FileRead(e, source.clone()))?;
-    let options = markdown_options();
+    let mut options = markdown_options();
+    if !config.gfm { without_gfm_extras(&mut options) }
+    if source.extension().is_some_and(|e| e == "html") {
+        // The whole point of markdown in an html file is that the html is kept
+        options.compile.allow_dangerous_html = true;
+    }
     let html = markdown::to_html_with_options(contents.as_str(), &options).map_err(|e| RenderError::MarkdownParse(e, source.clone()))?;
+    let html = if config.highlight_code { highlight_code_blocks(&html) } else { html };
+    let html = if config.line_numbers { number_code_lines(&html) } else { html };
+    let html = if config.figures { number_figures(&html) } else { html };
     let ast = markdown::to_mdast(contents.as_str(), &options.parse).map_err(|e| RenderError::MarkdownParse(e, source.clone()))?;
+    let headings = collect_headings(&ast);
+    let mut frontmatter = find_frontmatter(ast, source.clone())?;
 
-    if let Some(frontmatter) = find_frontmatter(ast, source.clone())? {
-        frontmatter.apply_layout(html, source, root)
+    // Headings get ids, so the table of contents can link to them
+    let html = if frontmatter.as_ref().is_some_and(|f| f.toc == Some(false)) {
+        html
+    } else {
+        let (html, toc) = add_heading_ids(&html, &headings);
+        if let Some(frontmatter) = &mut frontmatter { frontmatter.toc_html = toc }
+        html
+    };
+
+    // A section index lists its pages under its own content, and becomes the dir's index.html
+    let (html, source) = if source.file_name().is_some_and(|n| n == "_index.md") {
+        let default = Frontmatter::default();
+        let listing = section_listing(&source, root, frontmatter.as_ref().unwrap_or(&default))?;
+        (html + &listing, source.with_file_name("index.md"))
+    } else {
+        (html, source)
+    };
+
+    // Pages without frontmatter still get the site's default layout, if it has one
+    if let Some(frontmatter) = frontmatter.or_else(|| config.default_layout.is_some().then(Frontmatter::default)) {
+        frontmatter.apply_layout(html, source, root, config, |_| None)
     } else {
         Ok(RenderOutput::Rendered(source.with_extension("html"), html))
     }
 }
 
+/// The `_index.md` that would make a page at this path (relative to the root), if there is one.
+/// A directory's real `index.md` beats it, and underscored directories don't get one.
+fn section_index_for(source: &Path, root: &Path) -> Option<PathBuf> {
+    let dir = source.parent()?;
+    if skipped_path(dir.to_path_buf()) || root.join(dir).join("index.md").exists() { return None }
+    let index = dir.join("_index.md");
+    root.join(&index).is_file().then_some(index)
+}
+
+/// One page in a section index, with what it gets sorted by
+struct ListedPage {
+    /// The value of the index's `sort_by` key, if it has one and the page has it
+    key: Option<String>,
+    weight: Option<i64>,
+    date: Option<NaiveDateTime>,
+    title: String,
+    name: String
+}
+
+/// Put listed pages in order: by the `sort_by` key if there is one, then by weight, date and
+/// file name. Pages missing any of those go after the ones that have it.
+fn sort_listing(pages: &mut [ListedPage], reverse: bool) {
+    fn present_first<T: Ord>(a: &Option<T>, b: &Option<T>) -> Ordering {
+        match (a, b) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (a, b) => b.is_some().cmp(&a.is_some())
+        }
+    }
+    pages.sort_by(|a, b| {
+        let by_key = match (&a.key, &b.key) {
+            (Some(x), Some(y)) if reverse => y.cmp(x),
+            _ => present_first(&a.key, &b.key)
+        };
+        by_key.then(present_first(&a.weight, &b.weight))
+            .then(present_first(&a.date, &b.date))
+            .then(a.name.cmp(&b.name))
+    });
+}
+
+/// A list of links to the pages listed by a section index: the published markdown pages in its
+/// dir, or the ones its `pages` patterns pick out
+fn section_listing(source: &Path, root: &Path, index: &Frontmatter) -> Result<String, RenderError> {
+    let dir = source.parent().unwrap_or(Path::new(""));
+    let patterns = index.pages.clone().unwrap_or(vec!["*.md".to_string()]);
+
+    let mut pages = vec![];
+    let entries = fs::read_dir(root.join(dir)).map_err(|e| RenderError::FileRead(e, dir.to_path_buf()))?;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let page = dir.join(&name);
+        if !name.ends_with(".md") || name == "index.md" || skipped_path(page.clone()) || !entry.path().is_file() { continue }
+        if !patterns.iter().any(|p| glob_match(p, &name)) { continue }
+        let (frontmatter, fields) = read_frontmatter_and_fields(&page, root)?.unwrap_or_default();
+        if frontmatter.draft() || !frontmatter.published() { continue }
+
+        let field = |key: &str| fields.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+        let title = field("title").unwrap_or(name.trim_end_matches(".md").to_string());
+        let key = match index.sort_by.as_deref() {
+            Some("title") => Some(title.clone()),
+            // Dates are compared as dates, however they're written
+            Some("date") => frontmatter.date().map(|d| d.format("%Y-%m-%dT%H:%M:%S%.f").to_string()),
+            Some(key) => field(key),
+            None => None
+        };
+        pages.push(ListedPage { key, weight: frontmatter.weight, date: frontmatter.date(), title, name });
+    }
+    sort_listing(&mut pages, index.reverse == Some(true));
+
+    let escape = |s: &str| s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+    let items: Vec<String> = pages.iter()
+        .map(|page| format!("<li><a href=\"{}\">{}</a></li>\n", escape(&Path::new(&page.name).with_extension("html").to_string_lossy()), escape(&page.title)))
+        .collect();
+    Ok(format!("<ul class=\"section-index\">\n{}</ul>\n", items.concat()))
+}
+
+/// Whether a file name matches a pattern, where `*` is any run of characters
+fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else { return false };
+            (0..=name.len()).filter(|&i| name.is_char_boundary(i)).any(|i| glob_match(rest, &name[i..]))
+        }
+    }
+}
+
+/// Wrap every line of every code block (not inline code) in a numbered span. The code itself,
+/// blank lines and all, is left exactly as it was.
+fn number_code_lines(html: &str) -> String {
+    let mut result = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("<pre><code") {
+        let code_tag = start + "<pre>".len();
+        let Some(open_end) = rest[code_tag..].find('>').map(|n| code_tag + n + 1) else { break };
+        let Some(close) = rest[open_end..].find("</code></pre>").map(|n| open_end + n) else { break };
+        result.push_str(&rest[..open_end]);
+
+        // Code blocks end with a newline, which doesn't start another line
+        let code = &rest[open_end..close];
+        let (code, trailing) = match code.strip_suffix('\n') { Some(code) => (code, "\n"), None => (code, "") };
+        let lines: Vec<String> = code.split('\n').enumerate()
+            .map(|(n, line)| format!("<span class=\"line\" data-line=\"{}\">{}</span>", n + 1, line))
+            .collect();
+        if !code.is_empty() { result.push_str(&lines.join("\n")) }
+        result.push_str(trailing);
+        rest = &rest[close..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Turn each paragraph that's nothing but an image with alt text into a `<figure>`, captioned
+/// with the alt text and numbered from 1. Images without alt text, or in among other text, are
+/// left alone.
+fn number_figures(html: &str) -> String {
+    let mut result = String::new();
+    let mut rest = html;
+    let mut figure = 0;
+    while let Some(start) = rest.find("<p><img ") {
+        let Some(end) = rest[start..].find("</p>").map(|n| start + n) else { break };
+        let image = &rest[start + "<p>".len()..end];
+        result.push_str(&rest[..start]);
+        // The alt text is already escaped, so it can go straight into the caption
+        let alt = image.split_once(" alt=\"").and_then(|(_, after)| after.split_once('"')).map(|(alt, _)| alt);
+        match alt {
+            Some(alt) if !alt.is_empty() && image.matches('<').count() == 1 => {
+                figure += 1;
+                result.push_str(&format!("<figure>{}<figcaption>Figure {}. {}</figcaption></figure>", image, figure, alt))
+            }
+            _ => result.push_str(&rest[start..end + "</p>".len()])
+        }
+        rest = &rest[end + "</p>".len()..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Each heading on the page, in order, as its level and its text
+fn collect_headings(node: &Node) -> Vec<(u8, String)> {
+    let mut headings = vec![];
+    for child in node.children().into_iter().flatten() {
+        match child {
+            Node::Heading(heading) => headings.push((heading.depth, child.to_string())),
+            other => headings.extend(collect_headings(other))
+        }
+    }
+    headings
+}
+
+/// Give each heading an id made from its text, and make a table of contents linking to them:
+/// nested lists, following the heading levels. Ids are made unique by numbering repeats.
+fn add_heading_ids(html: &str, headings: &[(u8, String)]) -> (String, String) {
+    let mut result = String::new();
+    let mut rest = html;
+    let mut used: Vec<String> = vec![];
+    let mut toc = String::new();
+    let mut levels: Vec<u8> = vec![];
+    for (depth, text) in headings {
+        let tag = format!("<h{}>", depth);
+        let Some(start) = rest.find(&tag) else { continue };
+        let slug = Some(slugify(text)).filter(|s| !s.is_empty()).unwrap_or("section".to_string());
+        let id = (1..).map(|n| if n == 1 { slug.clone() } else { format!("{}-{}", slug, n) })
+            .find(|id| !used.contains(id)).unwrap();
+        result.push_str(&rest[..start]);
+        result.push_str(&format!("<h{} id=\"{}\">", depth, id));
+        rest = &rest[start + tag.len()..];
+
+        // A deeper heading starts a list inside the last item; a shallower one closes lists
+        if levels.last().is_none_or(|level| depth > level) {
+            toc.push_str("<ul>");
+            levels.push(*depth)
+        } else {
+            toc.push_str("</li>");
+            while levels.len() > 1 && levels.last().is_some_and(|level| depth < level) {
+                toc.push_str("</ul></li>");
+                levels.pop();
+            }
+        }
+        let text = text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+        toc.push_str(&format!("<li><a href=\"#{}\">{}</a>", id, text));
+        used.push(id);
+    }
+    result.push_str(rest);
+    toc.push_str(&"</li></ul>".repeat(levels.len()));
+    (result, toc)
+}
+
 // Construct the Markdown options we'll render with
 fn markdown_options() -> Options {
     markdown::Options {
         parse: ParseOptions {
             constructs: Constructs {
-                frontmatter: true,
+                frontmatter: true, // Both +++ toml and --- yaml
                 gfm_table: true,
+                gfm_footnote_definition: true,
+                gfm_label_start_footnote: true,
+                gfm_strikethrough: true,
+                gfm_task_list_item: true,
+                gfm_autolink_literal: true,
                 ..Default::default()
             },
             ..Default::default()
@@ -165,17 +924,126 @@ fn markdown_options() -> Options {
     }
 }
 
-// Look for and try to parse toml frontmatter
+// Turn off the GitHub extras, leaving CommonMark with tables (and frontmatter)
+fn without_gfm_extras(options: &mut Options) {
+    let constructs = &mut options.parse.constructs;
+    constructs.gfm_footnote_definition = false;
+    constructs.gfm_label_start_footnote = false;
+    constructs.gfm_strikethrough = false;
+    constructs.gfm_task_list_item = false;
+    constructs.gfm_autolink_literal = false;
+}
+
+// Look for and try to parse toml (or yaml) frontmatter
 fn find_frontmatter(node: Node, source: PathBuf) -> Result<Option<Frontmatter>, RenderError> {
-    if let Some(children) = node.children() {
-        for child in children.iter() {
-            if let Node::Toml(toml_str) = child {
-                let fmatter: Frontmatter = toml::from_str(toml_str.value.as_str()).map_err(|e| RenderError::FrontmatterParse(e, source))?;
-                return Ok(Some(fmatter))
-            }
+    frontmatter_block(&node).map(|block| parse_frontmatter(&block, &source)).transpose()
+}
+
+// The raw text of a page's frontmatter, as `+++` toml or `---` yaml
+enum FrontmatterBlock<'a> {
+    Toml(&'a str),
+    Yaml(&'a str)
+}
+
+impl FrontmatterBlock<'_> {
+    fn parse<T: DeserializeOwned>(&self, source: &Path) -> Result<T, RenderError> {
+        match self {
+            FrontmatterBlock::Toml(toml_str) => toml::from_str(toml_str).map_err(|e| RenderError::FrontmatterParse(e, source.to_path_buf())),
+            FrontmatterBlock::Yaml(yaml_str) => serde_yaml::from_str(yaml_str).map_err(|e| RenderError::YamlFrontmatterParse(e, source.to_path_buf()))
         }
-        Ok(None)
-    } else { Ok(None) }
+    }
+}
+
+// Parse frontmatter, checking the things toml can't check for us
+fn parse_frontmatter(block: &FrontmatterBlock, source: &Path) -> Result<Frontmatter, RenderError> {
+    check_frontmatter(block.parse(source)?, source)
+}
+
+fn check_frontmatter(frontmatter: Frontmatter, source: &Path) -> Result<Frontmatter, RenderError> {
+    if let Some(date) = &frontmatter.date && parse_date(date).is_none() {
+        return Err(RenderError::InvalidDate(date.clone(), source.to_path_buf()))
+    }
+    Ok(frontmatter)
+}
+
+// A date can be a string or one of toml's own dates; either way we keep it as text
+fn date_string<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum DateValue {
+        Text(String),
+        Toml(toml::value::Datetime)
+    }
+    Ok(Option::<DateValue>::deserialize(deserializer)?.map(|date| match date {
+        DateValue::Text(text) => text,
+        DateValue::Toml(date) => date.to_string()
+    }))
+}
+
+/// Read an ISO-8601 date, or date and time. A time zone offset is allowed but ignored, since
+/// all we do with dates is show and sort them.
+pub fn parse_date(text: &str) -> Option<NaiveDateTime> {
+    let text = text.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return date.and_hms_opt(0, 0, 0)
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Some(date.naive_local())
+    }
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+}
+
+// The page's frontmatter block, if there is one. Markdown only allows one, at the very top, but
+// toml is what we really speak, so it would win.
+fn frontmatter_block(node: &Node) -> Option<FrontmatterBlock<'_>> {
+    let children = node.children()?;
+    children.iter().find_map(|child| match child {
+        Node::Toml(toml) => Some(FrontmatterBlock::Toml(toml.value.as_str())),
+        _ => None
+    }).or_else(|| children.iter().find_map(|child| match child {
+        Node::Yaml(yaml) => Some(FrontmatterBlock::Yaml(yaml.value.as_str())),
+        _ => None
+    }))
+}
+
+/// Whether a file starts with `---` yaml frontmatter, which we can read but not edit
+pub fn has_yaml_frontmatter(contents: &str) -> bool {
+    contents.starts_with("---\n") || contents.starts_with("---\r\n")
+}
+
+// Find the byte range of the inside of the document's <head>, if it has one
+fn head_range(html: &str) -> Option<(usize, usize)> {
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<head")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</head>")?;
+    Some((start, end))
+}
+
+/// Insert a tag at the top of the document's <head>. Documents without a head are returned as-is.
+pub fn inject_into_head(html: &str, tag: &str) -> String {
+    match head_range(html) {
+        Some((start, _)) => format!("{}\n    {}{}", &html[..start], tag, &html[start..]),
+        None => html.to_string()
+    }
+}
+
+/// Whether the document's <head> already contains this (lowercase) snippet
+pub fn head_contains(html: &str, snippet: &str) -> bool {
+    head_range(html).is_some_and(|(start, end)| html[start..end].to_ascii_lowercase().contains(snippet))
+}
+
+// Make sure a layout declares its charset and a mobile-friendly viewport
+fn ensure_head_metas(layout: &str, charset: &str) -> String {
+    let mut layout = layout.to_string();
+    if !head_contains(&layout, "name=\"viewport\"") {
+        layout = inject_into_head(&layout, "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">");
+    }
+    if !head_contains(&layout, "charset") {
+        layout = inject_into_head(&layout, &format!("<meta charset=\"{}\">", charset.replace('"', "")));
+    }
+    layout
 }
 
 #[cfg(test)]
@@ -183,7 +1051,7 @@ mod tests {
     use super::*;
 
     fn render_file(path: impl Into<PathBuf>) -> RenderOutput {
-        match server_render(path.into(), Path::new("./testdata")) {
+        match server_render(path.into(), Path::new("./testdata"), &SiteConfig::default()) {
             Ok(ro) => ro,
             Err(e) => {
                 println!("{}", e);
@@ -233,6 +1101,14 @@ mod tests {
         assert!(matches!(contents, RenderOutput::Rendered(_, _))); // If we don't specify, it's published by default
     }
 
+    #[test]
+    fn test_draft() {
+        let contents = render_file("draft.html");
+        let RenderOutput::Draft(path, contents) = contents else { panic!() };
+        assert_eq!(path, PathBuf::from("draft.html"));
+        assert!(contents.contains("still being written"));
+    }
+
     #[test]
     fn test_raw() {
         let contents = render_file("raw.txt");
@@ -254,4 +1130,523 @@ mod tests {
         let contents = render_file("index.html");
         assert!(matches!(contents, RenderOutput::Rendered(_, _))) // We asked for the html file which doesn't exist but the md does
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_head_metas() {
+        // The post layout doesn't have either meta, so they get added
+        let RenderOutput::Rendered(_, contents) = render_file("index.html") else { panic!() };
+        assert!(contents.contains("<meta charset=\"utf-8\">"));
+        assert!(contents.contains("<meta name=\"viewport\""));
+
+        // Unless we turn that off
+        let config = SiteConfig { inject_meta: false, ..SiteConfig::default() };
+        let Ok(RenderOutput::Rendered(_, contents)) = server_render("index.html".into(), Path::new("./testdata"), &config) else { panic!() };
+        assert!(!contents.contains("<meta charset"));
+
+        // A layout that already has them is left alone
+        let layout = "<html><HEAD><meta charset=\"latin1\"><meta name=\"viewport\" content=\"width=500\"></HEAD><body>$content</body></html>";
+        assert_eq!(ensure_head_metas(layout, "utf-8"), layout);
+
+        // And a fragment with no head at all is too
+        assert_eq!(ensure_head_metas("<p>$content</p>", "utf-8"), "<p>$content</p>");
+
+        // Text that changes length when lowercased doesn't throw off where the head is
+        let layout = "<!-- İstanbul --><html><head></head><body>$content</body></html>";
+        assert_eq!(inject_into_head(layout, "<meta>"), "<!-- İstanbul --><html><head>\n    <meta></head><body>$content</body></html>");
+        assert!(head_contains("<!-- İİİ --><head><META></head>", "<meta>"));
+    }
+
+    #[test]
+    fn test_markdown_in_html() {
+        let contents = render_file("mixed.html");
+        let RenderOutput::Rendered(filename, contents) = contents else { panic!() };
+        assert_eq!(filename.to_str(), Some("mixed.html"));
+        assert!(contents.contains("<title>Mixed Content</title>")); // Uses the layout
+        assert!(contents.contains("<div class=\"note\">")); // Keeps the html
+        assert!(contents.contains("<strong>markdown</strong>")); // And renders the markdown inside it
+        assert!(!contents.contains("+++"));
+
+        // Without the frontmatter flag, html is still served raw
+        assert_eq!(render_file("plain.html"), RenderOutput::RawFile(PathBuf::from("plain.html")));
+        let built = file_render("mixed.html".into(), Path::new("./testdata"), &SiteConfig::default()).unwrap();
+        assert!(matches!(built, RenderOutput::Rendered(_, _)));
+    }
+
+    #[test]
+    fn test_xml_template() {
+        let config = SiteConfig { title: Some("Pest Toast Weekly".to_string()), ..SiteConfig::default() };
+        let output = file_render("feed.xml".into(), Path::new("./testdata"), &config).unwrap();
+        let RenderOutput::Rendered(filename, contents) = output else { panic!() };
+        assert_eq!(filename.to_str(), Some("feed.xml")); // Keeps its extension
+        assert!(contents.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">")); // Frontmatter is gone
+        assert!(contents.contains("<title>Pest Toast Weekly</title>"));
+        assert!(contents.contains("<description>The Feed</description>")); // Page variables work too
+        assert!(contents.trim_end().ends_with("</rss>"));
+
+        // Without frontmatter, it's just a file
+        assert_eq!(split_frontmatter("<rss></rss>"), None);
+    }
+
+    #[test]
+    fn test_frontmatter_fields() {
+        let fields = frontmatter_fields(Path::new("index.md"), Path::new("./testdata")).unwrap().unwrap();
+        assert_eq!(fields, vec![
+            ("layout".to_string(), "_layouts/post.html".to_string()),
+            ("published".to_string(), "true".to_string()),
+            ("title".to_string(), "Pest Toast".to_string()),
+        ]);
+
+        // No frontmatter at all
+        assert_eq!(frontmatter_fields(Path::new("dir/subdir.md"), Path::new("./testdata")).unwrap(), None);
+
+        // Frontmatter that won't parse
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("bad.md"), "+++\ntitle = \"Unterminated\n+++\nBody").unwrap();
+        let result = frontmatter_fields(Path::new("bad.md"), dir.path());
+        assert!(matches!(result, Err(RenderError::FrontmatterParse(_, _))));
+    }
+
+    #[test]
+    fn test_canonical_link() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("_layouts")).unwrap();
+        fs::create_dir_all(dir.path().join("posts")).unwrap();
+        fs::write(dir.path().join("_layouts/page.html"), "<html><head><title>$title</title></head><body>$content</body></html>").unwrap();
+        fs::write(dir.path().join("posts/crossposted.md"), "+++\nlayout = \"_layouts/page.html\"\ncanonical = \"https://elsewhere.example.com/post?a=1&b=2\"\n+++\nHi").unwrap();
+        fs::write(dir.path().join("posts/mine.md"), "+++\nlayout = \"_layouts/page.html\"\n+++\nHi").unwrap();
+        fs::write(dir.path().join("posts/index.md"), "+++\nlayout = \"_layouts/page.html\"\n+++\nHi").unwrap();
+        fs::write(dir.path().join("posts/reindex.md"), "+++\nlayout = \"_layouts/page.html\"\n+++\nHi").unwrap();
+        let render = |source: &str, config: &SiteConfig| {
+            let Ok(RenderOutput::Rendered(_, contents)) = file_render(source.into(), dir.path(), config) else { panic!() };
+            contents
+        };
+
+        // Nothing to go on, so no link
+        let config = SiteConfig::default();
+        assert!(!render("posts/mine.md", &config).contains("canonical"));
+
+        // An explicit canonical always wins
+        assert!(render("posts/crossposted.md", &config).contains("<link rel=\"canonical\" href=\"https://elsewhere.example.com/post?a=1&amp;b=2\">"));
+
+        // Otherwise it comes from the base url
+        let config = SiteConfig { base_url: Some("https://example.com/".to_string()), ..SiteConfig::default() };
+        assert!(render("posts/mine.md", &config).contains("<link rel=\"canonical\" href=\"https://example.com/posts/mine.html\">"));
+        assert!(render("posts/index.md", &config).contains("<link rel=\"canonical\" href=\"https://example.com/posts/\">"));
+        assert!(render("posts/reindex.md", &config).contains("<link rel=\"canonical\" href=\"https://example.com/posts/reindex.html\">"));
+        assert!(render("posts/crossposted.md", &config).contains("href=\"https://elsewhere.example.com/post?a=1&amp;b=2\""));
+    }
+
+    #[test]
+    fn test_gfm_extras() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("page.md"), "Said so.[^1] ~~Not~~ at https://example.com\n\n- [x] Done\n\n[^1]: Somewhere.\n").unwrap();
+        let render = |config: &SiteConfig| match file_render("page.md".into(), dir.path(), config).unwrap() {
+            RenderOutput::Rendered(_, html) => html,
+            other => panic!("{:?}", other)
+        };
+
+        let html = render(&SiteConfig::default());
+        assert!(html.contains("<sup><a href=\"#user-content-fn-1\" id=\"user-content-fnref-1\" data-footnote-ref=\"\" aria-describedby=\"footnote-label\">1</a></sup>"));
+        assert!(html.contains("<section data-footnotes=\"\" class=\"footnotes\">"));
+        assert!(html.contains("<del>Not</del>"));
+        assert!(html.contains("<a href=\"https://example.com\">https://example.com</a>"));
+        assert!(html.contains("<input type=\"checkbox\" disabled=\"\" checked=\"\" />"));
+
+        // Strict CommonMark leaves it all as text
+        let html = render(&SiteConfig { gfm: false, ..SiteConfig::default() });
+        assert!(!html.contains("<sup>") && !html.contains("<del>") && !html.contains("<a href=\"https") && !html.contains("<input"));
+        assert!(html.contains("~~Not~~") && html.contains("[x] Done"));
+    }
+
+    #[test]
+    fn test_code_line_numbers() {
+        let html = markdown::to_html("Some `inline` code\n\n```rust\nfn main() {\n\n    println!(\"<hi>\");\n}\n```\n");
+        let numbered = number_code_lines(&html);
+        assert_eq!(numbered.matches("<span class=\"line\"").count(), 4);
+        assert!(numbered.contains("<code>inline</code>")); // Inline code is left alone
+        assert!(numbered.contains(concat!(
+            "<pre><code class=\"language-rust\"><span class=\"line\" data-line=\"1\">fn main() {</span>\n",
+            "<span class=\"line\" data-line=\"2\"></span>\n",
+            "<span class=\"line\" data-line=\"3\">    println!(&quot;&lt;hi&gt;&quot;);</span>\n",
+            "<span class=\"line\" data-line=\"4\">}</span>\n</code></pre>"
+        )));
+
+        // Off by default
+        let RenderOutput::Rendered(_, contents) = render_file("index.html") else { panic!() };
+        assert!(!contents.contains("data-line"));
+        let config = SiteConfig { line_numbers: true, ..SiteConfig::default() };
+        let Ok(RenderOutput::Rendered(_, contents)) = server_render("index.html".into(), Path::new("./testdata"), &config) else { panic!() };
+        assert!(contents.contains("data-line=\"1\""));
+    }
+
+    #[test]
+    fn test_preview_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("_layouts")).unwrap();
+        fs::write(dir.path().join("_layouts/theme.html"),
+                  "<html><head><title>$title</title><script>$(go)</script></head><body><h1>$site_title</h1>$content<footer>$author</footer></body></html>").unwrap();
+        let config = SiteConfig { title: Some("My Site".to_string()), ..SiteConfig::default() };
+        let html = preview_layout(Path::new("_layouts/theme.html"), dir.path(), &config).unwrap();
+
+        assert!(html.contains("<title>Sample page title</title>"));
+        assert!(html.contains("<h1>My Site</h1>"));
+        assert!(html.contains("<footer>[author]</footer>"));
+        assert!(html.contains("<script>$(go)</script>"));
+        assert!(html.contains("<h2>")); // Sample content made it in
+        assert!(!html.contains("$content"));
+    }
+
+    #[test]
+    fn test_extra_variables() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("_layouts")).unwrap();
+        fs::write(dir.path().join("_layouts/page.html"),
+                  "<meta content=\"$description\">$title by $author ($author_url, $views)<script>$(go)</script>$content").unwrap();
+        fs::write(dir.path().join("page.md"),
+                  "+++\nlayout = \"_layouts/page.html\"\ntitle = \"Hi\"\nauthor = \"Ross\"\nviews = 12\nunused = \"x\"\n+++\nCost: $5 or $author").unwrap();
+
+        let RenderOutput::Rendered(_, html) = file_render("page.md".into(), dir.path(), &SiteConfig::default()).unwrap() else { panic!() };
+        // Missing ones stay as they are, and nothing in the content is touched
+        assert_eq!(html, "<meta content=\"$description\">Hi by Ross ($author_url, 12)<script>$(go)</script><p>Cost: $5 or $author</p>");
+
+        // The frontmatter can't stand in for the page's body
+        fs::write(dir.path().join("page.md"), "+++\nlayout = \"_layouts/page.html\"\ncontent = \"Fake\"\n+++\nReal").unwrap();
+        let RenderOutput::Rendered(_, html) = file_render("page.md".into(), dir.path(), &SiteConfig::default()).unwrap() else { panic!() };
+        assert!(html.ends_with("<p>Real</p>"));
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let layout = "<h1>$title</h1>{% if author %}<p class=\"byline\">By <em>$author</em></p>{% endif %}<main>$content</main>";
+        let with_author = [("title", "Hi".to_string()), ("author", "Ross".to_string())];
+        assert_eq!(substitute(&apply_conditionals(layout, &with_author), &with_author),
+                   "<h1>Hi</h1><p class=\"byline\">By <em>Ross</em></p><main>$content</main>");
+
+        // Missing and empty are both false
+        assert_eq!(apply_conditionals(layout, &[("title", "Hi".to_string())]), "<h1>$title</h1><main>$content</main>");
+        assert_eq!(apply_conditionals(layout, &[("author", String::new())]), "<h1>$title</h1><main>$content</main>");
+
+        // Whitespace in tags is fine, and anything we don't understand passes through
+        assert_eq!(apply_conditionals("{%if a%}A{%   endif %}{% raw %}", &[("a", "1".to_string())]), "A{% raw %}");
+        assert_eq!(apply_conditionals("{% if a %}never closed", &[]), "{% if a %}never closed");
+
+        // And it happens when a page is rendered into its layout
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("layout.html"), "{% if site_title %}<header>$site_title</header>{% endif %}$content").unwrap();
+        fs::write(dir.path().join("page.md"), "+++\nlayout = \"layout.html\"\n+++\nHi").unwrap();
+        let Ok(RenderOutput::Rendered(_, html)) = file_render("page.md".into(), dir.path(), &SiteConfig::default()) else { panic!() };
+        assert_eq!(html, "<p>Hi</p>");
+    }
+
+    #[test]
+    fn test_resolve_source() {
+        let root = Path::new("./testdata");
+        let resolve = |path: &str| resolve_source(Path::new(path), root).unwrap();
+        assert_eq!(resolve("_layouts/post.html"), ResolvedSource::Skipped);
+        assert_eq!(resolve("../src/main.rs"), ResolvedSource::Skipped);
+        assert_eq!(resolve("dir"), ResolvedSource::Dir);
+        assert_eq!(resolve("raw.txt"), ResolvedSource::Raw);
+        assert_eq!(resolve("plain.html"), ResolvedSource::Raw);
+        assert_eq!(resolve("index.md"), ResolvedSource::Markdown);
+        assert_eq!(resolve("mixed.html"), ResolvedSource::Markdown);
+        assert_eq!(resolve("feed.xml"), ResolvedSource::Template);
+        assert_eq!(resolve("index.html"), ResolvedSource::MarkdownFallback(PathBuf::from("index.md")));
+        assert_eq!(resolve("nothing.txt"), ResolvedSource::Missing);
+
+        // The server wants markdown asked for as html; a build goes the other way
+        let config = SiteConfig::default();
+        let file = |path: &str| file_render(PathBuf::from(path), root, &config).unwrap();
+        assert_eq!(render_file("index.md"), RenderOutput::NoOutput);
+        assert!(matches!(file("index.md"), RenderOutput::Rendered(path, _) if path == Path::new("index.html")));
+        assert!(matches!(render_file("index.html"), RenderOutput::Rendered(_, _)));
+        assert_eq!(file("index.html"), RenderOutput::NoOutput);
+        assert_eq!(file("raw.txt"), RenderOutput::RawFile(PathBuf::from("raw.txt")));
+        assert_eq!(file("_skipped.md"), RenderOutput::NoOutput);
+        assert_eq!(file("dir"), RenderOutput::Dir(PathBuf::from("dir")));
+    }
+
+    #[test]
+    fn test_is_noindex() {
+        assert!(is_noindex("<html><head><META NAME='robots' CONTENT='noindex, nofollow'></head></html>"));
+        assert!(is_noindex("<html><head><meta content=\"noindex\" name=\"robots\"/></head></html>"));
+        assert!(is_noindex(&format!("{}\n<p>No head</p>", NOINDEX_META)));
+        // Only the robots meta counts
+        assert!(!is_noindex("<html><head><title>Why noindex matters</title><!-- noindex --></head></html>"));
+        assert!(!is_noindex("<html><head><meta name=\"description\" content=\"noindex\"></head></html>"));
+        assert!(!is_noindex("<html><head></head><body><meta name=\"robots\" content=\"noindex\"></body></html>"));
+    }
+
+    #[test]
+    fn test_hidden_mode() {
+        let root = Path::new("./testdata");
+        let config = SiteConfig::default();
+        assert!(matches!(file_render(PathBuf::from("hidden.md"), root, &config).unwrap(), RenderOutput::Hidden(_, _)));
+
+        let config = SiteConfig { hidden_mode: HiddenMode::Noindex, ..SiteConfig::default() };
+        let RenderOutput::Rendered(path, contents) = file_render(PathBuf::from("hidden.md"), root, &config).unwrap() else { panic!() };
+        assert_eq!(path, PathBuf::from("hidden.html"));
+        let head = &contents[..contents.find("</head>").unwrap()];
+        assert!(head.contains("<meta name=\"robots\" content=\"noindex\">"));
+
+        // Published pages are left alone, and the server still serves hidden ones as hidden
+        let RenderOutput::Rendered(_, contents) = file_render(PathBuf::from("not_hidden.md"), root, &config).unwrap() else { panic!() };
+        assert!(!contents.contains("noindex"));
+        assert!(matches!(server_render(PathBuf::from("hidden.html"), root, &config).unwrap(), RenderOutput::Hidden(_, _)));
+
+        // A hidden feed can't have a meta tag in it, so it stays hidden
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("feed.xml"), "+++\npublished = false\n+++\n<rss></rss>").unwrap();
+        let output = file_render(PathBuf::from("feed.xml"), dir.path(), &config).unwrap();
+        assert_eq!(output, RenderOutput::Hidden(PathBuf::from("feed.xml"), "<rss></rss>".to_string()));
+    }
+
+    #[test]
+    fn test_figures() {
+        let html = markdown::to_html("![A cat](cat.png)\n\nSome text with ![an icon](icon.png) in it\n\n![](plain.png)\n\n![A dog](dog.png)\n");
+        let figures = number_figures(&html);
+        assert!(figures.contains("<figure><img src=\"cat.png\" alt=\"A cat\" /><figcaption>Figure 1. A cat</figcaption></figure>"));
+        assert!(figures.contains("<figure><img src=\"dog.png\" alt=\"A dog\" /><figcaption>Figure 2. A dog</figcaption></figure>"));
+        assert!(figures.contains("<p>Some text with <img src=\"icon.png\" alt=\"an icon\" /> in it</p>"));
+        assert!(figures.contains("<p><img src=\"plain.png\" alt=\"\" /></p>"));
+
+        // Only when asked for, and counting starts over on each page
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("page.md"), "![One](1.png)\n\n![Two](2.png)\n").unwrap();
+        let render = |config: &SiteConfig| match file_render("page.md".into(), dir.path(), config).unwrap() {
+            RenderOutput::Rendered(_, contents) => contents,
+            other => panic!("{:?}", other)
+        };
+        assert!(!render(&SiteConfig::default()).contains("<figure>"));
+        let config = SiteConfig { figures: true, ..SiteConfig::default() };
+        render(&config);
+        let again = render(&config);
+        assert!(again.contains("Figure 1. One") && again.contains("Figure 2. Two"));
+    }
+
+    #[test]
+    fn test_section_index() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("posts")).unwrap();
+        fs::create_dir_all(dir.path().join("_layouts")).unwrap();
+        fs::write(dir.path().join("_layouts/page.html"), "<html><head><title>$title</title></head><body>$content</body></html>").unwrap();
+        fs::write(dir.path().join("posts/_index.md"), "+++\nlayout = \"_layouts/page.html\"\ntitle = \"Posts\"\nsort_by = \"date\"\nreverse = true\n+++\nAll the posts").unwrap();
+        fs::write(dir.path().join("posts/first.md"), "+++\ntitle = \"First\"\ndate = 2024-01-01\n+++\nOne").unwrap();
+        fs::write(dir.path().join("posts/second.md"), "+++\ntitle = \"Second & last\"\ndate = 2024-02-01\n+++\nTwo").unwrap();
+        fs::write(dir.path().join("posts/wip.md"), "+++\ndraft = true\n+++\nNot yet").unwrap();
+        fs::write(dir.path().join("posts/notes.txt"), "Not a page").unwrap();
+
+        let config = SiteConfig::default();
+        let RenderOutput::Rendered(path, contents) = file_render("posts/_index.md".into(), dir.path(), &config).unwrap() else { panic!() };
+        assert_eq!(path, PathBuf::from("posts/index.html"));
+        assert!(contents.contains("<title>Posts</title>"));
+        assert!(contents.contains(concat!(
+            "<p>All the posts</p><ul class=\"section-index\">\n",
+            "<li><a href=\"second.html\">Second &amp; last</a></li>\n",
+            "<li><a href=\"first.html\">First</a></li>\n</ul>"
+        )));
+
+        // The server finds it at the dir's index.html
+        let served = server_render("posts/index.html".into(), dir.path(), &config).unwrap();
+        assert_eq!(served, RenderOutput::Rendered(path, contents));
+
+        // A list of patterns picks which pages; a real index.md takes over from it entirely
+        fs::write(dir.path().join("posts/_index.md"), "+++\npages = [\"f*\"]\n+++\n").unwrap();
+        let RenderOutput::Rendered(_, contents) = file_render("posts/_index.md".into(), dir.path(), &config).unwrap() else { panic!() };
+        assert!(contents.contains("first.html") && !contents.contains("second.html"));
+        fs::write(dir.path().join("posts/index.md"), "Handmade").unwrap();
+        assert_eq!(file_render("posts/_index.md".into(), dir.path(), &config).unwrap(), RenderOutput::NoOutput);
+
+        assert!(glob_match("*.md", "a.md") && glob_match("2024-*-*.md", "2024-01-x.md") && !glob_match("*.md", "a.txt"));
+    }
+
+    #[test]
+    fn test_listing_weights() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("_index.md"), "").unwrap();
+        fs::write(dir.path().join("a.md"), "+++\ntitle = \"A\"\n+++\n").unwrap();
+        fs::write(dir.path().join("b.md"), "+++\ntitle = \"B\"\nweight = 10\n+++\n").unwrap();
+        fs::write(dir.path().join("c.md"), "+++\ntitle = \"C\"\nweight = 1\n+++\n").unwrap();
+        fs::write(dir.path().join("d.md"), "+++\ntitle = \"D\"\norder = -5\n+++\n").unwrap();
+        fs::write(dir.path().join("e.md"), "No frontmatter at all").unwrap();
+
+        let RenderOutput::Rendered(_, contents) = file_render("_index.md".into(), dir.path(), &SiteConfig::default()).unwrap() else { panic!() };
+        let order: Vec<&str> = contents.lines().filter_map(|l| l.strip_prefix("<li><a href=\"")).map(|l| &l[..1]).collect();
+        assert_eq!(order, vec!["d", "c", "b", "a", "e"]);
+
+        // Unweighted pages fall back to date, then name
+        let mut pages: Vec<ListedPage> = [("x", None, Some("2024-02-01")), ("y", None, Some("2024-01-01")), ("z", Some(1), None), ("w", None, None)]
+            .into_iter()
+            .map(|(name, weight, date)| ListedPage { key: None, weight, date: date.and_then(parse_date), title: name.to_string(), name: name.to_string() })
+            .collect();
+        sort_listing(&mut pages, false);
+        assert_eq!(pages.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), vec!["z", "y", "x", "w"]);
+    }
+
+    #[test]
+    fn test_page_dates() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("layout.html"), "<time>$date</time>$content").unwrap();
+        let page = |name: &str, date: &str| {
+            fs::write(dir.path().join(name), format!("+++\nlayout = \"layout.html\"\n{}\n+++\nHi", date)).unwrap();
+            file_render(name.into(), dir.path(), &SiteConfig::default())
+        };
+        let rendered = |output| match output {
+            Ok(RenderOutput::Rendered(_, contents)) => contents,
+            other => panic!("{:?}", other)
+        };
+        assert_eq!(rendered(page("a.md", "date = \"2024-05-01\"")), "<time>May 1, 2024</time><p>Hi</p>");
+        assert_eq!(rendered(page("b.md", "date = 2024-03-09T10:15:00-05:00")), "<time>March 9, 2024</time><p>Hi</p>");
+        assert_eq!(rendered(page("c.md", "date = \"2023-12-25 08:00\"")), "<time>December 25, 2023</time><p>Hi</p>");
+        assert_eq!(rendered(page("d.md", "")), "<time></time><p>Hi</p>");
+        assert!(matches!(page("bad.md", "date = \"last Tuesday\""), Err(RenderError::InvalidDate(date, path)) if date == "last Tuesday" && path == Path::new("bad.md")));
+        fs::remove_file(dir.path().join("bad.md")).unwrap();
+
+        // Sorting by date goes by the date, not how it's written, and undated pages go last
+        fs::write(dir.path().join("_index.md"), "+++\nsort_by = \"date\"\n+++\n").unwrap();
+        let index = rendered(file_render("_index.md".into(), dir.path(), &SiteConfig::default()));
+        let order: Vec<&str> = index.lines().filter_map(|l| l.strip_prefix("<li><a href=\"")).map(|l| &l[..1]).collect();
+        assert_eq!(order, vec!["c", "b", "a", "d"]);
+    }
+
+    #[test]
+    fn test_layout_for_type() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("_layouts")).unwrap();
+        fs::write(dir.path().join("_layouts/post.html"), "<article>$content</article>").unwrap();
+        fs::write(dir.path().join("_layouts/special.html"), "<aside>$content</aside>").unwrap();
+        fs::write(dir.path().join("typed.md"), "+++\ntype = \"post\"\n+++\nHi").unwrap();
+        fs::write(dir.path().join("explicit.md"), "+++\ntype = \"post\"\nlayout = \"_layouts/special.html\"\n+++\nHi").unwrap();
+        fs::write(dir.path().join("unknown.md"), "+++\ntype = \"recipe\"\n+++\nHi").unwrap();
+
+        let config = SiteConfig { layouts: [("post".to_string(), "_layouts/post.html".to_string())].into(), ..SiteConfig::default() };
+        let render = |path: &str| match file_render(path.into(), dir.path(), &config).unwrap() {
+            RenderOutput::Rendered(_, contents) => contents,
+            other => panic!("{:?}", other)
+        };
+        assert_eq!(render("typed.md"), "<article><p>Hi</p></article>");
+        assert_eq!(render("explicit.md"), "<aside><p>Hi</p></aside>");
+        assert_eq!(render("unknown.md"), "<p>Hi</p>"); // No mapping, no layout
+
+        // With a default, everything without a layout of its own gets it, frontmatter or not
+        fs::write(dir.path().join("_layouts/default.html"), "<main>$content</main>").unwrap();
+        fs::write(dir.path().join("bare.md"), "Hi").unwrap();
+        let config = SiteConfig { default_layout: Some("_layouts/default.html".to_string()), ..config };
+        let render = |path: &str| match file_render(path.into(), dir.path(), &config).unwrap() {
+            RenderOutput::Rendered(_, contents) => contents,
+            other => panic!("{:?}", other)
+        };
+        assert_eq!(render("typed.md"), "<article><p>Hi</p></article>");
+        assert_eq!(render("unknown.md"), "<main><p>Hi</p></main>");
+        assert_eq!(render("bare.md"), "<main><p>Hi</p></main>");
+
+        // Unless a page opts out
+        fs::write(dir.path().join("none.md"), "+++\nlayout = \"none\"\n+++\nHi").unwrap();
+        fs::write(dir.path().join("empty.md"), "+++\ntype = \"post\"\nlayout = \"\"\n+++\nHi").unwrap();
+        assert_eq!(render("none.md"), "<p>Hi</p>");
+        assert_eq!(render("empty.md"), "<p>Hi</p>");
+    }
+
+    #[test]
+    fn test_toc() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("_layouts")).unwrap();
+        fs::write(dir.path().join("_layouts/doc.html"), "<nav>$toc</nav>$content").unwrap();
+        let page = "# Guide\n\n## Install & run\n\n### On *Linux*\n\n## Usage\n\n## Usage\n\n```\n<h2>not a heading</h2>\n```\n";
+        fs::write(dir.path().join("doc.md"), format!("+++\nlayout = \"_layouts/doc.html\"\n+++\n{}", page)).unwrap();
+        fs::write(dir.path().join("plain.md"), format!("+++\nlayout = \"_layouts/doc.html\"\ntoc = false\n+++\n{}", page)).unwrap();
+        let render = |path: &str| match file_render(path.into(), dir.path(), &SiteConfig::default()).unwrap() {
+            RenderOutput::Rendered(_, html) => html,
+            other => panic!("{:?}", other)
+        };
+
+        let html = render("doc.md");
+        let (toc, content) = html.split_once("</nav>").unwrap();
+        assert_eq!(toc, concat!("<nav><ul><li><a href=\"#guide\">Guide</a>",
+                                "<ul><li><a href=\"#install-run\">Install &amp; run</a><ul><li><a href=\"#on-linux\">On Linux</a></li></ul></li>",
+                                "<li><a href=\"#usage\">Usage</a></li><li><a href=\"#usage-2\">Usage</a></li></ul></li></ul>"));
+        assert!(content.starts_with("<h1 id=\"guide\">Guide</h1>\n<h2 id=\"install-run\">Install &amp; run</h2>\n<h3 id=\"on-linux\">On <em>Linux</em></h3>"));
+        assert!(content.contains("<h2 id=\"usage-2\">Usage</h2>") && content.contains("&lt;h2&gt;not a heading"));
+
+        // Opting out leaves the headings alone
+        let html = render("plain.md");
+        assert!(html.starts_with("<nav></nav><h1>Guide</h1>"));
+    }
+
+    #[test]
+    fn test_stylesheets() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("assets")).unwrap();
+        fs::write(dir.path().join("assets/_colors.scss"), "$accent: #336699;").unwrap();
+        fs::write(dir.path().join("assets/style.scss"), "@use 'colors';\na { color: colors.$accent; b { font-weight: bold } }").unwrap();
+        fs::write(dir.path().join("assets/plain.sass"), "p\n  margin: 0").unwrap();
+        fs::write(dir.path().join("assets/broken.scss"), "a { color: ").unwrap();
+        let config = SiteConfig::default();
+
+        let RenderOutput::Stylesheet(path, css) = file_render("assets/style.scss".into(), dir.path(), &config).unwrap() else { panic!() };
+        assert_eq!(path, Path::new("assets/style.css"));
+        assert_eq!(css, "a {\n  color: #336699;\n}\na b {\n  font-weight: bold;\n}\n");
+        assert!(matches!(file_render("assets/plain.sass".into(), dir.path(), &config).unwrap(), RenderOutput::Stylesheet(_, css) if css.contains("margin: 0")));
+        assert_eq!(file_render("assets/_colors.scss".into(), dir.path(), &config).unwrap(), RenderOutput::NoOutput);
+        assert!(matches!(file_render("assets/broken.scss".into(), dir.path(), &config), Err(RenderError::Stylesheet(_, _))));
+
+        // The server is asked for the css
+        assert_eq!(server_render("assets/style.css".into(), dir.path(), &config).unwrap(), RenderOutput::Stylesheet(path, css));
+        assert_eq!(server_render("assets/style.scss".into(), dir.path(), &config).unwrap(), RenderOutput::NoOutput);
+    }
+
+    #[test]
+    fn test_yaml_frontmatter() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("_layouts")).unwrap();
+        fs::write(dir.path().join("_layouts/post.html"), "<title>$title</title>$content").unwrap();
+        fs::write(dir.path().join("yaml.md"), "---\nlayout: _layouts/post.html\ntitle: From YAML\ntags: [a, b]\n---\nHi").unwrap();
+        fs::write(dir.path().join("bad.md"), "---\ntitle: [unclosed\n---\nHi").unwrap();
+
+        let config = SiteConfig::default();
+        let RenderOutput::Rendered(_, contents) = file_render("yaml.md".into(), dir.path(), &config).unwrap() else { panic!() };
+        assert_eq!(contents, "<title>From YAML</title><p>Hi</p>");
+
+        let fields = frontmatter_fields(Path::new("yaml.md"), dir.path()).unwrap().unwrap();
+        assert!(fields.contains(&("title".to_string(), "From YAML".to_string())));
+        assert!(has_yaml_frontmatter("---\ntitle: x\n---\n") && !has_yaml_frontmatter("+++\ntitle = \"x\"\n+++\n"));
+
+        let result = file_render("bad.md".into(), dir.path(), &config);
+        assert!(matches!(result, Err(RenderError::YamlFrontmatterParse(_, _))));
+    }
+
+    #[test]
+    fn test_edit_frontmatter() {
+        let page = "+++\n# The basics\ntitle = \"Hello\" # keep this short\npublished = true\nlayout = \"_layouts/post.html\"\n+++\n# Body\n\nText\n";
+        let edited = edit_frontmatter(page, "published", Some(false.into())).unwrap();
+        assert_eq!(edited, "+++\n# The basics\ntitle = \"Hello\" # keep this short\npublished = false\nlayout = \"_layouts/post.html\"\n+++\n# Body\n\nText\n");
+
+        // New keys go at the end, and removing one leaves the rest alone
+        let edited = edit_frontmatter(&edited, "draft", Some(true.into())).unwrap();
+        assert!(edited.contains("layout = \"_layouts/post.html\"\ndraft = true\n+++\n"));
+        let edited = edit_frontmatter(&edited, "published", None).unwrap();
+        assert_eq!(edited, "+++\n# The basics\ntitle = \"Hello\" # keep this short\nlayout = \"_layouts/post.html\"\ndraft = true\n+++\n# Body\n\nText\n");
+
+        assert_eq!(edit_frontmatter("Just text\n", "published", Some(false.into())).unwrap(), "+++\npublished = false\n+++\nJust text\n");
+    }
+
+    #[test]
+    fn test_field_values() {
+        let page = "+++\ntitle = \"Hello\" # keep this short\ntags = [\"a\", \"b\"]\n+++\nText";
+        assert_eq!(frontmatter_field(page, "title").as_deref(), Some("\"Hello\""));
+        assert_eq!(frontmatter_field(page, "tags").as_deref(), Some("[\"a\", \"b\"]"));
+        assert_eq!(frontmatter_field(page, "author"), None);
+        assert_eq!(frontmatter_field("Text", "title"), None);
+
+        assert_eq!(parse_field_value("true").as_bool(), Some(true));
+        assert_eq!(parse_field_value(" 3 ").as_integer(), Some(3));
+        assert_eq!(parse_field_value("\"quoted\"").as_str(), Some("quoted"));
+        assert_eq!(parse_field_value("just words").as_str(), Some("just words"));
+        assert_eq!(parse_field_value("2024-05-01").as_str(), Some("2024-05-01"));
+    }
+
+    #[test]
+    fn test_html_links() {
+        let html = "<p><a class=\"x\" href=\"a.html?x=1&amp;y=2\">A</a> <img alt=\"b\" src='b.png'>\n<link href=\"style.css\"><a data-href=\"no.html\">C</a><abbr href=\"no.html\">D</abbr></p>";
+        assert_eq!(html_links(html), vec!["a.html?x=1&y=2", "b.png"]);
+    }
+}
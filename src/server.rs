@@ -1,59 +1,600 @@
 use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::mpsc::Sender;
 use axum::body::Body;
-use axum::extract::State;
-use axum::http::Uri;
-use axum::response::{IntoResponse, Response};
+use axum::extract::{Path, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::extract::Request;
+use axum::http::{HeaderValue, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::Response;
 use axum::Router;
 use axum::routing::get;
-use crate::renderer::{server_render, RenderOutput};
+use tokio::sync::oneshot;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use crate::config::SiteConfig;
+use crate::fleen_app::FleenError;
+use crate::live_reload::{watch_for_changes, with_live_reload, RELOAD_PATH};
+use crate::renderer::{declared_content_type, preview_layout, resolve_source, server_render, RenderOutput, ResolvedSource};
 
-pub async fn start_server(root: PathBuf, port: u32) {
-    let app = Router::new()
+/// How many ports past the requested one to try before taking any free port
+const FALLBACK_PORTS: u16 = 10;
+
+/// Bind the preview server's port. With fallback on, a busy port means trying the next few and
+/// then letting the OS pick one; check the listener's address for where it ended up. Binding
+/// happens up front, rather than in the server task, so a failure can be reported. Only this
+/// machine can reach the server unless `lan_access` is on, in which case anything on the network can.
+pub fn bind_port(port: u16, fallback: bool, lan_access: bool) -> Result<std::net::TcpListener, FleenError> {
+    let host = if lan_access { Ipv4Addr::UNSPECIFIED } else { Ipv4Addr::LOCALHOST };
+    let bind = |port: u16| std::net::TcpListener::bind((host, port));
+    let listener = match bind(port) {
+        Ok(listener) => listener,
+        Err(e) if fallback && e.kind() == std::io::ErrorKind::AddrInUse => {
+            (1..=FALLBACK_PORTS).filter_map(|n| port.checked_add(n))
+                .find_map(|next| bind(next).ok())
+                .map_or_else(|| bind(0), Ok)
+                .map_err(|e| FleenError::ServerBind(port, e.to_string()))?
+        }
+        Err(e) => return Err(FleenError::ServerBind(port, e.to_string()))
+    };
+    // Tokio needs this to take the listener over
+    listener.set_nonblocking(true).map_err(|e| FleenError::ServerBind(port, e.to_string()))?;
+    Ok(listener)
+}
+
+/// This machine's address on the network, for telling other devices where the server is. Connecting
+/// a UDP socket doesn't send anything, but it does make the OS pick the interface it would route through.
+pub fn lan_address() -> Option<IpAddr> {
+    let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 9)).ok()?;
+    socket.local_addr().ok().map(|a| a.ip()).filter(|ip| !ip.is_loopback() && !ip.is_unspecified())
+}
+
+/// Serve the site. With live reload, pages get a script that reloads them whenever the site
+/// changes; without it, nothing watches the files at all. Each request is reported on `requests`.
+/// Sending (or dropping) `shutdown` stops it, once any requests in flight have been answered.
+pub async fn start_server(root: PathBuf, listener: std::net::TcpListener, live_reload: bool, requests: Sender<String>, shutdown: oneshot::Receiver<()>) -> Result<(), FleenError> {
+    let concurrency = SiteConfig::load(&root).map(|c| c.server_concurrency).unwrap_or(SiteConfig::default().server_concurrency);
+    let version = Arc::new(AtomicU64::new(0));
+    let app = if live_reload { with_live_reload(routes(root.clone()), version.clone()) } else { routes(root.clone()) };
+    let app = with_request_log(with_limit(app, concurrency), requests);
+
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    // The watcher runs as long as the server does, and stops with it
+    tokio::select! {
+        served = axum::serve(listener, app).with_graceful_shutdown(async { let _ = shutdown.await; }) => served?,
+        _ = watch_for_changes(root, version), if live_reload => {}
+    }
+    Ok(())
+}
+
+pub fn routes(root: PathBuf) -> Router {
+    let redirects = Arc::new(load_redirects(&root));
+    let root_redirects = redirects.clone();
+    Router::new()
         .route("/", get(|State(root): State<PathBuf>| async move {
             // We need a separate route for the default path because {*p} must match at least one thing
-            serve_path("index.html".to_string(), root.clone())
+            serve_path("index.html".to_string(), root.clone(), &root_redirects)
+        }))
+        .route("/_fleen/layout/{*layout}", get(|State(root): State<PathBuf>, Path(layout): Path<String>| async move {
+            serve_layout_preview(layout, root)
         }))
         .route("/{*path}", get(|State(root): State<PathBuf>, uri: Uri| async move {
-            serve_path(String::from(uri.path()), root.clone())
+            serve_path(String::from(uri.path()), root.clone(), &redirects)
         }))
-        .with_state(root);
+        .with_state(root)
+}
 
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+/// Only handle so many requests at once. Requests over the limit wait for a free slot instead
+/// of failing, so a page with lots of assets just loads a little slower. This has to be the
+/// global layer: axum applies layers to each route (and each request, for stateless routes),
+/// and a plain ConcurrencyLimitLayer would get a fresh limit every time.
+fn with_limit(router: Router, limit: usize) -> Router {
+    router.layer(GlobalConcurrencyLimitLayer::new(limit.max(1)))
 }
 
-fn serve_path(path: String, root: PathBuf) -> impl IntoResponse {
-    let path = path.strip_prefix("/").unwrap_or(path.as_str());
-    let render = server_render(path.into(), root.as_ref());
+/// Report each request as a line like "GET /about.html 404", so it's clear what the browser
+/// actually asked for. Live reload asks every second, which would drown everything else out, so
+/// that's left out.
+fn with_request_log(router: Router, requests: Sender<String>) -> Router {
+    router.layer(axum::middleware::from_fn(move |request: Request, next: Next| {
+        let requests = requests.clone();
+        async move {
+            let (method, path) = (request.method().clone(), request.uri().path().to_string());
+            let response = next.run(request).await;
+            if path != RELOAD_PATH {
+                // If nobody's listening, there's nobody to tell
+                let _ = requests.send(format!("{} {} {}", method, path, response.status().as_u16()));
+            }
+            response
+        }
+    }))
+}
 
-    match render {
-        Ok(RenderOutput::Rendered(_, content)) |
-        Ok(RenderOutput::Hidden(_, content)) => {
-            // We rendered some output so spit it back
+/// A layout wrapped around sample content, for the "Preview layout" action
+fn serve_layout_preview(layout: String, root: PathBuf) -> Response {
+    let layout = PathBuf::from(layout);
+    if layout.components().any(|c| !matches!(c, std::path::Component::Normal(_))) {
+        return not_found()
+    }
+    let html = SiteConfig::load(&root).map_err(|e| e.to_string())
+        .and_then(|config| preview_layout(&layout, &root, &config).map_err(|e| e.to_string()));
+    match html {
+        Ok(html) => Response::builder().status(200).header(CONTENT_TYPE, HTML).body(Body::from(html)).unwrap(),
+        Err(err) => server_error(err)
+    }
+}
+
+const HTML: &str = "text/html; charset=utf-8";
+
+/// What to tell the browser a file is, going by its extension. Anything we don't know is just
+/// bytes, which browsers will offer to download.
+fn mime_type(path: &std::path::Path) -> &'static str {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    match extension.as_str() {
+        "html" | "htm" => HTML,
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "application/javascript",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "pdf" => "application/pdf",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream"
+    }
+}
+
+fn ok(content_type: &str, body: impl Into<Body>) -> Response {
+    Response::builder().status(200).header(CONTENT_TYPE, content_type).body(body.into()).unwrap()
+}
+
+fn not_found() -> Response {
+    Response::builder().status(404).header(CONTENT_TYPE, HTML).body(Body::from(include_str!("../templates/404.html"))).unwrap()
+}
+
+/// The site's own 404 page, from `404.md` or `404.html` in the root, or our bundled one if it
+/// hasn't got one. It's rendered like any other page, so it gets its layout.
+fn site_not_found(root: &std::path::Path, config: &SiteConfig) -> Response {
+    let page = match server_render("404.html".into(), root, config) {
+        Ok(RenderOutput::Rendered(_, content) | RenderOutput::Hidden(_, content)) => content.into_bytes(),
+        Ok(RenderOutput::RawFile(file)) => match fs::read(root.join(file)) {
+            Ok(contents) => contents,
+            Err(_) => return not_found()
+        },
+        _ => return not_found()
+    };
+    Response::builder().status(404).header(CONTENT_TYPE, HTML).body(Body::from(page)).unwrap()
+}
+
+fn server_error(err: impl ToString) -> Response {
+    Response::builder().status(500).header(CONTENT_TYPE, HTML).body(Body::from(err.to_string())).unwrap()
+}
+
+/// A rule from the site's `_redirects` file
+#[derive(Clone, Debug, PartialEq)]
+struct Redirect {
+    from: String,
+    to: String,
+    /// 301 or 302 to send the browser to the new path, or 200 to serve the new path's content
+    /// at the old one
+    status: u16
+}
+
+/// Read `_redirects` from the root: one `from to [status]` rule per line, Netlify style, with
+/// 301 if there's no status. Blank lines and `#` comments are fine, and so are rules with a
+/// status that isn't 200 or a redirect, in that they're ignored. Only the preview server
+/// uses it; deploying it is up to the host.
+fn load_redirects(root: &std::path::Path) -> Vec<Redirect> {
+    parse_redirects(&fs::read_to_string(root.join("_redirects")).unwrap_or_default())
+}
+
+fn parse_redirects(contents: &str) -> Vec<Redirect> {
+    contents.lines().filter_map(|line| {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut parts = line.split_whitespace();
+        let (from, to) = (parts.next()?, parts.next()?);
+        let status = match parts.next() {
+            Some(status) => status.trim_end_matches('!').parse().ok()?,
+            None => 301
+        };
+        let code = StatusCode::from_u16(status).ok()?;
+        if code != StatusCode::OK && !code.is_redirection() { return None }
+        Some(Redirect { from: format!("/{}", from.trim_start_matches('/')), to: to.to_string(), status })
+    }).collect()
+}
+
+fn serve_path(path: String, root: PathBuf, redirects: &[Redirect]) -> Response {
+    let request = format!("/{}", path.trim_start_matches('/'));
+    if let Some(redirect) = redirects.iter().find(|r| r.from == request) {
+        return if redirect.status == 200 {
+            // A rewrite; the target's own rules don't apply, so we can't go round in circles
+            serve_path(redirect.to.clone(), root, &[])
+        } else {
             Response::builder()
-                .status(200)
-                .body(Body::from(content)).unwrap()
+                .status(redirect.status)
+                .header("Location", &redirect.to)
+                .body(Body::empty())
+                .unwrap_or_else(server_error)
         }
-        Ok(RenderOutput::RawFile(file)) => {
-            // We were pointed at the raw contents of a file:
-            Response::builder()
-                .status(200)
-                .body(Body::from(fs::read(root.join(file)).unwrap_or_default())).unwrap()
+    }
+    let path = path.strip_prefix("/").unwrap_or(path.as_str());
+    // Read the config fresh every time, so edits to it show up without restarting the server
+    let config = match SiteConfig::load(&root) {
+        Ok(config) => config,
+        Err(err) => return server_error(err)
+    };
+    // A favicon from elsewhere in the site is served from the root, like it will be when built
+    if let Some((source, name)) = config.favicon_output() && path == name && !root.join(&name).exists() {
+        return ok(mime_type(name.as_ref()), fs::read(root.join(source)).unwrap_or_default())
+    }
+    // Browsers ask for /favicon.ico whatever the page links to, so give them whatever icon there is
+    if path == "favicon.ico" && !root.join(path).exists() {
+        let icon = config.favicon_output().map_or_else(|| PathBuf::from("favicon.png"), |(source, _)| source);
+        if let Ok(contents) = fs::read(root.join(&icon)) {
+            return ok(mime_type(&icon), contents)
+        }
+    }
+    let render = server_render(path.into(), root.as_ref(), &config);
+    // Rendered output goes out as whatever its frontmatter says it is, if it says and it's something
+    // a header can hold. Otherwise it's usually html, but templates make other things.
+    let rendered = |output: PathBuf, content: String| match declared_content_type(path.as_ref(), &root) {
+        Some(content_type) if HeaderValue::from_str(&content_type).is_ok() => ok(&content_type, content),
+        Some(content_type) => {
+            eprintln!("Ignoring content_type {:?} in {}, which isn't a valid header", content_type, path);
+            ok(mime_type(&output), content)
         }
+        None => ok(mime_type(&output), content)
+    };
+
+    match render {
+        // We rendered some output so spit it back
+        Ok(RenderOutput::Rendered(output, content)) |
+        Ok(RenderOutput::Hidden(output, content)) => rendered(output, content),
+        Ok(RenderOutput::Draft(output, content)) if config.drafts => rendered(output, content),
+        Ok(RenderOutput::Stylesheet(output, css)) => ok(mime_type(&output), css),
+        // We were pointed at the raw contents of a file:
+        Ok(RenderOutput::RawFile(file)) => ok(mime_type(&file), fs::read(root.join(&file)).unwrap_or_default()),
+        // Asked for something that doesn't exist:
         Ok(RenderOutput::NoOutput) |
-        Ok(RenderOutput::Dir(_)) => {
-            // Asked for something that doesn't exist:
-            Response::builder()
-                .status(404)
-                .body(Body::from(include_str!("../templates/404.html"))).unwrap()
+        Ok(RenderOutput::Draft(_, _)) => site_not_found(&root, &config),
+        // A directory is its index page, if it has one, and otherwise a list of what's in it
+        Ok(RenderOutput::Dir(dir)) => {
+            let index = dir.join("index.html");
+            match resolve_source(&index, &root) {
+                Ok(ResolvedSource::Missing) => ok(HTML, dir_listing(&dir, &root, &config)),
+                _ => serve_path(index.to_string_lossy().to_string(), root, redirects)
+            }
         }
-        Err(err) => {
-            // Oh no!
-            Response::builder()
-                .status(500)
-                .body(Body::from(format!("{}", err))).unwrap()
+        // Oh no!
+        Err(err) => server_error(err)
+    }
+}
+
+/// A page linking to everything in a directory that the server would serve: subdirectories
+/// first, then files by the names they're served as. Only the dev server makes these; a built
+/// site has no such pages.
+fn dir_listing(dir: &std::path::Path, root: &std::path::Path, config: &SiteConfig) -> String {
+    let mut dirs = vec![];
+    let mut files = vec![];
+    for entry in fs::read_dir(root.join(dir)).into_iter().flatten().flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let source = dir.join(&name);
+        if name.starts_with('.') || config.excludes(root, &source) { continue }
+        match resolve_source(&source, root) {
+            Ok(ResolvedSource::Dir) => dirs.push(format!("{}/", name)),
+            // Markdown and stylesheets are served as what they become
+            Ok(ResolvedSource::Markdown) if source.extension().is_some_and(|e| e == "md") => files.push(served_name(&source, "html")),
+            Ok(ResolvedSource::Stylesheet) => files.push(served_name(&source, "css")),
+            Ok(ResolvedSource::Markdown | ResolvedSource::Template | ResolvedSource::Raw) => files.push(name),
+            _ => {}
         }
     }
+    dirs.sort();
+    files.sort();
+
+    let base = format!("/{}", dir.to_string_lossy().trim_matches('/'));
+    let base = base.trim_end_matches('/');
+    let title = escape(&format!("{}/", base));
+    let parent = match base.rsplit_once('/') {
+        Some((parent, _)) if !base.is_empty() => format!("<li><a href=\"{}/\">../</a></li>\n", escape(parent)),
+        _ => String::new()
+    };
+    let entries: Vec<String> = dirs.iter().chain(files.iter())
+        .map(|name| format!("<li><a href=\"{}/{}\">{}</a></li>\n", base, escape(name), escape(name)))
+        .collect();
+    format!("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n<h1>{}</h1>\n<ul>\n{}{}</ul>\n</body>\n</html>\n",
+            title, title, parent, entries.concat())
 }
 
+fn served_name(source: &std::path::Path, extension: &str) -> String {
+    source.with_extension(extension).file_name().unwrap_or_default().to_string_lossy().to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_port_fallback() {
+        let busy = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = busy.local_addr().unwrap().port();
+
+        let listener = bind_port(port, true, false).unwrap();
+        let actual = listener.local_addr().unwrap().port();
+        assert_ne!(actual, port);
+        std::net::TcpStream::connect(("127.0.0.1", actual)).unwrap();
+
+        // Strictly, a busy port is just an error
+        assert!(matches!(bind_port(port, false, false), Err(FleenError::ServerBind(p, _)) if p == port));
+    }
+
+    #[test]
+    fn test_bind_address() {
+        // Only this machine, unless asked otherwise
+        let local = bind_port(0, false, false).unwrap();
+        assert!(local.local_addr().unwrap().ip().is_loopback());
+        let lan = bind_port(0, false, true).unwrap();
+        assert!(lan.local_addr().unwrap().ip().is_unspecified());
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        let listener = bind_port(0, false, false).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (shutdown, stop) = oneshot::channel();
+        let (requests, _) = std::sync::mpsc::channel();
+        let server = tokio::spawn(start_server(PathBuf::from("./testdata"), listener, false, requests, stop));
+
+        // A request that's already in when the shutdown comes still gets its answer
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream.write_all(b"GET /not_hidden.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown.send(()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+
+        let stopped = tokio::time::timeout(Duration::from_secs(5), server).await;
+        assert!(matches!(stopped, Ok(Ok(Ok(())))));
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        // Track how many requests are being handled at once
+        let (current, max) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let (c, m) = (current.clone(), max.clone());
+        let slow = Router::new().route("/", get(move || async move {
+            let now = c.fetch_add(1, Ordering::SeqCst) + 1;
+            m.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            c.fetch_sub(1, Ordering::SeqCst);
+            "ok"
+        }));
+        let app = with_limit(slow, 2);
+
+        let requests = (0..6).map(|_| {
+            let app = app.clone();
+            tokio::spawn(async move { app.oneshot(Request::get("/").body(Body::empty()).unwrap()).await.unwrap() })
+        }).collect::<Vec<_>>();
+        for request in requests {
+            assert_eq!(request.await.unwrap().status(), 200); // Everything gets served eventually
+        }
+        assert_eq!(max.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_routes_with_limit() {
+        let app = with_limit(routes(PathBuf::from("./testdata")), 1);
+        let response = app.oneshot(Request::get("/raw.txt").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_request_log() {
+        let (requests, lines) = std::sync::mpsc::channel();
+        let version = Arc::new(AtomicU64::new(0));
+        let app = with_request_log(with_live_reload(routes(PathBuf::from("./testdata")), version), requests);
+        for path in ["/raw.txt", "/missing.html", RELOAD_PATH] {
+            app.clone().oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap();
+        }
+        // Live reload's polling isn't worth mentioning
+        assert_eq!(lines.try_iter().collect::<Vec<_>>(), vec!["GET /raw.txt 200", "GET /missing.html 404"]);
+    }
+
+    #[tokio::test]
+    async fn test_layout_preview_route() {
+        let app = routes(PathBuf::from("./testdata"));
+        let response = app.clone().oneshot(Request::get("/_fleen/layout/_layouts/post.html").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let response = app.oneshot(Request::get("/_fleen/layout/../Cargo.toml").body(Body::empty()).unwrap()).await.unwrap();
+        assert_ne!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_publish_states() {
+        let get_status = |path: &'static str| async move {
+            let app = routes(PathBuf::from("./testdata"));
+            app.oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap().status()
+        };
+        assert_eq!(get_status("/not_hidden.html").await, 200);
+        assert_eq!(get_status("/hidden.html").await, 200); // Served, even though it won't be built
+        assert_eq!(get_status("/draft.html").await, 404); // Drafts are off in testdata
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("_config.toml"), "drafts = true").unwrap();
+        fs::write(dir.path().join("draft.md"), "+++\ndraft = true\n+++\nHi").unwrap();
+        let app = routes(dir.path().to_path_buf());
+        let response = app.oneshot(Request::get("/draft.html").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_custom_404() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("_404.html"), "Never served").unwrap();
+        let get = |path: &'static str| {
+            let app = routes(dir.path().to_path_buf());
+            async move {
+                let response = app.oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap();
+                let status = response.status().as_u16();
+                (status, String::from_utf8(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap())
+            }
+        };
+        // Without one of its own, the site gets ours
+        assert_eq!(get("/nowhere.html").await, (404, include_str!("../templates/404.html").to_string()));
+        assert_eq!(get("/_404.html").await.0, 404);
+
+        fs::write(dir.path().join("404.md"), "# Lost").unwrap();
+        assert_eq!(get("/nowhere.html").await, (404, "<h1 id=\"lost\">Lost</h1>".to_string()));
+        fs::write(dir.path().join("404.html"), "<p>Gone</p>").unwrap();
+        assert_eq!(get("/nowhere.html").await, (404, "<p>Gone</p>".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_dir_listing() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("posts/sub")).unwrap();
+        fs::create_dir_all(dir.path().join("about")).unwrap();
+        for file in ["posts/first.md", "posts/photo.png", "posts/style.scss", "posts/_notes.md", "posts/.secret", "about/index.md"] {
+            fs::write(dir.path().join(file), "# Hi").unwrap();
+        }
+        let get = |path: &'static str| {
+            let app = routes(dir.path().to_path_buf());
+            async move {
+                let response = app.oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap();
+                let status = response.status().as_u16();
+                (status, String::from_utf8(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap())
+            }
+        };
+
+        let (status, listing) = get("/posts").await;
+        assert_eq!(status, 200);
+        let links: Vec<&str> = listing.split("<a href=\"").skip(1).map(|l| l.split('"').next().unwrap()).collect();
+        assert_eq!(links, vec!["/", "/posts/sub/", "/posts/first.html", "/posts/photo.png", "/posts/style.css"]);
+        assert_eq!(get("/posts/sub/").await.0, 200);
+
+        // A directory with an index is just its index
+        assert_eq!(get("/about/").await, (200, "<h1 id=\"hi\">Hi</h1>".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_content_types() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.md"), "# Home").unwrap();
+        fs::write(dir.path().join("style.css"), "body {}").unwrap();
+        fs::write(dir.path().join("app.js"), "go()").unwrap();
+        fs::write(dir.path().join("logo.svg"), "<svg/>").unwrap();
+        fs::write(dir.path().join("feed.xml"), "+++\ntitle = \"Feed\"\n+++\n<rss/>").unwrap();
+        fs::write(dir.path().join("data.bin"), "??").unwrap();
+        let content_type = |path: &'static str| {
+            let app = routes(dir.path().to_path_buf());
+            async move {
+                let response = app.oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap();
+                (response.status().as_u16(), response.headers()[CONTENT_TYPE].to_str().unwrap().to_string())
+            }
+        };
+        assert_eq!(content_type("/").await, (200, "text/html; charset=utf-8".to_string()));
+        assert_eq!(content_type("/style.css").await, (200, "text/css; charset=utf-8".to_string()));
+        assert_eq!(content_type("/app.js").await, (200, "application/javascript".to_string()));
+        assert_eq!(content_type("/logo.svg").await, (200, "image/svg+xml".to_string()));
+        assert_eq!(content_type("/feed.xml").await, (200, "application/xml".to_string()));
+        assert_eq!(content_type("/data.bin").await, (200, "application/octet-stream".to_string()));
+        assert_eq!(content_type("/nowhere.html").await, (404, "text/html; charset=utf-8".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_favicon_ico() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = routes(dir.path().to_path_buf());
+        let get = |app: &Router| app.clone().oneshot(Request::get("/favicon.ico").body(Body::empty()).unwrap());
+        assert_eq!(get(&app).await.unwrap().status(), 404);
+
+        // Browsers asking for the usual name get the icon the site has
+        fs::write(dir.path().join("favicon.png"), include_bytes!("../templates/favicon.png")).unwrap();
+        let response = get(&app).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["Content-Type"], "image/png");
+    }
+
+    #[tokio::test]
+    async fn test_redirects() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("new.md"), "The new page").unwrap();
+        fs::write(dir.path().join("_redirects"), "# Moved in the reorg\n/old.html /new.html 301\n/also-old.html  /new.html 200\n/temp /new.html 302\n").unwrap();
+        let app = routes(dir.path().to_path_buf());
+        let get = |path: &'static str| app.clone().oneshot(Request::get(path).body(Body::empty()).unwrap());
+
+        let response = get("/old.html").await.unwrap();
+        assert_eq!(response.status(), 301);
+        assert_eq!(response.headers()["Location"], "/new.html");
+        assert_eq!(get("/temp").await.unwrap().status(), 302);
+
+        // Served as if it were the new page
+        let response = get("/also-old.html").await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "<p>The new page</p>");
+
+        assert_eq!(get("/new.html").await.unwrap().status(), 200);
+        assert_eq!(get("/nowhere.html").await.unwrap().status(), 404);
+
+        // Nonsense in the file is an error, or no rule at all, rather than a panic
+        fs::write(dir.path().join("_redirects"), "/one /new.html 1\n/big /new.html 1000\n/gone /new.html 404\n/bad /new\u{7}.html 302\n").unwrap();
+        let app = routes(dir.path().to_path_buf());
+        let get = |path: &'static str| app.clone().oneshot(Request::get(path).body(Body::empty()).unwrap());
+        for path in ["/one", "/big", "/gone"] {
+            assert_eq!(get(path).await.unwrap().status(), 404);
+        }
+        assert_eq!(get("/bad").await.unwrap().status(), 500);
+    }
+
+    #[tokio::test]
+    async fn test_declared_content_type() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("_layouts")).unwrap();
+        fs::write(dir.path().join("_layouts/page.html"), "<html><head></head><body>$content</body></html>").unwrap();
+        fs::write(dir.path().join("amp.md"), "+++\nlayout = \"_layouts/page.html\"\ncontent_type = \"text/html; charset=iso-8859-1\"\n+++\nHi").unwrap();
+        fs::write(dir.path().join("notes.json"), "+++\ncontent_type = \"application/json\"\n+++\n{}").unwrap();
+        fs::write(dir.path().join("plain.md"), "Hi").unwrap();
+        fs::write(dir.path().join("broken.json"), "+++\ncontent_type = \"application/json\\nX-Evil: 1\"\n+++\n{}").unwrap();
+        let app = routes(dir.path().to_path_buf());
+        let get = |path: &'static str| app.clone().oneshot(Request::get(path).body(Body::empty()).unwrap());
+
+        let response = get("/amp.html").await.unwrap();
+        assert_eq!(response.headers()["Content-Type"], "text/html; charset=iso-8859-1");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<meta charset=\"iso-8859-1\">"));
+
+        assert_eq!(get("/notes.json").await.unwrap().headers()["Content-Type"], "application/json");
+        assert_eq!(get("/plain.html").await.unwrap().headers()["Content-Type"], "text/html; charset=utf-8"); // Same as ever
+        // A content type that can't be a header gets the usual one instead
+        assert_eq!(get("/broken.json").await.unwrap().headers()["Content-Type"], "application/json");
+    }
+}
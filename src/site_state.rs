@@ -0,0 +1,83 @@
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::fleen_app::FleenError;
+use crate::utils;
+
+/// Things Fleen remembers about a site between launches, kept in `_fleen/state.toml`. Unlike
+/// `_config.toml` this is ours to rewrite whenever we like, so nobody should hand-edit it.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SiteState {
+    /// Bookmarked files, relative to the site root
+    pub pins: Vec<PathBuf>,
+}
+
+impl SiteState {
+    /// Load the state for a site, or a fresh one. See `utils::load_toml`.
+    pub fn load(root: &Path) -> Self {
+        utils::load_toml(&state_path(root))
+    }
+
+    /// Save the state for a site, making `_fleen` if it needs to
+    pub fn save(&self, root: &Path) -> Result<(), FleenError> {
+        utils::save_toml(self, &state_path(root))
+    }
+
+    pub fn is_pinned(&self, path: &Path) -> bool {
+        self.pins.iter().any(|p| p == path)
+    }
+
+    /// Pin a file if it isn't pinned, unpin it if it is
+    pub fn toggle_pin(&mut self, path: &Path) {
+        if self.is_pinned(path) {
+            self.pins.retain(|p| p != path)
+        } else {
+            self.pins.push(path.to_path_buf())
+        }
+    }
+
+    /// Forget pins to files that don't exist anymore. Returns whether anything was removed.
+    pub fn prune(&mut self, root: &Path) -> bool {
+        let before = self.pins.len();
+        self.pins.retain(|p| root.join(p).exists());
+        self.pins.len() != before
+    }
+}
+
+fn state_path(root: &Path) -> PathBuf {
+    root.join("_fleen/state.toml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pins() {
+        let mut state = SiteState::default();
+        state.toggle_pin(Path::new("index.md"));
+        state.toggle_pin(Path::new("dir/subdir.md"));
+        state.toggle_pin(Path::new("gone.md"));
+        assert!(state.is_pinned(Path::new("index.md")));
+
+        // Toggling again unpins
+        state.toggle_pin(Path::new("index.md"));
+        assert!(!state.is_pinned(Path::new("index.md")));
+
+        // Files that don't exist get pruned
+        assert!(state.prune(Path::new("./testdata")));
+        assert_eq!(state.pins, vec![PathBuf::from("dir/subdir.md")]);
+        assert!(!state.prune(Path::new("./testdata")));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(SiteState::load(dir.path()), SiteState::default());
+
+        let mut state = SiteState::default();
+        state.toggle_pin(Path::new("posts/hello.md"));
+        state.save(dir.path()).unwrap();
+        assert_eq!(SiteState::load(dir.path()), state);
+    }
+}
@@ -1,99 +1,304 @@
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant, SystemTime};
 use eframe::egui;
-use eframe::egui::{Button, Context, Id};
-use egui_ltreeview::Action;
+use eframe::egui::{Button, Context, Id, Key, KeyboardShortcut, Modifiers, RichText};
+use egui_ltreeview::Action as TreeAction;
+use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
-use crate::utils;
-use crate::fleen_app::{FileType, FleenError, Site, SiteActions, TreeEntry};
-use crate::server::start_server;
+use crate::{build_log, preview, renderer, sitemap, utils};
+use crate::build_log::BuildLogEntry;
+use crate::clipboard::{ImageClipboard, SystemClipboard};
+use crate::config::{ConfigForm, PasteFormat};
+use crate::console::ConsoleLog;
+use crate::idle_deploy::{site_fingerprint, IdleDeploy};
+use crate::live_diff::{LiveDiff, LiveStatus};
+use crate::notifications::Notifications;
+use crate::preferences::Preferences;
+use crate::preview::Block;
+use crate::site_state::SiteState;
+use crate::fleen_app::{filter_tree, is_image_file, plan_batch_rename, CAN_TRASH, undersized_image, BuildSummary, DeployOptions, DeployTarget, Diagnostic, Severity, sort_tree, FileType, FleenError, ImageLinkReport, RenamePattern, Site, SiteActions, TreeEntry, TreeSort};
+use crate::server::{bind_port, lan_address, start_server};
 use crate::ui_ext::{ButtonExtensions, UiExtensions};
-use crate::utils::{open_filename, open_server};
+use crate::utils::{open_filename, open_url};
+use crate::watch_build::WatchBuild;
 
 pub struct SiteUi {
     site: Arc<Site>,
     error: Option<FleenError>,
     message: Option<String>,
     selected_file: Option<String>,
+    selected_files: Vec<String>,
     dialog_mode: Option<DialogMode>,
     server_handle: Option<JoinHandle<()>>,
+    /// Tells the server to stop, letting it finish what it's doing first
+    server_shutdown: Option<oneshot::Sender<()>>,
+    /// Only filled in if the server stops by itself, which means something went wrong
+    server_response: Pending<()>,
     server_port: String,
-    deploy_response: Arc<Mutex<Option<Result<String, FleenError>>>>,
+    /// What the running server has been asked for, a line per request, for the console
+    server_requests: Option<Receiver<String>>,
+    /// Whether the next server start lets other devices on the network in, rather than just this machine
+    lan_access: bool,
+    /// Where the running server can be reached: the loopback address it's bound to, or our network
+    /// address if it's open to the LAN
+    server_host: IpAddr,
+    deploy_response: Pending<String>,
+    /// The running deploy's output, a line at a time, for the console
+    deploy_lines: Option<Receiver<String>>,
+    /// Set to stop the deploy that's running
+    deploy_cancel: Arc<AtomicBool>,
     deploying: bool,
-    image_message: Option<TempMessage>,
+    /// The site after an import, and how many posts were made
+    import_response: Pending<(Site, usize)>,
+    importing: bool,
+    live_diff_response: Pending<Vec<LiveDiff>>,
+    comparing: bool,
+    live_diff: Option<Vec<LiveDiff>>,
+    notifications: Notifications,
+    /// Pages rendered to temp files for a quick look, and when; they're deleted after a while
+    built_previews: Vec<(Instant, tempfile::TempPath)>,
+    tree_sort: TreeSort,
+    /// Only show files whose paths contain this
+    tree_filter: String,
+    show_modified: bool,
+    palette: Option<Palette>,
+    diagnostics: Option<Vec<Diagnostic>>,
+    frontmatter_view: Option<FrontmatterView>,
+    /// The selected page as it renders, and when the page was last changed when we rendered it
+    page_preview: Option<PagePreview>,
+    show_preview: bool,
+    /// The newest entries in the site's build log, when we're showing it
+    build_log: Option<Vec<String>>,
+    /// A page, and the pages that link to it
+    links_here: Option<(String, Vec<PathBuf>)>,
+    config_form: Option<ConfigForm>,
+    /// What a build would produce, worked out when first needed and forgotten when the site changes
+    build_summary: Option<Result<BuildSummary, String>>,
+    /// The count in progress, if there is one. Each count gets its own, so one for an old copy
+    /// of the site can't land after a new one's started.
+    build_summary_response: Option<Pending<BuildSummary>>,
+    strict: bool,
+    dry_run: bool,
+    deploy_target: DeployTarget,
+    console: Arc<Mutex<ConsoleLog>>,
+    state: SiteState,
+    /// Watching for the site to go idle so we can deploy it, if the user turned that on
+    auto_deploy: Option<IdleDeploy>,
+    last_scan: Instant,
+    /// When the user said to quit even though the server or a deploy was running. We give them a
+    /// moment to stop before closing.
+    quitting: Option<Instant>,
+    /// Fleen's own settings, for the theme
+    preferences: Preferences,
+    /// Whether the theme showing is dark, as of the last frame
+    dark_mode: bool,
+    /// Rebuilding into a folder whenever the site changes, if the user turned that on
+    watch_build: Option<WatchBuild>,
+    // Set when an action opens a dialog with a text field, so the dialog can grab focus
+    dialog_just_opened: bool,
 }
 
+/// How long to wait for the server and a deploy to stop when quitting, before closing anyway
+const QUIT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Where a background task leaves its result for the UI to pick up
+type Pending<T> = Arc<Mutex<Option<Result<T, FleenError>>>>;
+
 impl From<Site> for SiteUi {
     fn from(value: Site) -> Self {
         Self {
+            strict: value.config.strict,
+            dry_run: false,
+            deploy_target: if value.config.git_deploy.is_some() { DeployTarget::GitBranch } else { DeployTarget::Script },
+            server_port: value.config.server_port.to_string(),
+            server_requests: None,
+            lan_access: false,
+            server_host: Ipv4Addr::LOCALHOST.into(),
+            state: SiteState::load(&value.root),
             site: Arc::new(value),
             error: None,
             message: None,
             selected_file: None,
+            selected_files: vec![],
             dialog_mode: None,
             server_handle: None,
-            server_port: "3000".to_string(),
+            server_shutdown: None,
+            server_response: Arc::new(Mutex::new(None)),
             deploy_response: Arc::new(Mutex::new(None)),
+            deploy_lines: None,
+            deploy_cancel: Arc::new(AtomicBool::new(false)),
             deploying: false,
-            image_message: None,
+            import_response: Arc::new(Mutex::new(None)),
+            importing: false,
+            live_diff_response: Arc::new(Mutex::new(None)),
+            comparing: false,
+            live_diff: None,
+            notifications: Notifications::default(),
+            built_previews: vec![],
+            tree_sort: TreeSort::Name,
+            tree_filter: String::new(),
+            show_modified: false,
+            palette: None,
+            diagnostics: None,
+            frontmatter_view: None,
+            page_preview: None,
+            show_preview: true,
+            links_here: None,
+            build_log: None,
+            config_form: None,
+            build_summary: None,
+            build_summary_response: None,
+            console: Arc::new(Mutex::new(ConsoleLog::default())),
+            auto_deploy: None,
+            watch_build: None,
+            quitting: None,
+            preferences: Preferences::path().map(|path| Preferences::load(&path)).unwrap_or_default(),
+            dark_mode: true,
+            last_scan: Instant::now(),
+            dialog_just_opened: false,
         }
     }
 }
 
 impl SiteUi {
+    /// Swap in a freshly-read copy of the site, after something changed on disk
+    fn set_site(&mut self, site: Site) {
+        utils::set_safe_mode(site.config.safe_mode);
+        self.site = Arc::new(site);
+        self.build_summary = None;
+        self.build_summary_response = None;
+        if self.state.prune(&self.site.root) {
+            self.save_state()
+        }
+    }
+
+    fn save_state(&mut self) {
+        if let Err(e) = self.state.save(&self.site.root) { self.error = Some(e) }
+    }
+
+    /// Open a file for editing, mentioning it if we couldn't use the configured editor
+    fn open_file(&mut self, path: &str) {
+        match open_filename(path) {
+            Ok(Some(note)) => self.notifications.warning(note),
+            Ok(None) => {}
+            Err(e) => self.error = Some(e)
+        }
+    }
+
+    /// Where a selected file is, relative to the site root
+    fn relative_path(&self, selected: &str) -> PathBuf {
+        let path = PathBuf::from(selected);
+        path.strip_prefix(&self.site.root).map(|p| p.to_path_buf()).unwrap_or(path)
+    }
+
     pub fn display(&mut self, ctx: &Context) {
+        self.preferences.apply(ctx);
+        self.dark_mode = ctx.theme() == egui::Theme::Dark;
         self.check_deploy_status(ctx);
+        self.check_auto_deploy(ctx);
+        self.check_watch_build(ctx);
+        self.check_dropped_files(ctx);
+        self.check_import_status(ctx);
+        self.check_live_diff_status(ctx);
+        self.check_server_status(ctx);
+        self.check_close_request(ctx);
         self.error_dialog(ctx);
         self.message_dialog(ctx);
-        self.temp_message();
+        self.diagnostics_dialog(ctx);
+        self.frontmatter_dialog(ctx);
+        self.links_here_dialog(ctx);
+        self.build_log_dialog(ctx);
+        self.live_diff_dialog(ctx);
+        self.settings_dialog(ctx);
+        self.notifications.show(ctx);
+        // Long enough for any browser to have read them
+        self.built_previews.retain(|(made, _)| made.elapsed() < Duration::from_secs(300));
 
-        let mut just_clicked = false;
+        if ctx.input_mut(|i| i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND, Key::P))) {
+            self.palette = match self.palette { Some(_) => None, None => Some(Palette::default()) };
+        }
+        self.check_shortcuts(ctx);
+
+        self.status_bar(ctx);
+        self.console_panel(ctx);
+        self.preview_panel(ctx);
 
         egui::CentralPanel::default().show(ctx, |ui| {
             let width = ui.available_width() / 3.0 - 5.0;
             let height = ui.available_height() - 120.0;
             ui.horizontal(|ui| {
                 ui.column(width, |ui| {
+                    self.pinned_files(ui);
+                    self.tree_options(ui);
                     egui::ScrollArea::new([true, true])
                         .auto_shrink([false, false])
                         .min_scrolled_height(height)
                         .show(ui, |ui| self.tree_view(ui));
-                    just_clicked = self.tree_buttons(ui);
+                    self.tree_buttons(ui);
                 });
                 ui.column(width, |ui| self.server_controls(ui));
                 ui.column(width, |ui| {
-                    ui.add_enabled_ui(!self.deploying, |ui| {
-                        let label = if self.deploying {
-                            "Deploying..."
-                        } else {
-                            "Build and Deploy"
-                        };
-                        if ui.add_fill_width(Button::green(label)).clicked() {
-                            self.build_and_deploy();
-                        }
-                    });
-
-                    if ui.add_fill_width(Button::blue("Build site...")).clicked() &&
-                        let Some(path) = rfd::FileDialog::new().pick_folder() {
-                        match self.site.build_site(&path) {
-                            Ok(()) => { self.message = Some("Site built successfully".to_string()) }
-                            Err(err) => { self.error = Some(err) }
-                        }
-                    }
+                    let label = if self.deploying {
+                        "Deploying..."
+                    } else {
+                        "Build and Deploy"
+                    };
+                    self.build_summary_label(ui);
+                    self.action_button(ui, Action::BuildAndDeploy, Button::green(label));
+                    if self.deploying { self.action_button(ui, Action::CancelDeploy, Button::red("Cancel deploy")) }
+                    self.action_button(ui, Action::BuildSite, Button::blue("Build site..."));
+                    self.watch_build_controls(ui);
+                    self.action_button(ui, Action::Validate, Button::new("Validate"));
+                    self.action_button(ui, Action::ShowBuildLog, Button::new("Build log"));
+                    let label = if self.comparing { "Comparing..." } else { "Compare with live site" };
+                    self.action_button(ui, Action::CompareWithLive, Button::new(label));
+                    ui.checkbox(&mut self.strict, "Strict (warnings fail the build)");
+                    ui.checkbox(&mut self.dry_run, "Dry run (don't really deploy)");
+                    egui::ComboBox::from_label("Deploy with")
+                        .selected_text(match self.deploy_target {
+                            DeployTarget::Script => "deploy.sh",
+                            DeployTarget::GitBranch => "Git branch"
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut self.deploy_target, DeployTarget::Script, "deploy.sh");
+                            ui.selectable_value(&mut self.deploy_target, DeployTarget::GitBranch, "Git branch");
+                        });
+                    self.auto_deploy_controls(ui);
+                    self.action_button(ui, Action::EditSettings, Button::new("Settings..."));
+                    let label = if self.dark_mode { "Light theme" } else { "Dark theme" };
+                    self.action_button(ui, Action::ToggleTheme, Button::new(label));
+                    let label = if self.show_preview { "Hide preview" } else { "Show preview" };
+                    self.action_button(ui, Action::TogglePreview, Button::new(label));
                 })
             });
         });
 
+        self.palette_dialog(ctx);
+
+        let just_clicked = std::mem::take(&mut self.dialog_just_opened);
         match self.dialog_mode {
             Some(DialogMode::NewFile(_)) => self.new_file_dialog(ctx, just_clicked),
-            Some(DialogMode::ConfirmDelete(_)) => self.confirm_delete_dialog(ctx),
+            Some(DialogMode::ConfirmDelete(_) | DialogMode::ConfirmPermanentDelete(..)) => self.confirm_delete_dialog(ctx),
+            Some(DialogMode::ConfirmQuit) => self.confirm_quit_dialog(ctx),
             Some(DialogMode::RenameFile(_)) => self.rename_dialog(ctx, just_clicked),
+            Some(DialogMode::BatchRename(_)) => self.batch_rename_dialog(ctx),
+            Some(DialogMode::SetFrontmatter(_)) => self.set_frontmatter_dialog(ctx, just_clicked),
+            Some(DialogMode::FixImageLinks(_)) => self.fix_image_links_dialog(ctx),
+            Some(DialogMode::ImportFeed(_)) => self.import_feed_dialog(ctx, just_clicked),
+            Some(DialogMode::NewLayout(_)) => self.new_layout_dialog(ctx, just_clicked),
             None => {}
         }
     }
 
     fn check_deploy_status(&mut self, ctx: &Context) {
+        if let Some(lines) = &self.deploy_lines && let Ok(mut log) = self.console.lock() {
+            for line in lines.try_iter() { log.push(line) }
+        }
         if self.deploying && let Ok(mut m) = self.deploy_response.lock() {
             if let Some(result) = m.take() {
                 match result {
@@ -106,6 +311,194 @@ impl SiteUi {
         }
     }
 
+    fn check_import_status(&mut self, ctx: &Context) {
+        if !self.importing { return }
+        let result = self.import_response.lock().ok().and_then(|mut m| m.take());
+        match result {
+            Some(Ok((new_site, count))) => {
+                self.set_site(new_site);
+                self.notifications.info(format!("Imported {} posts", count));
+                self.importing = false;
+                self.dialog_mode = None;
+            }
+            Some(Err(e)) => {
+                self.error = Some(e);
+                self.importing = false;
+            }
+            None => ctx.request_repaint_after(Duration::from_millis(100))
+        }
+    }
+
+    fn check_live_diff_status(&mut self, ctx: &Context) {
+        if !self.comparing { return }
+        let result = self.live_diff_response.lock().ok().and_then(|mut m| m.take());
+        match result {
+            Some(Ok(diffs)) => self.live_diff = Some(diffs),
+            Some(Err(e)) => self.error = Some(e),
+            None => {
+                ctx.request_repaint_after(Duration::from_millis(100));
+                return
+            }
+        }
+        self.comparing = false;
+    }
+
+    fn check_server_status(&mut self, ctx: &Context) {
+        if let Some(lines) = &self.server_requests && let Ok(mut log) = self.console.lock() {
+            for line in lines.try_iter() { log.push(line) }
+        }
+        // Requests come from the browser, not from us, so keep looking while the server's up
+        if self.server_handle.is_some() { ctx.request_repaint_after(Duration::from_millis(250)) }
+        if !self.server_handle.as_ref().is_some_and(|handle| handle.is_finished()) { return }
+        self.server_handle = None;
+        self.server_shutdown = None;
+        if let Some(Err(e)) = self.server_response.lock().ok().and_then(|mut m| m.take()) {
+            self.error = Some(e)
+        }
+    }
+
+    /// Scan the site for changes now and then, and deploy once it's been idle long enough
+    fn check_auto_deploy(&mut self, ctx: &Context) {
+        let Some(idle) = &mut self.auto_deploy else { return };
+        let now = Instant::now();
+        if now.duration_since(self.last_scan) >= Duration::from_secs(1) {
+            idle.observe(site_fingerprint(&self.site.root, &self.site.config), now);
+            self.last_scan = now;
+        }
+        // Hold off while a deploy is still going; we'll catch up when it's done
+        if !self.deploying && idle.due(now) {
+            self.build_and_deploy()
+        }
+        ctx.request_repaint_after(Duration::from_millis(250));
+    }
+
+    /// Take in files dropped on the window: images like a pasted one, and pages into the selected
+    /// dir. Anything else is ignored.
+    fn check_dropped_files(&mut self, ctx: &Context) {
+        let dropped: Vec<PathBuf> = ctx.input(|i| i.raw.dropped_files.iter().filter_map(|file| file.path.clone()).collect());
+        for file in dropped {
+            if is_image_file(&file) {
+                let clipboard = SystemClipboard::open().ok();
+                match self.site.add_image_file(&file, clipboard.as_ref().map(|c| c as &dyn ImageClipboard)) {
+                    Ok((new_site, path)) => {
+                        match undersized_image(&path, &new_site.config) {
+                            Some(warning) => self.notifications.warning(format!("Image saved, but: {}", warning)),
+                            None => self.notifications.info("Image saved!")
+                        };
+                        self.set_site(new_site);
+                    }
+                    Err(e) => self.notifications.error(e.to_string())
+                }
+            } else if file.extension().is_some_and(|ext| ext == "md" || ext == "html") {
+                match self.site.import_page(&file, self.selected_file.as_ref()) {
+                    Ok((new_site, path)) => {
+                        self.set_site(new_site);
+                        let path = path.to_string_lossy().to_string();
+                        self.selected_files = vec![path.clone()];
+                        self.selected_file = Some(path);
+                        self.notifications.info("Page added");
+                    }
+                    Err(e) => self.error = Some(e)
+                }
+            }
+        }
+    }
+
+    /// Report each build the watcher finishes in the console, with a toast if it failed
+    fn check_watch_build(&mut self, ctx: &Context) {
+        let Some(watch) = &self.watch_build else { return };
+        for result in watch.results() {
+            let Ok(mut log) = self.console.lock() else { continue };
+            match result {
+                Ok(warnings) if warnings.is_empty() => log.push(format!("Rebuilt into {}", watch.target.display())),
+                Ok(warnings) => {
+                    log.push(format!("Rebuilt into {} with warnings:", watch.target.display()));
+                    for warning in warnings { log.push(warning.to_string()) }
+                }
+                Err(err) => {
+                    log.push_text(&format!("Rebuild failed: {}", err));
+                    self.notifications.error("Rebuild failed; see the console")
+                }
+            }
+        }
+        ctx.request_repaint_after(Duration::from_millis(250));
+    }
+
+    /// The checkbox to keep a build up to date, which asks where to build if the site doesn't say
+    fn watch_build_controls(&mut self, ui: &mut egui::Ui) {
+        let mut enabled = self.watch_build.is_some();
+        if ui.checkbox(&mut enabled, "Rebuild on changes").changed() {
+            self.watch_build = None;
+            if enabled {
+                let target = self.site.config.output_path(&self.site.root).or_else(|| rfd::FileDialog::new().pick_folder());
+                match target.map(|path| self.site.watch_build(&path, self.strict)) {
+                    Some(Ok(watch)) => self.watch_build = Some(watch),
+                    Some(Err(e)) => self.error = Some(e),
+                    None => {}
+                }
+            }
+        }
+        if let Some(watch) = &self.watch_build {
+            ui.small(format!("Building into {}", watch.target.display()));
+        }
+    }
+
+    /// How big a build would be, so there are no surprises
+    fn build_summary_label(&mut self, ui: &mut egui::Ui) {
+        self.check_build_summary(ui.ctx());
+        let recount = ui.horizontal(|ui| {
+            match &self.build_summary {
+                Some(Ok(summary)) => ui.label(summary.to_string()),
+                Some(Err(err)) => ui.colored_label(egui::Color32::RED, err.as_str()),
+                None => ui.label("Counting...")
+            };
+            ui.small_button("⟳").on_hover_text("Recount").clicked()
+        }).inner;
+        if recount {
+            self.build_summary = None;
+            self.build_summary_response = None;
+        }
+    }
+
+    /// Count up a build in the background when we need a count, since compiling a big site takes
+    /// a while, and pick the count up when it's done
+    fn check_build_summary(&mut self, ctx: &Context) {
+        if self.build_summary.is_some() { return }
+        let pending = self.build_summary_response.get_or_insert_with(|| {
+            let pending = Pending::default();
+            let (site, mutex) = (self.site.clone(), pending.clone());
+            std::thread::spawn(move || {
+                let result = site.build_summary();
+                if let Ok(mut m) = mutex.lock() {
+                    *m = Some(result);
+                }
+            });
+            pending
+        });
+        match pending.lock().ok().and_then(|mut m| m.take()) {
+            Some(result) => {
+                self.build_summary = Some(result.map_err(|e| e.to_string()));
+                self.build_summary_response = None;
+            }
+            None => ctx.request_repaint_after(Duration::from_millis(100))
+        }
+    }
+
+    /// The opt-in checkbox, and the countdown (with a way out) when a deploy is coming
+    fn auto_deploy_controls(&mut self, ui: &mut egui::Ui) {
+        let mut enabled = self.auto_deploy.is_some();
+        let label = format!("Deploy automatically after {}s idle", self.site.config.auto_deploy_delay);
+        if ui.checkbox(&mut enabled, label).changed() {
+            self.auto_deploy = enabled.then(|| IdleDeploy::new(Duration::from_secs(self.site.config.auto_deploy_delay)));
+        }
+        if let Some(idle) = &mut self.auto_deploy && let Some(remaining) = idle.remaining(Instant::now()) {
+            ui.horizontal(|ui| {
+                ui.label(format!("Deploying in {}s", remaining.as_secs() + 1));
+                if ui.add(Button::red("Cancel")).clicked() { idle.cancel() }
+            });
+        }
+    }
+
     /// If there's an error dialog, display it
     fn error_dialog(&mut self, ctx: &Context) {
         if let Some(err) = &self.error {
@@ -132,35 +525,712 @@ impl SiteUi {
         }
     }
 
-    /// A temporary message (replaces the label on the image button)
-    fn temp_message(&mut self) {
-        if let Some(TempMessage { created, .. }) = &self.image_message
-            && *created < Instant::now() - Duration::from_secs(2) {
-            self.image_message = None
+    /// The results of validating the site, if we've done that
+    fn diagnostics_dialog(&mut self, ctx: &Context) {
+        if let Some(diagnostics) = &self.diagnostics {
+            let mut close = false;
+            egui::Window::new("Validation").collapsible(false).resizable(false).show(ctx, |ui| {
+                if diagnostics.is_empty() {
+                    ui.label("No problems found");
+                }
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    for diagnostic in diagnostics.iter() {
+                        ui.horizontal(|ui| {
+                            match diagnostic.severity {
+                                Severity::Error => ui.colored_label(egui::Color32::RED, "error"),
+                                Severity::Warning => ui.colored_label(egui::Color32::YELLOW, "warning")
+                            };
+                            ui.strong(diagnostic.file.to_string_lossy());
+                            ui.label(&diagnostic.message);
+                        });
+                    }
+                });
+                close = ui.button("Thanks!").clicked();
+            });
+            if close { self.diagnostics = None }
+        }
+    }
+
+    /// What we made of the selected file's frontmatter
+    fn frontmatter_dialog(&mut self, ctx: &Context) {
+        if let Some(view) = &self.frontmatter_view {
+            let mut close = false;
+            egui::Window::new(format!("Frontmatter: {}", view.file)).collapsible(false).resizable(false).show(ctx, |ui| {
+                match &view.fields {
+                    Ok(Some(fields)) => {
+                        egui::Grid::new("frontmatter_fields").striped(true).show(ui, |ui| {
+                            for (key, value) in fields.iter() {
+                                ui.strong(key);
+                                ui.label(value);
+                                ui.end_row();
+                            }
+                        });
+                    }
+                    Ok(None) => { ui.label("This file has no frontmatter"); }
+                    Err(err) => { ui.colored_label(egui::Color32::RED, err); }
+                }
+                close = ui.button("Thanks!").clicked();
+            });
+            if close { self.frontmatter_view = None }
+        }
+    }
+
+    /// Recent builds and deploys, and how they went
+    fn build_log_dialog(&mut self, ctx: &Context) {
+        if let Some(lines) = &self.build_log {
+            let mut close = false;
+            egui::Window::new("Build log").collapsible(false).resizable(true).show(ctx, |ui| {
+                if lines.is_empty() { ui.label("Nothing's been built yet"); }
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    for line in lines { ui.monospace(line); }
+                });
+                close = ui.button("Thanks!").clicked();
+            });
+            if close { self.build_log = None }
+        }
+    }
+
+    /// The pages that link to the selected one
+    fn links_here_dialog(&mut self, ctx: &Context) {
+        if let Some((file, pages)) = &self.links_here {
+            let mut close = false;
+            egui::Window::new(format!("What links to {}", file)).collapsible(false).resizable(false).show(ctx, |ui| {
+                if pages.is_empty() {
+                    ui.label("Nothing links here");
+                }
+                for page in pages {
+                    ui.label(page.to_string_lossy());
+                }
+                close = ui.button("Thanks!").clicked();
+            });
+            if close { self.links_here = None }
+        }
+    }
+
+    /// Which built files differ from the live site
+    fn live_diff_dialog(&mut self, ctx: &Context) {
+        if let Some(diffs) = &self.live_diff {
+            let mut close = false;
+            let count = |status| diffs.iter().filter(|d| d.status == status).count();
+            egui::Window::new("Compared with live site").collapsible(false).resizable(false).show(ctx, |ui| {
+                ui.label(format!("{} changed, {} new, {} unchanged",
+                                 count(LiveStatus::Changed), count(LiveStatus::New), count(LiveStatus::Unchanged)));
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    for diff in diffs.iter().filter(|d| d.status != LiveStatus::Unchanged) {
+                        ui.horizontal(|ui| {
+                            match diff.status {
+                                LiveStatus::New => ui.colored_label(egui::Color32::GREEN, "new"),
+                                _ => ui.colored_label(egui::Color32::YELLOW, "changed")
+                            };
+                            ui.label(diff.path.to_string_lossy());
+                        });
+                    }
+                });
+                close = ui.button("Thanks!").clicked();
+            });
+            if close { self.live_diff = None }
+        }
+    }
+
+    /// A form for the settings in `_config.toml`
+    fn settings_dialog(&mut self, ctx: &Context) {
+        let Some(form) = &mut self.config_form else { return };
+        let (mut save, mut cancel) = (false, false);
+        egui::Window::new("Settings").collapsible(false).resizable(false).show(ctx, |ui| {
+            egui::Grid::new("settings").num_columns(2).show(ui, |ui| {
+                let text = |ui: &mut egui::Ui, label: &str, value: &mut String| {
+                    ui.label(label);
+                    ui.text_edit_singleline(value);
+                    ui.end_row();
+                };
+                text(ui, "Title", &mut form.title);
+                text(ui, "Feed title (blank for the title)", &mut form.feed_title);
+                text(ui, "Base url", &mut form.base_url);
+                text(ui, "Favicon", &mut form.favicon);
+                text(ui, "Image dir", &mut form.image_dir);
+                text(ui, "Image names ({date}, {random})", &mut form.image_name);
+                text(ui, "Default layout", &mut form.default_layout);
+                text(ui, "Output dir (blank to ask)", &mut form.output_dir);
+                text(ui, "Preserve on build (comma separated)", &mut form.preserve);
+                text(ui, "Minimum image width", &mut form.min_image_width);
+                text(ui, "Minimum image height", &mut form.min_image_height);
+                text(ui, "Shrink pasted images wider than", &mut form.max_image_width);
+                text(ui, "Shrink pasted images taller than", &mut form.max_image_height);
+                text(ui, "Auto deploy delay (seconds)", &mut form.auto_deploy_delay);
+                text(ui, "Deploy timeout (seconds, 0 for none)", &mut form.deploy_timeout);
+                text(ui, "Server port", &mut form.server_port);
+                text(ui, "Server concurrency", &mut form.server_concurrency);
+            });
+            egui::ComboBox::from_label("Save pasted images as")
+                .selected_text(form.paste_format.name())
+                .show_ui(ui, |ui| {
+                    for format in PasteFormat::ALL {
+                        ui.selectable_value(&mut form.paste_format, format, format.name());
+                    }
+                });
+            ui.checkbox(&mut form.inject_meta, "Add charset and viewport metas to layouts");
+            ui.checkbox(&mut form.strict, "Strict builds by default");
+            ui.checkbox(&mut form.drafts, "Show drafts in the preview server");
+            ui.checkbox(&mut form.live_reload, "Reload previewed pages when files change");
+            ui.checkbox(&mut form.hidden_noindex, "Build unpublished pages, marked noindex");
+            ui.checkbox(&mut form.line_numbers, "Number lines in code blocks");
+            ui.checkbox(&mut form.highlight_code, "Highlight code blocks (rather than in the browser)");
+            ui.checkbox(&mut form.gfm, "Footnotes, strikethrough, task lists and bare links in markdown");
+            ui.checkbox(&mut form.figures, "Number captioned images as figures");
+            ui.checkbox(&mut form.port_fallback, "Use another port if the server's is busy");
+            ui.checkbox(&mut form.safe_mode, "Safe mode (never start other programs)");
+
+            let problem = form.to_config().err();
+            if let Some(problem) = &problem {
+                ui.colored_label(egui::Color32::RED, problem);
+            }
+            ui.horizontal(|ui| {
+                save = ui.add_enabled(problem.is_none(), Button::green("Save")).clicked();
+                cancel = ui.button("Cancel").clicked();
+            });
+        });
+
+        if save {
+            let result = form.save(&self.site.root).and_then(|_| Site::open(&self.site.root));
+            match result {
+                Ok(new_site) => {
+                    self.set_site(new_site);
+                    self.config_form = None;
+                }
+                Err(e) => self.error = Some(e)
+            }
+        } else if cancel {
+            self.config_form = None
+        }
+    }
+
+    /// Whether an action makes sense right now. Buttons and the palette both check this.
+    fn enabled(&self, action: Action) -> bool {
+        let single_selection = self.selected_file.is_some() && !self.root_selected();
+        match action {
+            Action::NewPage | Action::NewLayout | Action::EditSettings | Action::ToggleTheme | Action::TogglePreview | Action::BuildSite | Action::Validate | Action::FixImageLinks | Action::ShowBuildLog => true,
+            Action::ImportFeed => !self.importing,
+            Action::CompareWithLive => self.site.config.base_url.is_some() && !self.comparing,
+            Action::OpenSelected => self.selected_file.is_some(),
+            Action::RenameSelected | Action::DuplicateSelected | Action::DeleteSelected | Action::TogglePin => single_selection,
+            Action::TogglePublished => single_selection && self.selected_file.as_ref().is_some_and(|f| f.ends_with(".md")),
+            Action::WhatLinksHere => single_selection,
+            Action::ShowFrontmatter => self.selected_file.as_ref().is_some_and(|f| f.ends_with(".md") || f.ends_with(".html")),
+            Action::BatchRename => self.selected_files.len() > 1 && !self.root_selected(),
+            Action::SetFrontmatter => self.selected_files.iter().any(|f| f.ends_with(".md")),
+            Action::PasteImage => self.site.image_dir_exists(),
+            Action::Refresh => true,
+            Action::BuildAndDeploy => !self.deploying,
+            Action::CancelDeploy => self.deploying,
+            Action::StartServer => self.server_handle.is_none() && self.server_port.parse::<u16>().is_ok(),
+            // Once it's been told to stop, it's only running until it's finished what it's doing
+            Action::StopServer | Action::OpenInBrowser => self.server_shutdown.is_some(),
+            Action::PreviewPage => {
+                let can_serve = self.server_handle.is_some() || self.server_port.parse::<u16>().is_ok();
+                can_serve && self.selected_file.as_ref().is_some_and(|f| page_url("", &self.relative_path(f)).is_some())
+            }
+            Action::PreviewBuilt => self.selected_file.as_ref().is_some_and(|f| f.ends_with(".md") && page_url("", &self.relative_path(f)).is_some()),
+            Action::OpenLive => self.site.config.base_url.is_some() && self.selected_file.as_ref().is_some_and(|f| page_url("", &self.relative_path(f)).is_some()),
+            Action::PreviewLayout => self.server_handle.is_some() && self.selected_file.as_ref().is_some_and(|f| {
+                let path = self.relative_path(f);
+                path.starts_with("_layouts") && path.extension().is_some_and(|e| e == "html")
+            })
+        }
+    }
+
+    /// Do the thing an action stands for, if it's currently enabled
+    fn perform(&mut self, action: Action) {
+        if !self.enabled(action) { return }
+        match action {
+            Action::NewPage => {
+                self.dialog_mode = Some(DialogMode::NewFile(String::new()));
+                self.dialog_just_opened = true;
+            }
+            Action::NewLayout => {
+                self.dialog_mode = Some(DialogMode::NewLayout(String::new()));
+                self.dialog_just_opened = true;
+            }
+            Action::OpenSelected => {
+                if let Some(fname) = self.selected_file.clone() { self.open_file(&fname) }
+            }
+            Action::RenameSelected => {
+                if let Some(selected) = &self.selected_file {
+                    self.dialog_mode = Some(DialogMode::RenameFile(utils::label_for_path(&PathBuf::from(&selected))));
+                    self.dialog_just_opened = true;
+                }
+            }
+            Action::DuplicateSelected => {
+                let Some(selected) = &self.selected_file else { return };
+                match self.site.duplicate_page(selected) {
+                    Ok((new_site, copy)) => {
+                        self.set_site(new_site);
+                        self.selected_file = Some(utils::id_for_path(&copy));
+                        self.selected_files = vec![utils::id_for_path(&copy)];
+                    }
+                    Err(e) => self.error = Some(e)
+                }
+            }
+            Action::DeleteSelected => {
+                self.dialog_mode = self.selected_file.clone().map(DialogMode::ConfirmDelete)
+            }
+            Action::BatchRename => {
+                self.dialog_mode = Some(DialogMode::BatchRename(BatchRenameForm::default()))
+            }
+            Action::SetFrontmatter => {
+                let pages = self.selected_files.iter().filter(|f| f.ends_with(".md")).map(|f| self.relative_path(f)).collect();
+                self.dialog_mode = Some(DialogMode::SetFrontmatter(FrontmatterForm::new(pages, &self.site.root)));
+                self.dialog_just_opened = true;
+            }
+            Action::ImportFeed => {
+                self.dialog_mode = Some(DialogMode::ImportFeed(ImportForm::default()));
+                self.dialog_just_opened = true;
+            }
+            Action::FixImageLinks => {
+                self.dialog_mode = Some(DialogMode::FixImageLinks(self.site.plan_image_link_fixes()))
+            }
+            Action::ShowFrontmatter => {
+                if let Some(selected) = &self.selected_file {
+                    let source = self.relative_path(selected);
+                    let fields = renderer::frontmatter_fields(&source, &self.site.root).map_err(|e| e.to_string());
+                    self.frontmatter_view = Some(FrontmatterView { file: utils::label_for_path(&source), fields })
+                }
+            }
+            Action::WhatLinksHere => {
+                if let Some(selected) = &self.selected_file {
+                    let path = self.relative_path(selected);
+                    self.links_here = Some((utils::label_for_path(&path), self.site.links_to(&path)))
+                }
+            }
+            Action::TogglePin => {
+                if let Some(selected) = &self.selected_file {
+                    let path = self.relative_path(selected);
+                    self.state.toggle_pin(&path);
+                    self.save_state();
+                }
+            }
+            Action::PasteImage => {
+                match self.site.paste_image() {
+                    Ok((new_site, path)) => {
+                        let size = fs::metadata(&path).map(|m| utils::format_size(m.len())).unwrap_or_default();
+                        match undersized_image(&path, &new_site.config) {
+                            Some(warning) => self.notifications.warning(format!("Image saved ({}), but: {}", size, warning)),
+                            None => self.notifications.info(format!("Image saved! ({})", size))
+                        };
+                        self.set_site(new_site);
+                    },
+                    Err(e) => self.notifications.error(e.to_string())
+                }
+            }
+            Action::TogglePublished => {
+                let Some(selected) = &self.selected_file else { return };
+                let source = self.relative_path(selected);
+                let result = renderer::read_frontmatter(&source, &self.site.root).map_err(FleenError::from).and_then(|frontmatter| {
+                    let published = frontmatter.is_none_or(|f| f.published());
+                    Ok((self.site.set_published(&source, !published)?, published))
+                });
+                match result {
+                    Ok((site, published)) => {
+                        self.set_site(site);
+                        self.notifications.info(if published { "Unpublished" } else { "Published" });
+                    }
+                    Err(e) => self.error = Some(e)
+                }
+            }
+            Action::BuildSite => {
+                let target = self.site.config.output_path(&self.site.root).or_else(|| rfd::FileDialog::new().pick_folder());
+                if let Some(path) = target {
+                    let started = Instant::now();
+                    let result = self.site.build_to_dir(&path, self.strict);
+                    let _ = build_log::append(&self.site.root, &BuildLogEntry::new("build", started, &result));
+                    match result {
+                        Ok(warnings) if warnings.is_empty() => self.notifications.info("Site built successfully"),
+                        Ok(warnings) => {
+                            self.message = Some("Site built with warnings".to_string());
+                            self.diagnostics = Some(warnings)
+                        }
+                        Err(err) => { self.error = Some(err) }
+                    }
+                }
+            }
+            Action::BuildAndDeploy => self.build_and_deploy(),
+            Action::ToggleTheme => {
+                // It takes effect next frame. Forgetting it for next time isn't worth an error.
+                self.preferences.dark_mode = Some(!self.dark_mode);
+                if let Some(path) = Preferences::path() { let _ = self.preferences.save(&path); }
+            }
+            Action::TogglePreview => {
+                self.show_preview = !self.show_preview;
+                // Nothing to keep an eye on while it's hidden
+                if !self.show_preview { self.page_preview = None }
+            }
+            Action::CancelDeploy => {
+                // The deploy notices this, stops the script, and reports back like any failure
+                self.deploy_cancel.store(true, Ordering::Relaxed);
+            }
+            Action::Refresh => {
+                // Everything we've worked out from the old copy of the site goes too
+                match Site::open(&self.site.root) {
+                    Ok(site) => {
+                        self.set_site(site);
+                        self.frontmatter_view = None;
+                        self.live_diff = None;
+                    }
+                    Err(e) => self.error = Some(e)
+                }
+            }
+            Action::ShowBuildLog => self.build_log = Some(build_log::recent(&self.site.root, 100)),
+            Action::Validate => self.diagnostics = Some(self.site.validate()),
+            Action::EditSettings => self.config_form = Some(ConfigForm::from(&self.site.config)),
+            Action::CompareWithLive => {
+                self.comparing = true;
+                let (site, mutex) = (self.site.clone(), self.live_diff_response.clone());
+                tokio::spawn(async move {
+                    let result = site.compare_with_live().await;
+                    if let Ok(mut m) = mutex.lock() {
+                        *m = Some(result);
+                    }
+                });
+            }
+            Action::StartServer => {
+                let Ok(port_num) = self.server_port.parse::<u16>() else { return };
+                let bound = bind_port(port_num, self.site.config.port_fallback, self.lan_access)
+                    .and_then(|listener| Ok((listener.local_addr()?, listener)));
+                match bound {
+                    Ok((address, listener)) => {
+                        let actual = address.port();
+                        if actual != port_num {
+                            self.notifications.info(format!("Port {} is busy, so the server is on {}", port_num, actual));
+                            self.server_port = actual.to_string();
+                        }
+                        // Bound to every interface, the address to share is the one the network knows us by
+                        self.server_host = match address.ip() {
+                            ip if ip.is_unspecified() => lan_address().unwrap_or(Ipv4Addr::LOCALHOST.into()),
+                            ip => ip
+                        };
+                        let (path, mutex) = (self.site.root.to_path_buf(), self.server_response.clone());
+                        let live_reload = self.site.config.live_reload;
+                        let (requests, lines) = mpsc::channel();
+                        self.server_requests = Some(lines);
+                        let (shutdown, stop) = oneshot::channel();
+                        self.server_shutdown = Some(shutdown);
+                        self.server_handle = Some(tokio::spawn(async move {
+                            let result = start_server(path, listener, live_reload, requests, stop).await;
+                            if let Ok(mut m) = mutex.lock() {
+                                *m = Some(result);
+                            }
+                        }))
+                    }
+                    Err(e) => self.error = Some(e)
+                }
+            }
+            Action::StopServer => {
+                // The server finishes up on its own. The handle stays until it has, and with it the
+                // port, so a new server can't be started while this one's still holding on to it.
+                if let Some(shutdown) = self.server_shutdown.take() {
+                    let _ = shutdown.send(());
+                }
+            }
+            Action::PreviewPage => {
+                let Some(selected) = &self.selected_file else { return };
+                let source = self.relative_path(selected);
+                let Some((start, mut url)) = plan_page_preview(self.server_handle.is_some(), &self.server_url(), &source) else { return };
+                if start {
+                    self.perform(Action::StartServer);
+                    // It may have ended up on another port
+                    let Some((_, started_url)) = plan_page_preview(true, &self.server_url(), &source).filter(|_| self.server_handle.is_some()) else { return };
+                    url = started_url
+                }
+                if let Err(e) = open_url(&url) { self.error = Some(e) }
+            }
+            Action::PreviewBuilt => {
+                let Some(selected) = &self.selected_file else { return };
+                let opened = self.site.render_to_temp(&self.relative_path(selected)).and_then(|path| {
+                    open_url(&path.to_string_lossy())?;
+                    Ok(path)
+                });
+                match opened {
+                    Ok(path) => {
+                        self.built_previews.push((Instant::now(), path));
+                        self.notifications.info("This is just the page, so images and links to the rest of the site may not work");
+                    }
+                    Err(e) => self.error = Some(e)
+                }
+            }
+            Action::OpenLive => {
+                let (Some(selected), Some(base_url)) = (&self.selected_file, &self.site.config.base_url) else { return };
+                if let Some(url) = page_url(base_url, &self.relative_path(selected)) && let Err(e) = open_url(&url) {
+                    self.error = Some(e)
+                }
+            }
+            Action::PreviewLayout => {
+                if let Some(selected) = &self.selected_file {
+                    let layout = self.relative_path(selected).to_string_lossy().replace('\\', "/");
+                    let url = format!("{}/_fleen/layout/{}", self.server_url(), layout);
+                    if let Err(e) = open_url(&url) { self.error = Some(e) }
+                }
+            }
+            Action::OpenInBrowser => {
+                if let Err(e) = open_url(&self.server_url()) { self.error = Some(e) }
+            }
+        }
+    }
+
+    /// A full-width button which performs an action, greyed out when the action isn't enabled
+    fn action_button(&mut self, ui: &mut egui::Ui, action: Action, button: impl egui::Widget) {
+        let enabled = self.enabled(action);
+        let mut response = ui.add_enabled_ui(enabled, |ui| ui.add_fill_width(button)).inner;
+        if let Some(shortcut) = action.shortcut() {
+            response = response.on_hover_text(ui.ctx().format_shortcut(&shortcut));
+        }
+        if response.clicked() {
+            self.perform(action)
+        }
+    }
+
+    /// Do whatever action's shortcut was pressed, unless someone's typing into a text field or
+    /// a dialog is open
+    fn check_shortcuts(&mut self, ctx: &Context) {
+        if ctx.wants_keyboard_input() || self.dialog_mode.is_some() || self.palette.is_some() { return }
+        let pressed = Action::ALL.into_iter().find(|action| {
+            action.shortcut().is_some_and(|shortcut| ctx.input_mut(|i| i.consume_shortcut(&shortcut)))
+        });
+        // Disabled actions (like deleting the root) do nothing, same as their buttons
+        if let Some(action) = pressed { self.perform(action) }
+    }
+
+    /// The actions the palette should offer for a query: matching ones that are currently enabled
+    fn palette_actions(&self, query: &str) -> Vec<Action> {
+        filter_actions(query).into_iter().filter(|a| self.enabled(*a)).collect()
+    }
+
+    fn palette_dialog(&mut self, ctx: &Context) {
+        let Some(palette) = &self.palette else { return };
+        let mut chosen = None;
+        let mut close = ctx.input(|i| i.key_pressed(Key::Escape));
+
+        let actions = self.palette_actions(&palette.query.clone());
+        let Some(palette) = &mut self.palette else { unreachable!() };
+        if ctx.input(|i| i.key_pressed(Key::ArrowDown)) { palette.selected += 1 }
+        if ctx.input(|i| i.key_pressed(Key::ArrowUp)) { palette.selected = palette.selected.saturating_sub(1) }
+        palette.selected = palette.selected.min(actions.len().saturating_sub(1));
+
+        egui::Window::new("Command palette").collapsible(false).resizable(false).show(ctx, |ui| {
+            let resp = ui.text_edit_singleline(&mut palette.query);
+            if !palette.focused {
+                resp.request_focus();
+                palette.focused = true;
+            }
+            if resp.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                chosen = actions.get(palette.selected).copied();
+            }
+            for (n, action) in actions.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.selectable_label(n == palette.selected, action.label()).clicked() {
+                        chosen = Some(*action)
+                    }
+                    if let Some(shortcut) = action.shortcut() { ui.weak(ctx.format_shortcut(&shortcut)); }
+                });
+            }
+            if actions.is_empty() { ui.weak("No matching actions"); }
+            if ui.button("Cancel").clicked() { close = true }
+        });
+
+        if let Some(action) = chosen {
+            self.palette = None;
+            self.perform(action);
+        } else if close {
+            self.palette = None;
         }
     }
 
     fn build_and_deploy(&mut self) {
         self.deploying = true;
         let mutex = self.deploy_response.clone();
+        let console = self.console.clone();
+        let options = DeployOptions { strict: self.strict, dry_run: self.dry_run, target: self.deploy_target };
+        if let Ok(mut log) = console.lock() {
+            log.clear();
+            log.push(if options.dry_run { "Building and deploying (dry run)..." } else { "Building and deploying..." });
+        }
         let site = self.site.clone();
+        let (sender, receiver) = mpsc::channel();
+        self.deploy_lines = Some(receiver);
+        self.deploy_cancel = Arc::new(AtomicBool::new(false));
+        let cancel = self.deploy_cancel.clone();
         tokio::spawn(async move {
-            let result = site.build_and_deploy().await;
+            let started = Instant::now();
+            let result = site.build_and_deploy(options, sender, cancel).await;
+            let action = match (options.target, options.dry_run) {
+                (DeployTarget::Script, false) => "deploy (script)",
+                (DeployTarget::Script, true) => "deploy (script, dry run)",
+                (DeployTarget::GitBranch, false) => "deploy (git branch)",
+                (DeployTarget::GitBranch, true) => "deploy (git branch, dry run)"
+            };
+            let _ = build_log::append(&site.root, &BuildLogEntry::new(action, started, &result));
+            let note = dry_run_note(&site.root, options.target);
+            let result = result
+                .map(|output| if options.dry_run { format!("{}:\n\n{}", note, output) } else { output });
+            // A script's output is already in the console, line by line, but failures from git or
+            // from before anything ran aren't
+            if let Ok(mut log) = console.lock() {
+                match &result {
+                    Ok(_) if options.dry_run => log.push(note),
+                    Ok(_) => {}
+                    Err(err @ (FleenError::DeployTimedOut | FleenError::DeployCancelled)) => log.push(err.to_string()),
+                    Err(FleenError::DeployError(_)) if options.target == DeployTarget::Script => {}
+                    Err(err) => log.push_text(&err.to_string())
+                }
+            }
             if let Ok(mut m) = mutex.lock() {
                 *m = Some(result);
             }
         });
     }
 
+    /// Which site this is, how big it is, and whether the server's up, along the very bottom
+    fn status_bar(&mut self, ctx: &Context) {
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let root = self.site.root.to_string_lossy().to_string();
+                if ui.link(&root).on_hover_text("Open the site's folder").clicked() && let Err(e) = open_filename(&root) {
+                    self.error = Some(e)
+                }
+                ui.separator();
+                let count = self.site.renderable_count();
+                ui.label(if count == 1 { "1 file".to_string() } else { format!("{} files", count) });
+                ui.separator();
+                if self.server_handle.is_some() {
+                    ui.colored_label(egui::Color32::from_rgb(0, 170, 0), "●");
+                    ui.label(format!("Server running on port {}", self.server_port));
+                } else {
+                    ui.colored_label(ui.visuals().weak_text_color(), "●");
+                    ui.label("Server stopped");
+                }
+            });
+        });
+    }
+
+    /// The output of the last script we ran, which sticks around after its dialog is gone
+    fn console_panel(&mut self, ctx: &Context) {
+        egui::TopBottomPanel::bottom("console").resizable(true).show(ctx, |ui| {
+            egui::CollapsingHeader::new("Console").id_salt("console_header").show(ui, |ui| {
+                let Ok(mut log) = self.console.lock() else { return };
+                ui.horizontal(|ui| {
+                    if ui.button("Copy").clicked() { ctx.copy_text(log.text()) }
+                    if ui.button("Clear").clicked() { log.clear() }
+                });
+                egui::ScrollArea::vertical().max_height(200.0).stick_to_bottom(true).auto_shrink([false, true]).show(ui, |ui| {
+                    if log.is_empty() { ui.weak("Nothing yet"); }
+                    for line in log.lines() {
+                        ui.monospace(line);
+                    }
+                });
+            });
+        });
+    }
+
+    /// The selected markdown page, rendered with its layout. It's rendered again whenever the
+    /// file changes, which a thread watches for so we aren't redrawing for nothing.
+    fn preview_panel(&mut self, ctx: &Context) {
+        if !self.show_preview { return }
+        egui::SidePanel::right("preview").resizable(true).default_width(300.0).show(ctx, |ui| {
+            ui.heading("Preview");
+            ui.separator();
+            let Some(selected) = self.selected_file.clone().filter(|f| f.ends_with(".md")) else {
+                self.page_preview = None;
+                ui.weak("Select a markdown page to preview it here");
+                return
+            };
+            let modified = fs::metadata(&selected).and_then(|m| m.modified()).ok();
+            if !self.page_preview.as_ref().is_some_and(|p| p.file == selected && p.modified == modified) {
+                let blocks = preview::preview_page(&self.relative_path(&selected), &self.site.root, &self.site.config).map_err(|e| e.to_string());
+                let stop_watching = watch_modified(ctx.clone(), selected.clone(), modified);
+                self.page_preview = Some(PagePreview { file: selected, modified, blocks, stop_watching });
+            }
+
+            let Some(page_preview) = &self.page_preview else { return };
+            egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+                match &page_preview.blocks {
+                    Ok(blocks) if blocks.is_empty() => { ui.weak("Nothing to show"); }
+                    Ok(blocks) => for block in blocks {
+                        match block {
+                            Block::Heading(level, text) => { ui.label(RichText::new(text).strong().size(24.0 - 2.0 * *level as f32)); }
+                            Block::Paragraph(text) => { ui.label(text); }
+                            Block::ListItem(text) => { ui.label(format!("• {}", text)); }
+                            Block::Code(code) => { ui.code(code); }
+                            Block::Rule => { ui.separator(); }
+                        }
+                    }
+                    Err(e) => { ui.colored_label(ui.visuals().error_fg_color, e); }
+                }
+            });
+        });
+    }
+
+    /// Bookmarked files: click to select, double-click to open
+    fn pinned_files(&mut self, ui: &mut egui::Ui) {
+        if self.state.pins.is_empty() { return }
+        ui.label("Pinned");
+        for pin in self.state.pins.clone() {
+            let path = utils::id_for_path(&self.site.root.join(&pin));
+            let selected = self.selected_file.as_ref() == Some(&path);
+            let resp = ui.add_fill_width(egui::Button::selectable(selected, pin.to_string_lossy()));
+            if resp.clicked() {
+                self.selected_file = Some(path.clone());
+                self.selected_files = vec![path.clone()];
+            }
+            if resp.double_clicked() { self.open_file(&path) }
+        }
+        ui.separator();
+    }
+
+    /// The sort order and modified-time toggle above the tree
+    fn tree_options(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("tree_sort")
+                .selected_text(match self.tree_sort {
+                    TreeSort::Name => "By name",
+                    TreeSort::NewestFirst => "Newest first",
+                    TreeSort::OldestFirst => "Oldest first"
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.tree_sort, TreeSort::Name, "By name");
+                    ui.selectable_value(&mut self.tree_sort, TreeSort::NewestFirst, "Newest first");
+                    ui.selectable_value(&mut self.tree_sort, TreeSort::OldestFirst, "Oldest first");
+                });
+            ui.checkbox(&mut self.show_modified, "Show modified");
+            if ui.button("Refresh").on_hover_text("Re-read the site from disk").clicked() {
+                self.perform(Action::Refresh);
+                ui.ctx().request_repaint();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.tree_filter).hint_text("Filter files"));
+            if !self.tree_filter.is_empty() && ui.button("Clear").clicked() {
+                self.tree_filter.clear()
+            }
+        });
+    }
+
     fn tree_view(&mut self, ui: &mut egui::Ui) {
         let tv = egui_ltreeview::TreeView::new(Id::from("tree"))
-            .allow_multi_selection(false)
+            .allow_multi_selection(true)
             .allow_drag_and_drop(false);
+        let show_modified = self.show_modified;
+        let label = |entry: &TreeEntry, path: &PathBuf| {
+            match entry.modified() {
+                Some(time) if show_modified => {
+                    let time: chrono::DateTime<chrono::Local> = time.into();
+                    format!("{}  ({})", utils::label_for_path(path), time.format("%Y-%m-%d %H:%M"))
+                }
+                _ => utils::label_for_path(path)
+            }
+        };
         let (_, actions) = tv.show(ui, |builder| {
-            for entry in self.site.tree.iter() {
+            for entry in filter_tree(&sort_tree(&self.site.tree, self.tree_sort), &self.site.root, &self.tree_filter).iter() {
                 match entry {
-                    TreeEntry::File(p) => builder.leaf(utils::id_for_path(p), utils::label_for_path(p)),
-                    TreeEntry::Dir(p) => { builder.dir(utils::id_for_path(p), utils::label_for_path(p)); },
+                    TreeEntry::File(p, _) => builder.leaf(utils::id_for_path(p), label(entry, p)),
+                    TreeEntry::Dir(p, _) => { builder.dir(utils::id_for_path(p), label(entry, p)); },
                     TreeEntry::CloseDir => builder.close_dir()
                 }
             }
@@ -168,12 +1238,13 @@ impl SiteUi {
 
         for action in actions {
             match action {
-                Action::SetSelected(files) => {
-                    self.selected_file = files.first().cloned()
+                TreeAction::SetSelected(files) => {
+                    self.selected_file = files.first().cloned();
+                    self.selected_files = files;
                 }
-                Action::Activate(activate) => {
+                TreeAction::Activate(activate) => {
                     for fname in activate.selected {
-                        if let Err(e) = open_filename(&fname) { self.error = Some(e) }
+                        self.open_file(&fname)
                     }
                 }
                 _ => {}
@@ -181,86 +1252,52 @@ impl SiteUi {
         }
     }
 
-    fn tree_buttons(&mut self, ui: &mut egui::Ui) -> bool {
-        let mut just_clicked = false;
-
-        if ui.add_fill_width(egui::Button::new("Open")).clicked() &&
-            let Some(fname) = &self.selected_file &&
-            let Err(e) = open_filename(fname) {
-            self.error = Some(e)
-        }
-
-        let new_btn = Button::green("New page");
-        if ui.add_fill_width(new_btn).clicked() {
-            just_clicked = true;
-            self.dialog_mode = Some(DialogMode::NewFile(String::new()));
-        }
-
-        let rename_btn = Button::new("Rename");
-        let delete_btn = Button::red("Delete");
-        if self.root_selected() || self.selected_file.is_none() {
-            ui.add_enabled_ui(false, |ui| {
-                ui.add_fill_width(rename_btn);
-                ui.add_fill_width(delete_btn);
-            });
-        } else if let Some(selected) = &self.selected_file {
-            if ui.add_fill_width(rename_btn).clicked() {
-                self.dialog_mode = Some(DialogMode::RenameFile(utils::label_for_path(&PathBuf::from(&selected))));
-                just_clicked = true;
-            }
-            if ui.add_fill_width(delete_btn).clicked() {
-                self.dialog_mode = Some(DialogMode::ConfirmDelete(selected.clone()));
-            }
-        }
+    fn tree_buttons(&mut self, ui: &mut egui::Ui) {
+        self.action_button(ui, Action::OpenSelected, Button::new("Open"));
+        self.action_button(ui, Action::NewPage, Button::green("New page"));
+        self.action_button(ui, Action::NewLayout, Button::green("New layout"));
+        self.action_button(ui, Action::RenameSelected, Button::new("Rename"));
+        self.action_button(ui, Action::DuplicateSelected, Button::new("Duplicate"));
+        self.action_button(ui, Action::DeleteSelected, Button::red("Delete"));
+        self.action_button(ui, Action::BatchRename, Button::new("Batch rename"));
+        self.action_button(ui, Action::SetFrontmatter, Button::new("Set frontmatter..."));
+        self.action_button(ui, Action::ShowFrontmatter, Button::new("Show frontmatter"));
+        self.action_button(ui, Action::WhatLinksHere, Button::new("What links here"));
+        self.action_button(ui, Action::FixImageLinks, Button::new("Fix image links..."));
+        self.action_button(ui, Action::ImportFeed, Button::new("Import feed..."));
+        let pinned = self.selected_file.as_ref().is_some_and(|f| self.state.is_pinned(&self.relative_path(f)));
+        self.action_button(ui, Action::TogglePin, Button::new(if pinned { "Unpin" } else { "Pin" }));
+        self.action_button(ui, Action::TogglePublished, Button::new("Publish / unpublish"));
 
-        ui.add_enabled_ui(self.site.image_dir_exists() && self.image_message.is_none(), |ui| {
-            let label = match &self.image_message {
-                Some(TempMessage { message, .. }) => message.as_str(),
-                _ => "Image from clipboard"
-            };
+        self.action_button(ui, Action::PasteImage, Button::blue("Image from clipboard"));
+    }
 
-            if ui.add_fill_width(Button::blue(label)).clicked() {
-                self.image_message = match self.site.paste_image() {
-                    Ok(new_site) => {
-                        self.site = Arc::new(new_site);
-                        Some(TempMessage { message: "Image saved!".to_string(), created: Instant::now() })
-                    },
-                    Err(e) => Some(TempMessage { message: e.to_string(), created: Instant::now() })
-                }
-            }
-        });
-        just_clicked
+    /// The address of the server's front page
+    fn server_url(&self) -> String {
+        format!("http://{}:{}", self.server_host, self.server_port)
     }
 
     fn server_controls(&mut self, ui: &mut egui::Ui) {
         ui.vertical(|ui| {
             ui.label("Port");
-            let open_button = Button::new(format!("Open http://localhost:{}", self.server_port));
+            let running = self.server_handle.is_some();
             let port_editor = egui::TextEdit::singleline(&mut self.server_port);
-
-            if let Some(join_handle) = &self.server_handle {
+            if running {
                 ui.add_enabled_ui(false, |ui| ui.add_fill_width(port_editor));
-                let stop_btn = Button::red("Stop server");
-                if ui.add_fill_width(stop_btn).clicked() {
-                    join_handle.abort();
-                    self.server_handle = None;
-                }
-                if ui.add_fill_width(open_button).clicked() {
-                    open_server(self.server_port.as_str());
-                }
+                let stop_label = if self.server_shutdown.is_some() { "Stop server" } else { "Stopping..." };
+                self.action_button(ui, Action::StopServer, Button::red(stop_label));
             } else {
                 ui.add(port_editor);
-                let start_btn = Button::green("Start server");
-                if let Ok(port_num) = self.server_port.parse::<u32>() {
-                    if ui.add_fill_width(start_btn).clicked() {
-                        let path = self.site.root.to_path_buf();
-                        self.server_handle = Some(tokio::spawn(start_server(path, port_num)))
-                    }
-                } else {
-                    ui.add_enabled_ui(false, |ui| ui.add_fill_width(start_btn));
-                }
-                ui.add_enabled_ui(false, |ui| ui.add_fill_width(open_button));
+                self.action_button(ui, Action::StartServer, Button::green("Start server"));
             }
+            let lan_toggle = egui::Checkbox::new(&mut self.lan_access, "Let other devices on the network see it");
+            ui.add_enabled(!running, lan_toggle).on_hover_text("Otherwise only this computer can reach the server");
+            let open_button = Button::new(format!("Open {}", self.server_url()));
+            self.action_button(ui, Action::OpenInBrowser, open_button);
+            self.action_button(ui, Action::PreviewPage, Button::new("Preview selected page"));
+            self.action_button(ui, Action::PreviewBuilt, Button::new("Preview built HTML"));
+            self.action_button(ui, Action::OpenLive, Button::new("Open selected page live"));
+            self.action_button(ui, Action::PreviewLayout, Button::new("Preview selected layout"));
         });
     }
 
@@ -291,7 +1328,7 @@ impl SiteUi {
                             self.selected_file.as_ref());
                     match r {
                         Ok(new_site) => {
-                            self.site = Arc::new(new_site);
+                            self.set_site(new_site);
                             self.dialog_mode = None;
                         }
                         Err(e) => { self.error = Some(e) }
@@ -309,6 +1346,35 @@ impl SiteUi {
         });
     }
 
+    fn new_layout_dialog(&mut self, ctx: &Context, just_clicked: bool) {
+        egui::Window::new("New layout").collapsible(false).resizable(false).show(ctx, |ui| {
+            ui.label("Name (it goes in _layouts/)");
+            let Some(DialogMode::NewLayout(name)) = &mut self.dialog_mode else { unreachable!() };
+            let resp = ui.text_edit_singleline(name);
+            let enter_key = resp.lost_focus();
+            if just_clicked { resp.request_focus() } // See new_file_dialog
+
+            ui.horizontal(|ui| {
+                let btn = ui.button("Create");
+                if enter_key { btn.request_focus() }
+                if btn.clicked() {
+                    let Some(DialogMode::NewLayout(name)) = &self.dialog_mode else { unreachable!() };
+                    match self.site.create_layout(name) {
+                        Ok((new_site, path)) => {
+                            self.set_site(new_site);
+                            self.dialog_mode = None;
+                            self.open_file(&path.to_string_lossy())
+                        }
+                        Err(e) => { self.error = Some(e) }
+                    }
+                }
+                if ui.button("Cancel").clicked() {
+                    self.dialog_mode = None
+                }
+            })
+        });
+    }
+
     fn rename_dialog(&mut self, ctx: &Context, just_clicked: bool) {
         egui::Window::new("Rename").collapsible(false).resizable(false).show(ctx, |ui| {
             ui.label("New name");
@@ -325,7 +1391,8 @@ impl SiteUi {
                     let Some(DialogMode::RenameFile(fname)) = &self.dialog_mode else { unreachable!() };
                     match self.site.rename_page(self.selected_file.as_ref().unwrap(), fname) {
                         Ok(new_site) => {
-                            self.site = Arc::new(new_site);
+                            self.set_site(new_site);
+                            self.notifications.info("Renamed");
                             self.dialog_mode = None; // Close the dialog, we're done
                         }
                         Err(e) => { self.error = Some(e) }
@@ -338,12 +1405,258 @@ impl SiteUi {
         });
     }
 
+    fn batch_rename_dialog(&mut self, ctx: &Context) {
+        let Some(DialogMode::BatchRename(form)) = &mut self.dialog_mode else { unreachable!() };
+        let (mut rename, mut cancel) = (false, false);
+        egui::Window::new("Batch rename").collapsible(false).resizable(false).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut form.use_replace, false, "Pattern");
+                ui.radio_value(&mut form.use_replace, true, "Find and replace");
+            });
+            if form.use_replace {
+                ui.label("Find");
+                ui.text_edit_singleline(&mut form.find);
+                ui.label("Replace with");
+                ui.text_edit_singleline(&mut form.replace);
+            } else {
+                ui.label("Pattern ({name}, {stem}, {ext}, {date}, {n})");
+                ui.text_edit_singleline(&mut form.pattern);
+            }
+
+            // Preview what every file will be called, marking any that collide
+            let paths: Vec<PathBuf> = self.selected_files.iter().map(PathBuf::from).collect();
+            let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+            let plan = plan_batch_rename(&paths, &form.rename_pattern(), &today);
+            egui::Grid::new("batch_rename_preview").show(ui, |ui| {
+                for step in plan.iter() {
+                    ui.label(utils::label_for_path(&step.from));
+                    let new_name = utils::label_for_path(&step.to);
+                    if step.collision {
+                        ui.colored_label(egui::Color32::RED, format!("{} (collision)", new_name));
+                    } else {
+                        ui.label(new_name);
+                    }
+                    ui.end_row();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let valid = plan.iter().all(|p| !p.collision);
+                rename = ui.add_enabled(valid, Button::new("Rename all")).clicked();
+                cancel = ui.button("Cancel").clicked();
+            })
+        });
+
+        if rename {
+            let pattern = form.rename_pattern();
+            match self.site.batch_rename(&self.selected_files, &pattern) {
+                Ok(new_site) => {
+                    self.set_site(new_site);
+                    self.notifications.info(format!("Renamed {} files", self.selected_files.len()));
+                    self.dialog_mode = None;
+                    self.selected_file = None;
+                    self.selected_files.clear();
+                }
+                Err(e) => { self.error = Some(e) }
+            }
+        } else if cancel {
+            self.dialog_mode = None
+        }
+    }
+
+    /// Set or remove one frontmatter key on every selected page, showing what each has now first
+    fn set_frontmatter_dialog(&mut self, ctx: &Context, just_clicked: bool) {
+        let Some(DialogMode::SetFrontmatter(form)) = &mut self.dialog_mode else { unreachable!() };
+        let (mut apply, mut cancel) = (false, false);
+        egui::Window::new("Set frontmatter").collapsible(false).resizable(false).show(ctx, |ui| {
+            ui.label("Key");
+            let key_field = ui.text_edit_singleline(&mut form.key);
+            if just_clicked { key_field.request_focus() }
+            ui.checkbox(&mut form.remove, "Remove this key");
+            if !form.remove {
+                ui.label("Value (TOML, or plain text for a string)");
+                ui.text_edit_singleline(&mut form.value);
+            }
+
+            let key = form.key.trim();
+            let new_value = if form.remove { "(removed)".to_string() } else { renderer::parse_field_value(&form.value).to_string().trim().to_string() };
+            egui::Grid::new("set_frontmatter_preview").show(ui, |ui| {
+                for (page, contents) in form.pages.iter() {
+                    ui.label(page.to_string_lossy());
+                    let current = contents.as_deref().and_then(|c| renderer::frontmatter_field(c, key));
+                    ui.label(current.unwrap_or("(not set)".to_string()));
+                    ui.label(format!("→ {}", new_value));
+                    ui.end_row();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                apply = ui.add_enabled(!key.is_empty(), Button::new("Apply to all")).clicked();
+                cancel = ui.button("Cancel").clicked();
+            })
+        });
+
+        if apply {
+            let key = form.key.trim().to_string();
+            let value = (!form.remove).then(|| renderer::parse_field_value(&form.value));
+            let pages: Vec<PathBuf> = form.pages.iter().map(|(page, _)| page.clone()).collect();
+            match self.site.set_frontmatter_field(&pages, &key, value) {
+                Ok(new_site) => {
+                    self.set_site(new_site);
+                    self.notifications.info(format!("Updated {} pages", pages.len()));
+                    self.dialog_mode = None;
+                }
+                Err(e) => { self.error = Some(e) }
+            }
+        } else if cancel {
+            self.dialog_mode = None
+        }
+    }
+
+    /// Preview the image links we can fix (and the ones we can't) before rewriting anything
+    fn fix_image_links_dialog(&mut self, ctx: &Context) {
+        let Some(DialogMode::FixImageLinks(report)) = &self.dialog_mode else { unreachable!() };
+        let (mut apply, mut cancel) = (false, false);
+        egui::Window::new("Fix image links").collapsible(false).resizable(false).show(ctx, |ui| {
+            if report.fixes.is_empty() && report.unresolved.is_empty() {
+                ui.label("No broken image links");
+            }
+            egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                egui::Grid::new("image_link_fixes").striped(true).show(ui, |ui| {
+                    for fix in report.fixes.iter() {
+                        ui.strong(fix.file.to_string_lossy());
+                        ui.label(&fix.from);
+                        ui.label(format!("→ {}", fix.to));
+                        ui.end_row();
+                    }
+                    for diagnostic in report.unresolved.iter() {
+                        ui.strong(diagnostic.file.to_string_lossy());
+                        ui.colored_label(egui::Color32::YELLOW, &diagnostic.message);
+                        ui.end_row();
+                    }
+                });
+            });
+            ui.horizontal(|ui| {
+                apply = ui.add_enabled(!report.fixes.is_empty(), Button::new("Fix links")).clicked();
+                cancel = ui.button("Cancel").clicked();
+            })
+        });
+
+        if apply {
+            match self.site.apply_link_fixes(&report.fixes) {
+                Ok(new_site) => {
+                    self.set_site(new_site);
+                    self.dialog_mode = None;
+                }
+                Err(e) => { self.error = Some(e) }
+            }
+        } else if cancel {
+            self.dialog_mode = None
+        }
+    }
+
+    fn import_feed_dialog(&mut self, ctx: &Context, just_clicked: bool) {
+        let Some(DialogMode::ImportFeed(form)) = &mut self.dialog_mode else { unreachable!() };
+        let (mut import, mut cancel) = (false, false);
+        egui::Window::new("Import feed").collapsible(false).resizable(false).show(ctx, |ui| {
+            ui.label("RSS, Atom or JSON feed (a url or a file)");
+            ui.horizontal(|ui| {
+                let resp = ui.text_edit_singleline(&mut form.source);
+                if just_clicked { resp.request_focus() } // See new_file_dialog
+                if ui.button("Choose file...").clicked() && let Some(path) = rfd::FileDialog::new().pick_file() {
+                    form.source = path.to_string_lossy().to_string()
+                }
+            });
+            ui.label("Put the posts in");
+            ui.text_edit_singleline(&mut form.dir);
+            ui.horizontal(|ui| {
+                let label = if self.importing { "Importing..." } else { "Import" };
+                import = ui.add_enabled(!self.importing && !form.source.is_empty(), Button::green(label)).clicked();
+                cancel = ui.button("Cancel").clicked();
+            })
+        });
+
+        if import {
+            self.importing = true;
+            let (site, mutex) = (self.site.clone(), self.import_response.clone());
+            let (source, dir) = (form.source.clone(), form.dir.clone());
+            tokio::spawn(async move {
+                let result = site.import_feed(&source, &dir).await;
+                if let Ok(mut m) = mutex.lock() {
+                    *m = Some(result);
+                }
+            });
+        } else if cancel {
+            self.dialog_mode = None
+        }
+    }
+
+    /// Closing the window would cut off the server and any deploy, so ask first. Once the user's
+    /// sure, let them finish up before really closing.
+    fn check_close_request(&mut self, ctx: &Context) {
+        if let Some(started) = self.quitting {
+            let settled = self.server_handle.is_none() && !self.deploying;
+            if settled || started.elapsed() > QUIT_GRACE_PERIOD {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close)
+            } else {
+                ctx.request_repaint_after(Duration::from_millis(100))
+            }
+            return
+        }
+        if ctx.input(|i| i.viewport().close_requested()) && (self.server_handle.is_some() || self.deploying) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.dialog_mode = Some(DialogMode::ConfirmQuit);
+        }
+    }
+
+    fn confirm_quit_dialog(&mut self, ctx: &Context) {
+        let (mut quit, mut cancel) = (false, false);
+        egui::Window::new("Quit Fleen?").collapsible(false).resizable(false).show(ctx, |ui| {
+            if self.server_handle.is_some() { ui.label("The preview server is running. It'll be stopped."); }
+            if self.deploying { ui.label("A deploy is still going. It'll be cancelled."); }
+            ui.horizontal(|ui| {
+                quit = ui.add(Button::red("Quit")).clicked();
+                cancel = ui.button("Cancel").clicked();
+            })
+        });
+        if quit {
+            self.quit()
+        } else if cancel {
+            self.dialog_mode = None
+        }
+    }
+
+    /// Stop the server and any deploy, and close the window once they have
+    fn quit(&mut self) {
+        // Like the Stop server button; the handle stays so we can tell when it's done
+        if let Some(shutdown) = self.server_shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if self.deploying { self.deploy_cancel.store(true, Ordering::Relaxed) }
+        self.quitting = Some(Instant::now());
+        self.dialog_mode = None;
+    }
+
     fn confirm_delete_dialog(&mut self, ctx: &Context) {
         let (mut del, mut cancel) = (false, false);
-        let Some(DialogMode::ConfirmDelete(fname)) = &self.dialog_mode else { unreachable!() };
+        let (fname, permanently) = match &self.dialog_mode {
+            Some(DialogMode::ConfirmDelete(fname)) => (fname.clone(), !CAN_TRASH),
+            Some(DialogMode::ConfirmPermanentDelete(fname, _)) => (fname.clone(), true),
+            _ => unreachable!()
+        };
         egui::Window::new("Are you sure?").collapsible(false).resizable(false).show(ctx, |ui| {
-            let file = utils::label_for_path(&PathBuf::from(fname));
-            ui.heading(format!("Really delete {}?", file));
+            let file = utils::label_for_path(&PathBuf::from(&fname));
+            match &self.dialog_mode {
+                Some(DialogMode::ConfirmPermanentDelete(_, err)) => {
+                    ui.heading(format!("Delete {} for good?", file));
+                    ui.label(format!("It couldn't go in the trash ({}), and this can't be undone.", err));
+                }
+                _ if CAN_TRASH => { ui.heading(format!("Move {} to the trash?", file)); }
+                _ => {
+                    ui.heading(format!("Really delete {}?", file));
+                    ui.label("This can't be undone: there's no trash to put it in.");
+                }
+            }
             ui.horizontal(|ui| {
                 let btn = Button::red("Yep, I'm sure");
                 del = ui.add(btn).clicked();
@@ -352,33 +1665,512 @@ impl SiteUi {
         });
 
         if del {
-            match self.site.delete_page(fname) {
-                Ok(new_site) => { self.site = Arc::new(new_site) }
+            self.dialog_mode = None;
+            match self.site.delete_page(&fname, permanently) {
+                Ok(new_site) => { self.set_site(new_site) }
+                // Offer to delete it outright instead
+                Err(FleenError::TrashFailed(_, err)) => {
+                    self.dialog_mode = Some(DialogMode::ConfirmPermanentDelete(fname, err));
+                    return
+                }
                 Err(e) => { self.error = Some(e) }
             }
-            self.dialog_mode = None;
             self.selected_file = None;
+            self.selected_files.clear();
         } else if cancel {
             self.dialog_mode = None
         }
     }
 
     fn root_selected(&self) -> bool {
-        if let Some(path) = &self.selected_file {
-            *path == self.site.root
-        } else {
-            false
-        }
+        self.selected_files.iter().any(|path| *path == self.site.root)
     }
 }
 
 enum DialogMode {
     NewFile(String),
     ConfirmDelete(String),
-    RenameFile(String)
+    /// Deleting for good, since the trash wouldn't take it: the file, and why not
+    ConfirmPermanentDelete(String, String),
+    ConfirmQuit,
+    RenameFile(String),
+    BatchRename(BatchRenameForm),
+    SetFrontmatter(FrontmatterForm),
+    FixImageLinks(ImageLinkReport),
+    ImportFeed(ImportForm),
+    NewLayout(String)
+}
+
+struct ImportForm {
+    source: String,
+    dir: String
+}
+
+impl Default for ImportForm {
+    fn default() -> Self {
+        Self { source: String::new(), dir: "posts".to_string() }
+    }
+}
+
+struct FrontmatterForm {
+    key: String,
+    value: String,
+    remove: bool,
+    /// Each page with its contents as of opening the dialog, for the preview
+    pages: Vec<(PathBuf, Option<String>)>
+}
+
+impl FrontmatterForm {
+    fn new(pages: Vec<PathBuf>, root: &Path) -> Self {
+        let pages = pages.into_iter().map(|page| {
+            let contents = std::fs::read_to_string(root.join(&page)).ok();
+            (page, contents)
+        }).collect();
+        Self { key: String::new(), value: String::new(), remove: false, pages }
+    }
+}
+
+struct BatchRenameForm {
+    use_replace: bool,
+    pattern: String,
+    find: String,
+    replace: String
+}
+
+impl Default for BatchRenameForm {
+    fn default() -> Self {
+        Self { use_replace: false, pattern: "{date}-{name}".to_string(), find: String::new(), replace: String::new() }
+    }
+}
+
+impl BatchRenameForm {
+    fn rename_pattern(&self) -> RenamePattern {
+        if self.use_replace {
+            RenamePattern::Replace(self.find.clone(), self.replace.clone())
+        } else {
+            RenamePattern::Template(self.pattern.clone())
+        }
+    }
+}
+
+/// Everything the user can do from the buttons or the command palette
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Action {
+    NewPage,
+    NewLayout,
+    OpenSelected,
+    RenameSelected,
+    DuplicateSelected,
+    DeleteSelected,
+    BatchRename,
+    SetFrontmatter,
+    TogglePin,
+    TogglePublished,
+    ShowFrontmatter,
+    WhatLinksHere,
+    FixImageLinks,
+    ImportFeed,
+    PasteImage,
+    BuildSite,
+    BuildAndDeploy,
+    CancelDeploy,
+    Validate,
+    ShowBuildLog,
+    CompareWithLive,
+    EditSettings,
+    ToggleTheme,
+    TogglePreview,
+    StartServer,
+    StopServer,
+    OpenInBrowser,
+    PreviewPage,
+    PreviewBuilt,
+    OpenLive,
+    PreviewLayout,
+    Refresh
+}
+
+impl Action {
+    const ALL: [Action; 32] = [
+        Action::NewPage, Action::NewLayout, Action::OpenSelected, Action::RenameSelected, Action::DuplicateSelected, Action::DeleteSelected,
+        Action::BatchRename, Action::SetFrontmatter, Action::TogglePin, Action::TogglePublished, Action::ShowFrontmatter, Action::WhatLinksHere, Action::FixImageLinks, Action::ImportFeed, Action::PasteImage, Action::BuildSite, Action::BuildAndDeploy, Action::CancelDeploy,
+        Action::Validate, Action::ShowBuildLog, Action::CompareWithLive, Action::EditSettings, Action::ToggleTheme, Action::TogglePreview, Action::StartServer, Action::StopServer, Action::OpenInBrowser,
+        Action::PreviewPage, Action::PreviewBuilt, Action::OpenLive, Action::PreviewLayout, Action::Refresh
+    ];
+
+    /// The key that does this without going near the mouse, if it has one
+    fn shortcut(&self) -> Option<KeyboardShortcut> {
+        match self {
+            Action::NewPage => Some(KeyboardShortcut::new(Modifiers::COMMAND, Key::N)),
+            Action::RenameSelected => Some(KeyboardShortcut::new(Modifiers::COMMAND, Key::R)),
+            Action::DeleteSelected => Some(KeyboardShortcut::new(Modifiers::NONE, Key::Delete)),
+            Action::BuildSite => Some(KeyboardShortcut::new(Modifiers::COMMAND, Key::B)),
+            _ => None
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Action::NewPage => "New page",
+            Action::NewLayout => "New layout",
+            Action::OpenSelected => "Open selected file",
+            Action::RenameSelected => "Rename selected file",
+            Action::DuplicateSelected => "Make a copy of selected file",
+            Action::DeleteSelected => "Delete selected file",
+            Action::BatchRename => "Batch rename",
+            Action::SetFrontmatter => "Set a frontmatter field on selected pages",
+            Action::TogglePin => "Pin / unpin selected file",
+            Action::TogglePublished => "Publish / unpublish selected page",
+            Action::ShowFrontmatter => "Show frontmatter",
+            Action::WhatLinksHere => "What links to the selected file",
+            Action::FixImageLinks => "Fix moved image links",
+            Action::ImportFeed => "Import posts from a feed",
+            Action::PasteImage => "Image from clipboard",
+            Action::BuildSite => "Build site",
+            Action::BuildAndDeploy => "Build and deploy",
+            Action::CancelDeploy => "Cancel deploy",
+            Action::Validate => "Validate site",
+            Action::ShowBuildLog => "Show build log",
+            Action::CompareWithLive => "Compare with live site",
+            Action::EditSettings => "Edit site settings",
+            Action::ToggleTheme => "Switch between light and dark theme",
+            Action::TogglePreview => "Show / hide the page preview",
+            Action::StartServer => "Start server",
+            Action::StopServer => "Stop server",
+            Action::OpenInBrowser => "Open in browser",
+            Action::PreviewPage => "Preview selected page in browser",
+            Action::PreviewBuilt => "Preview built HTML in browser",
+            Action::OpenLive => "Open selected page on the live site",
+            Action::PreviewLayout => "Preview selected layout",
+            Action::Refresh => "Refresh the site from disk"
+        }
+    }
+}
+
+/// Where a page ends up under some base url (the preview server, or the live site), for a source
+/// file relative to the root. Only pages get urls: markdown, and html. The url itself is the
+/// same one the sitemap uses.
+fn page_url(base_url: &str, source: &Path) -> Option<String> {
+    let ext = source.extension()?;
+    if ext != "md" && ext != "html" || renderer::skipped_path(source.to_path_buf()) { return None }
+    Some(sitemap::page_url(base_url.trim_end_matches('/'), &source.with_extension("html")))
+}
+
+/// What to say after a dry run. Without a `deploy-dryrun.sh`, the real script ran, and all we
+/// can say is that it was asked to hold back.
+fn dry_run_note(root: &Path, target: DeployTarget) -> &'static str {
+    if target == DeployTarget::Script && !root.join("_scripts/deploy-dryrun.sh").exists() {
+        "Dry run (FLEEN_DRY_RUN=1 was passed to deploy.sh)"
+    } else {
+        "Dry run, nothing was deployed"
+    }
+}
+
+/// Previewing a page: whether the server needs starting first, and the url to open
+fn plan_page_preview(server_running: bool, server_url: &str, source: &Path) -> Option<(bool, String)> {
+    Some((!server_running, page_url(server_url, source)?))
+}
+
+/// How well a query fuzzily matches a label: every query character has to appear in order.
+/// Lower is better; None is no match.
+fn fuzzy_score(query: &str, label: &str) -> Option<usize> {
+    let label: Vec<char> = label.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    for ch in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = label[pos..].iter().position(|c| *c == ch)?;
+        score += found; // Penalize gaps between matched characters
+        pos += found + 1;
+    }
+    Some(score)
+}
+
+/// All the actions matching a query, best match first
+fn filter_actions(query: &str) -> Vec<Action> {
+    let mut scored: Vec<(usize, Action)> = Action::ALL.iter()
+        .filter_map(|a| fuzzy_score(query, a.label()).map(|score| (score, *a)))
+        .collect();
+    scored.sort_by_key(|(score, _)| *score); // Stable, so ties keep their ALL order
+    scored.into_iter().map(|(_, a)| a).collect()
 }
 
-struct TempMessage {
-    created: Instant,
-    message: String
-}
\ No newline at end of file
+#[derive(Default)]
+struct Palette {
+    query: String,
+    selected: usize,
+    focused: bool
+}
+
+struct PagePreview {
+    file: String,
+    modified: Option<SystemTime>,
+    blocks: Result<Vec<Block>, String>,
+    /// Tells the thread watching the file that this preview is gone
+    stop_watching: Arc<AtomicBool>
+}
+
+impl Drop for PagePreview {
+    fn drop(&mut self) {
+        self.stop_watching.store(true, Ordering::Relaxed)
+    }
+}
+
+/// Check the file's modified time every second, and ask for a redraw once it's changed, so
+/// whatever's showing it can catch up. Stops after that, or when told to.
+fn watch_modified(ctx: Context, file: String, modified: Option<SystemTime>) -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    std::thread::spawn(move || {
+        while !stopped.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_secs(1));
+            if fs::metadata(&file).and_then(|m| m.modified()).ok() != modified {
+                ctx.request_repaint();
+                break
+            }
+        }
+    });
+    stop
+}
+
+struct FrontmatterView {
+    file: String,
+    fields: Result<Option<Vec<(String, String)>>, String>
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site_ui() -> SiteUi {
+        SiteUi::from(Site::open(&PathBuf::from("./testdata")).unwrap())
+    }
+
+    #[test]
+    fn test_filter_actions() {
+        assert_eq!(filter_actions("").len(), Action::ALL.len()); // Everything matches an empty query
+        assert_eq!(filter_actions("deploy"), vec![Action::CancelDeploy, Action::BuildAndDeploy]);
+        assert_eq!(filter_actions("bld")[0..2], [Action::BuildSite, Action::BuildAndDeploy]);
+        assert_eq!(filter_actions("stop srv"), vec![Action::StopServer]);
+        assert!(filter_actions("xyzzy").is_empty());
+    }
+
+    #[test]
+    fn test_action_dispatch() {
+        let mut ui = site_ui();
+        // Nothing selected, so nothing to delete
+        assert!(ui.palette_actions("delete").is_empty());
+        ui.perform(Action::DeleteSelected);
+        assert!(ui.dialog_mode.is_none());
+
+        ui.perform(Action::NewPage);
+        assert!(matches!(ui.dialog_mode, Some(DialogMode::NewFile(_))));
+        assert!(ui.dialog_just_opened);
+
+        let selected = "./testdata/raw.txt".to_string();
+        ui.selected_file = Some(selected.clone());
+        ui.selected_files = vec![selected.clone()];
+        assert_eq!(ui.palette_actions("delete"), vec![Action::DeleteSelected]);
+        ui.perform(Action::DeleteSelected);
+        assert!(matches!(&ui.dialog_mode, Some(DialogMode::ConfirmDelete(f)) if *f == selected));
+
+        ui.perform(Action::RenameSelected);
+        assert!(matches!(&ui.dialog_mode, Some(DialogMode::RenameFile(f)) if f == "raw.txt"));
+
+        // The server isn't running, so it can't be stopped
+        assert!(ui.palette_actions("server").contains(&Action::StartServer));
+        assert!(!ui.palette_actions("server").contains(&Action::StopServer));
+    }
+
+    #[test]
+    fn test_confirm_quit() {
+        let mut ui = site_ui();
+        let ctx = Context::default();
+        let frame = |ui: &mut SiteUi, close: bool| {
+            let mut input = egui::RawInput::default();
+            if close {
+                let info = egui::ViewportInfo { events: vec![egui::ViewportEvent::Close], ..Default::default() };
+                input.viewports.insert(egui::ViewportId::ROOT, info);
+            }
+            let output = ctx.run(input, |ctx| ui.check_close_request(ctx));
+            output.viewport_output.get(&egui::ViewportId::ROOT).map(|v| v.commands.clone()).unwrap_or_default()
+        };
+
+        // Nothing running, so closing goes ahead
+        assert!(frame(&mut ui, true).is_empty());
+        assert!(ui.dialog_mode.is_none());
+
+        // A deploy stops the close to ask first
+        ui.deploying = true;
+        assert_eq!(frame(&mut ui, true), vec![egui::ViewportCommand::CancelClose]);
+        assert!(matches!(ui.dialog_mode, Some(DialogMode::ConfirmQuit)));
+
+        // Quitting cancels the deploy, and closes once it's stopped
+        ui.quit();
+        assert!(ui.deploy_cancel.load(Ordering::Relaxed));
+        assert!(frame(&mut ui, false).is_empty());
+        ui.deploying = false;
+        assert_eq!(frame(&mut ui, false), vec![egui::ViewportCommand::Close]);
+    }
+
+    #[test]
+    fn test_build_logged() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.md"), "Hi").unwrap();
+        fs::write(dir.path().join("_config.toml"), "output_dir = \"public\"").unwrap();
+        fs::create_dir(dir.path().join("public")).unwrap();
+        let mut ui = SiteUi::from(Site::open(dir.path()).unwrap());
+        ui.perform(Action::BuildSite);
+        assert!(dir.path().join("public/index.html").exists());
+        let log = build_log::recent(dir.path(), 10);
+        assert_eq!(log.len(), 1);
+        assert!(log[0].contains("  build  ") && log[0].ends_with("  ok"));
+    }
+
+    #[test]
+    fn test_toggle_preview() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.md"), "# Hi").unwrap();
+        let mut ui = SiteUi::from(Site::open(dir.path()).unwrap());
+        ui.selected_file = Some(utils::id_for_path(&dir.path().join("index.md")));
+        let ctx = Context::default();
+        let _ = ctx.run(egui::RawInput::default(), |ctx| ui.preview_panel(ctx));
+        let stop_watching = ui.page_preview.as_ref().unwrap().stop_watching.clone();
+        assert!(!stop_watching.load(Ordering::Relaxed));
+
+        // Hiding it drops the preview, and the thread watching the file with it
+        ui.perform(Action::TogglePreview);
+        let _ = ctx.run(egui::RawInput::default(), |ctx| ui.preview_panel(ctx));
+        assert!(ui.page_preview.is_none());
+        assert!(stop_watching.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_stop_server() {
+        let mut ui = site_ui();
+        ui.server_port = "0".to_string();
+        ui.perform(Action::StartServer);
+        assert!(ui.enabled(Action::StopServer));
+
+        // It's still running until it's finished, so it can't be started again yet
+        ui.perform(Action::StopServer);
+        assert!(ui.server_handle.is_some());
+        assert!(!ui.enabled(Action::StopServer) && !ui.enabled(Action::StartServer));
+
+        let ctx = Context::default();
+        let started = Instant::now();
+        while ui.server_handle.is_some() && started.elapsed() < Duration::from_secs(5) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            ui.check_server_status(&ctx);
+        }
+        assert!(ui.enabled(Action::StartServer));
+    }
+
+    #[test]
+    fn test_shortcuts() {
+        let mut ui = site_ui();
+        let ctx = Context::default();
+        let press = |ui: &mut SiteUi, key, modifiers| {
+            let event = egui::Event::Key { key, physical_key: None, pressed: true, repeat: false, modifiers };
+            let _ = ctx.run(egui::RawInput { events: vec![event], modifiers, ..Default::default() }, |ctx| ui.check_shortcuts(ctx));
+        };
+
+        // Nothing selected, so nothing to delete
+        press(&mut ui, Key::Delete, Modifiers::NONE);
+        assert!(ui.dialog_mode.is_none());
+        press(&mut ui, Key::N, Modifiers::COMMAND);
+        assert!(matches!(ui.dialog_mode, Some(DialogMode::NewFile(_))));
+
+        // Not while a dialog's open
+        let selected = "./testdata/raw.txt".to_string();
+        ui.selected_file = Some(selected.clone());
+        ui.selected_files = vec![selected.clone()];
+        press(&mut ui, Key::Delete, Modifiers::NONE);
+        assert!(matches!(ui.dialog_mode, Some(DialogMode::NewFile(_))));
+
+        ui.dialog_mode = None;
+        press(&mut ui, Key::Delete, Modifiers::NONE);
+        assert!(matches!(&ui.dialog_mode, Some(DialogMode::ConfirmDelete(f)) if *f == selected));
+    }
+
+    #[test]
+    fn test_page_preview() {
+        let local = "http://localhost:3000";
+        assert_eq!(page_url(local, Path::new("posts/hello.md")), Some("http://localhost:3000/posts/hello.html".to_string()));
+        assert_eq!(page_url(local, Path::new("index.md")), Some("http://localhost:3000/".to_string()));
+        assert_eq!(page_url(local, Path::new("reindex.md")), Some("http://localhost:3000/reindex.html".to_string()));
+        assert_eq!(page_url(local, Path::new("about.html")), Some("http://localhost:3000/about.html".to_string()));
+        assert_eq!(page_url(local, Path::new("images/cat.png")), None);
+        assert_eq!(page_url(local, Path::new("_layouts/post.html")), None);
+
+        // Start the server if it isn't going, otherwise use the one we've got
+        assert_eq!(plan_page_preview(false, "http://127.0.0.1:3000", Path::new("a.md")), Some((true, "http://127.0.0.1:3000/a.html".to_string())));
+        assert_eq!(plan_page_preview(true, "http://127.0.0.1:3000", Path::new("a.md")), Some((false, "http://127.0.0.1:3000/a.html".to_string())));
+
+        let mut ui = site_ui();
+        ui.selected_file = Some("./testdata/raw.txt".to_string());
+        assert!(!ui.enabled(Action::PreviewPage));
+        ui.selected_file = Some("./testdata/index.md".to_string());
+        assert!(ui.enabled(Action::PreviewPage));
+    }
+
+    #[test]
+    fn test_dry_run_note() {
+        // Only a dry run script means nothing was deployed for sure
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(dry_run_note(dir.path(), DeployTarget::Script), "Dry run (FLEEN_DRY_RUN=1 was passed to deploy.sh)");
+        assert_eq!(dry_run_note(dir.path(), DeployTarget::GitBranch), "Dry run, nothing was deployed");
+        fs::create_dir(dir.path().join("_scripts")).unwrap();
+        fs::write(dir.path().join("_scripts/deploy-dryrun.sh"), "").unwrap();
+        assert_eq!(dry_run_note(dir.path(), DeployTarget::Script), "Dry run, nothing was deployed");
+    }
+
+    #[test]
+    fn test_live_url() {
+        let base = "https://example.com/blog/";
+        assert_eq!(page_url(base, Path::new("posts/2024-hello.md")), Some("https://example.com/blog/posts/2024-hello.html".to_string()));
+        assert_eq!(page_url(base, Path::new("posts/index.md")), Some("https://example.com/blog/posts/".to_string()));
+
+        // Only offered when we know where the live site is
+        let mut ui = site_ui();
+        ui.selected_file = Some("./testdata/index.md".to_string());
+        assert!(!ui.enabled(Action::OpenLive));
+        let mut site = Site::open(&PathBuf::from("./testdata")).unwrap();
+        site.config.base_url = Some(base.to_string());
+        ui.site = Arc::new(site);
+        assert!(ui.enabled(Action::OpenLive));
+    }
+
+    #[test]
+    fn test_background_build_summary() {
+        let ctx = Context::default();
+        let mut ui = site_ui();
+        ui.check_build_summary(&ctx);
+        let started = Instant::now();
+        while ui.build_summary.is_none() && started.elapsed() < Duration::from_secs(10) {
+            std::thread::sleep(Duration::from_millis(10));
+            ui.check_build_summary(&ctx);
+        }
+        assert!(ui.build_summary.as_ref().unwrap().as_ref().unwrap().pages > 0);
+        assert!(ui.build_summary_response.is_none());
+    }
+
+    #[test]
+    fn test_refresh() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.md"), "# Home").unwrap();
+        let mut ui = SiteUi::from(Site::open(dir.path()).unwrap());
+        ui.build_summary = Some(ui.site.build_summary().map_err(|e| e.to_string()));
+        assert_eq!(ui.build_summary.as_ref().unwrap().as_ref().unwrap().pages, 1);
+
+        // Changed behind our back
+        std::fs::write(dir.path().join("new.md"), "# New").unwrap();
+        let has_new = |ui: &SiteUi| ui.site.tree.iter().any(|e| matches!(e, TreeEntry::File(p, _) if p.ends_with("new.md")));
+        assert!(!has_new(&ui));
+
+        ui.perform(Action::Refresh);
+        assert!(has_new(&ui));
+        assert!(ui.build_summary.is_none());
+        ui.build_summary = Some(ui.site.build_summary().map_err(|e| e.to_string()));
+        assert_eq!(ui.build_summary.as_ref().unwrap().as_ref().unwrap().pages, 2);
+    }
+}
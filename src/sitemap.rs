@@ -0,0 +1,65 @@
+use std::path::Path;
+use crate::renderer::{is_noindex, RenderOutput};
+
+/// A `sitemap.xml` listing every page a build renders, under the site's base url. Hidden pages,
+/// drafts and raw files aren't pages, and neither is anything asking not to be indexed.
+pub fn sitemap<'a>(outputs: impl Iterator<Item=&'a RenderOutput>, base_url: &str) -> String {
+    let base = base_url.trim_end_matches('/');
+    let mut urls: Vec<String> = outputs.filter_map(|output| match output {
+        RenderOutput::Rendered(path, contents) if is_page(path) && !is_noindex(contents) => Some(page_url(base, path)),
+        _ => None
+    }).collect();
+    urls.sort();
+
+    let entries: Vec<String> = urls.iter()
+        .map(|url| format!("  <url><loc>{}</loc></url>\n", url.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")))
+        .collect();
+    format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n{}</urlset>\n", entries.concat())
+}
+
+/// Whether the build already has a sitemap of its own, which we shouldn't replace
+pub fn has_sitemap<'a>(mut outputs: impl Iterator<Item=&'a RenderOutput>) -> bool {
+    outputs.any(|output| matches!(output, RenderOutput::Rendered(path, _) | RenderOutput::RawFile(path) if path == Path::new("sitemap.xml")))
+}
+
+// Templates render too, but a feed or manifest isn't a page
+fn is_page(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "html")
+}
+
+/// Where a built page lives; a dir's index is known by the dir's url
+pub fn page_url(base: &str, path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let path = if path == "index.html" { "" } else { path.strip_suffix("/index.html").map(|dir| &path[..dir.len() + 1]).unwrap_or(&path) };
+    format!("{}/{}", base, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_sitemap() {
+        let page = |path: &str, contents: &str| RenderOutput::Rendered(PathBuf::from(path), contents.to_string());
+        let outputs = [
+            page("index.html", "<p>Home</p>"),
+            page("posts/index.html", "<p>Posts</p>"),
+            page("posts/a&b.html", "<p>Hi</p>"),
+            page("feed.xml", "<rss/>"),
+            page("unlisted.html", "<meta name=\"robots\" content=\"noindex\">\n<p>Shh</p>"),
+            RenderOutput::Hidden(PathBuf::from("hidden.html"), "<p>Hidden</p>".to_string()),
+            RenderOutput::RawFile(PathBuf::from("raw.html")),
+            RenderOutput::Dir(PathBuf::from("posts"))
+        ];
+        assert_eq!(sitemap(outputs.iter(), "https://example.com/"), concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+            "  <url><loc>https://example.com/</loc></url>\n",
+            "  <url><loc>https://example.com/posts/</loc></url>\n",
+            "  <url><loc>https://example.com/posts/a&amp;b.html</loc></url>\n",
+            "</urlset>\n"
+        ));
+        assert!(!has_sitemap(outputs.iter()));
+        assert!(has_sitemap([RenderOutput::RawFile(PathBuf::from("sitemap.xml"))].iter()));
+    }
+}
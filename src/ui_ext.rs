@@ -1,4 +1,4 @@
-use eframe::egui::{Align, Button, Color32, InnerResponse, IntoAtoms, Layout, Response, RichText, Ui, Widget};
+use eframe::egui::{Align, Button, Color32, InnerResponse, Layout, Response, RichText, Ui, Visuals, Widget, WidgetInfo, WidgetType};
 
 pub trait UiExtensions {
     /// A widget that fills the width of its container. Good for buttons.
@@ -19,22 +19,107 @@ impl UiExtensions for Ui {
     }
 }
 
-pub trait ButtonExtensions<'a> {
-    fn red(atoms: impl IntoAtoms<'a>) -> Self;
-    fn green(text: impl Into<String>) -> Self;
-    fn blue(text: impl Into<String>) -> Self;
+/// Colored buttons. The color means something (red is destructive, green creates or starts
+/// things), so that meaning also goes in the name screen readers announce.
+pub trait ButtonExtensions {
+    fn red(text: impl Into<String>) -> AccessibleButton;
+    fn green(text: impl Into<String>) -> AccessibleButton;
+    fn blue(text: impl Into<String>) -> AccessibleButton;
 }
 
-impl<'a> ButtonExtensions<'a> for Button<'a> {
-    fn red(atoms: impl IntoAtoms<'a>) -> Self {
-        Self::new(atoms).fill(Color32::DARK_RED)
+impl ButtonExtensions for Button<'_> {
+    fn red(text: impl Into<String>) -> AccessibleButton {
+        let text = text.into();
+        let name = format!("{}, destructive action", text);
+        AccessibleButton { text, color: ButtonColor::Red, name }
     }
 
-    fn green(text: impl Into<String>) -> Self {
-        Self::new(RichText::new(text).color(Color32::WHITE)).fill(Color32::DARK_GREEN)
+    fn green(text: impl Into<String>) -> AccessibleButton {
+        let text = text.into();
+        let name = format!("{}, creates or starts", text);
+        AccessibleButton { text, color: ButtonColor::Green, name }
     }
 
-    fn blue(text: impl Into<String>) -> Self {
-        Self::new(RichText::new(text).color(Color32::WHITE)).fill(Color32::DARK_BLUE)
+    fn blue(text: impl Into<String>) -> AccessibleButton {
+        let text = text.into();
+        AccessibleButton { name: text.clone(), text, color: ButtonColor::Blue }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ButtonColor {
+    Red,
+    Green,
+    Blue
+}
+
+impl ButtonColor {
+    /// The fill and text colors for a button, which depend on the theme: deep colors with white
+    /// text when it's dark, pale ones with dark text when it's light
+    pub fn colors(&self, visuals: &Visuals) -> (Color32, Color32) {
+        if visuals.dark_mode {
+            let fill = match self {
+                ButtonColor::Red => Color32::DARK_RED,
+                ButtonColor::Green => Color32::DARK_GREEN,
+                ButtonColor::Blue => Color32::DARK_BLUE
+            };
+            (fill, Color32::WHITE)
+        } else {
+            let fill = match self {
+                ButtonColor::Red => Color32::from_rgb(255, 200, 200),
+                ButtonColor::Green => Color32::from_rgb(190, 235, 190),
+                ButtonColor::Blue => Color32::from_rgb(200, 215, 255)
+            };
+            (fill, Color32::BLACK)
+        }
+    }
+}
+
+/// A colored button with its own accessible name, rather than just its text
+pub struct AccessibleButton {
+    text: String,
+    color: ButtonColor,
+    name: String
+}
+
+impl Widget for AccessibleButton {
+    fn ui(self, ui: &mut Ui) -> Response {
+        let (fill, text) = self.color.colors(ui.visuals());
+        let response = Button::new(RichText::new(self.text).color(text)).fill(fill).ui(ui);
+        let enabled = ui.is_enabled();
+        response.widget_info(|| WidgetInfo::labeled(WidgetType::Button, enabled, &self.name));
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eframe::egui::{CentralPanel, Context, RawInput};
+
+    #[test]
+    fn test_accessible_name() {
+        let ctx = Context::default();
+        ctx.enable_accesskit();
+        let output = ctx.run(RawInput::default(), |ctx| {
+            CentralPanel::default().show(ctx, |ui| ui.add(Button::red("Delete")));
+        });
+
+        let update = output.platform_output.accesskit_update.unwrap();
+        let labels: Vec<&str> = update.nodes.iter().filter_map(|(_, node)| node.label()).collect();
+        assert!(labels.contains(&"Delete, destructive action"));
+    }
+
+    #[test]
+    fn test_button_contrast() {
+        let brightness = |c: Color32| c.r() as i32 * 299 + c.g() as i32 * 587 + c.b() as i32 * 114;
+        for visuals in [Visuals::dark(), Visuals::light()] {
+            for color in [ButtonColor::Red, ButtonColor::Green, ButtonColor::Blue] {
+                let (fill, text) = color.colors(&visuals);
+                // Text stands out from its button, and the button from the window behind it
+                assert!((brightness(fill) - brightness(text)).abs() > 125_000, "{:?}", color);
+                assert_ne!(fill, visuals.panel_fill);
+            }
+        }
     }
 }
\ No newline at end of file
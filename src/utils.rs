@@ -1,9 +1,14 @@
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{fs, io, time};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use tinyrand::{Rand, Seeded};
 use crate::fleen_app::FleenError;
 
+/// Fill an empty dir with the skeleton of a new site, with a placeholder favicon that the
+/// default layout links to
 pub fn initialize_site(root: &Path) -> Result<(), io::Error> {
     fs::create_dir(root.join("_layouts"))?;
     fs::create_dir(root.join("_scripts"))?;
@@ -13,33 +18,147 @@ pub fn initialize_site(root: &Path) -> Result<(), io::Error> {
     fs::write(root.join("_scripts/deploy.sh"), include_str!("../templates/deploy.sh"))?;
     fs::write(root.join("assets/.keep"), "")?;
     fs::write(root.join("images/.keep"), "")?;
+    fs::write(root.join("favicon.png"), include_bytes!("../templates/favicon.png"))?;
     Ok(())
 }
 
-pub fn open_filename(filename: &str) -> Result<(), FleenError> {
-    // TODO this doesn't work for html files. We really want to open things in a platform-dependent way
-    // - md, html, all other text, should open in gedit on linux or the user's preferred editor on mac
-    // - images should open in an image viewer preferably
-    // - dirs should open in a file browser
-    // - on mac we can open -t to force a text editor
-    // - on linux we should maybe allow a FLEEN_EDITOR env var, defaulting to EDITOR if missing?
-    Command::new("open").arg(filename).spawn().map_err(|err| {
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Turn safe mode on or off, from the site's settings
+pub fn set_safe_mode(on: bool) {
+    SAFE_MODE.store(on, Ordering::Relaxed)
+}
+
+/// In safe mode we never start another program. `FLEEN_SAFE_MODE` in the environment turns it
+/// on regardless of the setting.
+pub fn safe_mode() -> bool {
+    SAFE_MODE.load(Ordering::Relaxed) || std::env::var("FLEEN_SAFE_MODE").is_ok_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Every time we're about to spawn a process, check with this first. `what` finishes the
+/// sentence "Safe mode is on, so Fleen won't..."
+pub fn allow_spawn(what: &str) -> Result<(), FleenError> {
+    check_spawn(safe_mode(), what)
+}
+
+// The check itself, apart from the global setting, so tests can try it without flipping safe
+// mode for every other test running at the same time
+fn check_spawn(safe: bool, what: &str) -> Result<(), FleenError> {
+    if safe { Err(FleenError::SafeMode(what.to_string())) } else { Ok(()) }
+}
+
+/// Open a file for editing. Text files go to `FLEEN_EDITOR`, or else `EDITOR`, if one is set
+/// (a program, with any arguments it needs, like `code --wait`); everything else opens however
+/// the system opens that kind of file. If the editor won't start we fall back to the system,
+/// and return a note saying why.
+pub fn open_filename(filename: &str) -> Result<Option<String>, FleenError> {
+    allow_spawn(&format!("open {}", filename))?;
+    let editor = std::env::var("FLEEN_EDITOR").or_else(|_| std::env::var("EDITOR")).ok()
+        .filter(|_| is_text_file(filename));
+    open_with(filename, editor.as_deref(), &mut |command| command.spawn().map(|_| ()))
+}
+
+/// Files someone would want to edit as text, rather than view
+fn is_text_file(filename: &str) -> bool {
+    const TEXT: [&str; 12] = ["md", "html", "htm", "css", "scss", "sass", "js", "json", "xml", "txt", "toml", "sh"];
+    Path::new(filename).extension().is_some_and(|ext| TEXT.iter().any(|t| ext.eq_ignore_ascii_case(t)))
+}
+
+/// Lowercase letters and digits, with single dashes between the words
+pub fn slugify(title: &str) -> String {
+    title.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// The platform's way to open a file or url with whatever normally handles it
+fn system_open(target: &str) -> Command {
+    if cfg!(target_os = "windows") {
+        // start's first quoted argument is a window title, so give it an empty one
+        let mut command = Command::new("cmd");
+        command.args(["/c", "start", ""]).arg(target);
+        command
+    } else if cfg!(target_os = "macos") {
+        let mut command = Command::new("open");
+        command.arg(target);
+        command
+    } else {
+        let mut command = Command::new("xdg-open");
+        command.arg(target);
+        command
+    }
+}
+
+/// The command to run the configured editor on a file, if one's configured
+fn editor_command(editor: Option<&str>, filename: &str) -> Option<Command> {
+    let mut parts = editor?.split_whitespace();
+    let mut command = Command::new(parts.next()?);
+    command.args(parts).arg(filename);
+    Some(command)
+}
+
+fn open_with(filename: &str, editor: Option<&str>, spawn: &mut dyn FnMut(&mut Command) -> io::Result<()>) -> Result<Option<String>, FleenError> {
+    let mut note = None;
+    if let Some(mut command) = editor_command(editor, filename) {
+        match spawn(&mut command) {
+            Ok(()) => return Ok(None),
+            Err(e) => note = Some(format!("Couldn't start your editor ({}): {}. Opened it the usual way instead.", editor.unwrap_or_default(), e))
+        }
+    }
+    spawn(&mut system_open(filename)).map_err(|err| {
         FleenError::FileIo(filename.to_owned(), err.to_string())
     })?;
-    Ok(())
+    Ok(note)
 }
 
-pub fn unique_image_name(image_dir: &Path) -> Result<PathBuf, FleenError> {
+/// A name for a new image in `image_dir` that isn't taken yet, from a template like
+/// `image_{random}` (see `SiteConfig::image_name`). A template without `{random}` gets `-2`,
+/// `-3` and so on added until the name's free.
+pub fn unique_image_name(image_dir: &Path, template: &str, extension: &str) -> Result<PathBuf, FleenError> {
     let mut rng = tinyrand::StdRand::seed(time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap().as_secs());
+    let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+    let mut n = 1;
     loop {
-        let fname = format!("image_{}.png", random_name(&mut rng));
-        let path = image_dir.join(fname);
+        let name = template.replace("{date}", &date).replace("{random}", &random_name(&mut rng));
+        let name = if n > 1 && !template.contains("{random}") { format!("{}-{}", name, n) } else { name };
+        let path = image_dir.join(format!("{}.{}", name, extension));
         if !path.exists() {
             return Ok(path)
         }
+        n += 1;
     }
 }
 
+/// Somewhere next to a file or dir to put a copy of it: `post.md` gets `post-copy.md`, or
+/// `post-copy-2.md` if that's taken, and so on
+pub fn copy_name(path: &Path) -> PathBuf {
+    // Dirs with dots in their names don't have extensions, they're just named that
+    let (stem, extension) = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) if !path.is_dir() => (stem.to_string_lossy(), format!(".{}", ext.to_string_lossy())),
+        _ => (path.file_name().unwrap_or_default().to_string_lossy(), String::new())
+    };
+    (1..).map(|n| match n {
+        1 => path.with_file_name(format!("{}-copy{}", stem, extension)),
+        n => path.with_file_name(format!("{}-copy-{}{}", stem, n, extension))
+    }).find(|candidate| !candidate.exists()).unwrap()
+}
+
+/// Copy everything in one dir into another, which has to exist already
+pub fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(from)?.flatten() {
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            fs::create_dir_all(&target)?;
+            copy_dir(&entry.path(), &target)?
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
 fn random_name(rng: &mut tinyrand::StdRand) -> String {
     let mut s = String::new();
     let chs = ['0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f'];
@@ -50,9 +169,23 @@ fn random_name(rng: &mut tinyrand::StdRand) -> String {
     s
 }
 
-pub fn open_server(port: &str) {
+pub fn open_url(url: &str) -> Result<(), FleenError> {
+    allow_spawn("open a browser")?;
     // If this doesn't work, not like I can do much about it.
-    let _ = Command::new("open").arg(format!("http://localhost:{}", port)).spawn();
+    let _ = system_open(url).spawn();
+    Ok(())
+}
+
+/// A byte count in human terms, like "1.2 MB"
+pub fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["bytes", "KB", "MB"] {
+        if size < 1024.0 {
+            return if unit == "bytes" { format!("{} bytes", bytes) } else { format!("{:.1} {}", size, unit) }
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} GB", size)
 }
 
 pub fn label_for_path(path: &Path) -> String {
@@ -61,4 +194,68 @@ pub fn label_for_path(path: &Path) -> String {
 
 pub fn id_for_path(path: &Path) -> String {
     path.to_string_lossy().to_string()
-}
\ No newline at end of file
+}
+
+/// Read one of Fleen's own toml files, like the recent sites. Any problem reading it just gives
+/// us the defaults; it's not worth bothering the user over.
+pub fn load_toml<T: DeserializeOwned + Default>(path: &Path) -> T {
+    fs::read_to_string(path).ok()
+        .and_then(|s| toml::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// Write one of Fleen's own toml files, making its dir first if it isn't there yet
+pub fn save_toml<T: Serialize>(value: &T, path: &Path) -> Result<(), FleenError> {
+    let io_error = |e: String| FleenError::FileIo(path.to_string_lossy().to_string(), e);
+    let contents = toml::to_string(value).map_err(|e| io_error(e.to_string()))?;
+    if let Some(dir) = path.parent() { fs::create_dir_all(dir).map_err(|e| io_error(e.to_string()))? }
+    fs::write(path, contents).map_err(|e| io_error(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safe_mode() {
+        assert!(check_spawn(false, "open /nowhere/index.md").is_ok());
+        let Err(err) = check_spawn(true, "open /nowhere/index.md") else { panic!("safe mode should refuse") };
+        assert!(matches!(err, FleenError::SafeMode(_)));
+        assert_eq!(err.to_string(), "Safe mode is on, so Fleen won't open /nowhere/index.md");
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 bytes");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(3 * 1024 * 1024), "3.0 MB");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.0 GB");
+    }
+
+    #[test]
+    fn test_editor_fallback() {
+        let mut ran = vec![];
+        let mut spawn = |command: &mut Command| {
+            ran.push(command.get_program().to_string_lossy().to_string());
+            if command.get_program() == "not-an-editor" { Err(io::Error::from(io::ErrorKind::NotFound)) } else { Ok(()) }
+        };
+        assert_eq!(open_with("a.md", Some("vim -p"), &mut spawn).unwrap(), None);
+        let note = open_with("a.md", Some("not-an-editor"), &mut spawn).unwrap();
+        assert!(note.unwrap().starts_with("Couldn't start your editor (not-an-editor)"));
+        assert_eq!(open_with("a.md", None, &mut spawn).unwrap(), None);
+        let system = system_open("a.md").get_program().to_string_lossy().to_string();
+        assert_eq!(ran, vec!["vim".to_string(), "not-an-editor".to_string(), system.clone(), system]);
+
+        // A missing system opener is still an error
+        let mut missing = |_: &mut Command| Err(io::Error::from(io::ErrorKind::NotFound));
+        assert!(matches!(open_with("a.md", None, &mut missing), Err(FleenError::FileIo(name, _)) if name == "a.md"));
+
+        let command = editor_command(Some("code --wait"), "a.md").unwrap();
+        assert_eq!(command.get_args().collect::<Vec<_>>(), vec!["--wait", "a.md"]);
+        assert!(editor_command(Some("  "), "a.md").is_none());
+
+        // Only text goes to the editor
+        assert!(is_text_file("post.md") && is_text_file("layout.HTML") && is_text_file("style.css"));
+        assert!(!is_text_file("cat.png") && !is_text_file("assets"));
+    }
+}
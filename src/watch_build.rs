@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::config::SiteConfig;
+use crate::fleen_app::{Diagnostic, FleenError, Site, SiteActions};
+use crate::idle_deploy::{site_fingerprint, IdleDeploy};
+
+/// How often to look at the site for changes
+const SCAN_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the site has to sit still after a change before we rebuild, so saving several files
+/// at once is one build rather than several
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Keeps a build on disk up to date with the site: it's built once to start with, and again each
+/// time the site changes and settles down. Each build is a whole build, like "Build site" does.
+/// Watching stops when this is dropped.
+///
+/// Changes are found by polling `site_fingerprint`, like auto deploy does, rather than with file
+/// system events. That's a scan of the site every `SCAN_INTERVAL`, which is cheap for the size of
+/// site Fleen is for, and it needs no extra dependency or per-platform quirks.
+pub struct WatchBuild {
+    pub target: PathBuf,
+    stop: Arc<AtomicBool>,
+    results: Receiver<Result<Vec<Diagnostic>, FleenError>>
+}
+
+impl WatchBuild {
+    pub fn start(root: &Path, target: &Path, strict: bool) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, results) = mpsc::channel();
+        let (root, watched_target, watching) = (root.to_path_buf(), target.to_path_buf(), stop.clone());
+        thread::spawn(move || {
+            // The site is reopened for every build, so new files and settings are picked up
+            let build = || Site::open(&root).and_then(|site| {
+                (&site).build_to_dir(&watched_target, strict).map(|warnings| (site.config.clone(), warnings))
+            });
+            let mut config = SiteConfig::load(&root).unwrap_or_default();
+            let mut changes = IdleDeploy::new(SETTLE_TIME);
+            let mut first = true;
+            while !watching.load(Ordering::Relaxed) {
+                // Look before building, so changes made during a build are caught next time round
+                let now = Instant::now();
+                changes.observe(site_fingerprint(&root, &config), now);
+                if std::mem::take(&mut first) || changes.due(now) {
+                    let result = build().map(|(new_config, warnings)| {
+                        config = new_config;
+                        warnings
+                    });
+                    // Nobody's listening any more, so nobody wants the build either
+                    if sender.send(result).is_err() { break }
+                }
+                thread::sleep(SCAN_INTERVAL);
+            }
+        });
+        Self { target: target.to_path_buf(), stop, results }
+    }
+
+    /// The builds finished since we last asked, oldest first
+    pub fn results(&self) -> impl Iterator<Item = Result<Vec<Diagnostic>, FleenError>> + '_ {
+        self.results.try_iter()
+    }
+}
+
+impl Drop for WatchBuild {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_watch_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.md"), "Hello").unwrap();
+        let watch = WatchBuild::start(dir.path(), target.path(), false);
+
+        // It builds straight away
+        let next = || watch.results.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(next().is_ok());
+        assert!(fs::read_to_string(target.path().join("index.html")).unwrap().contains("Hello"));
+
+        // And again when something changes
+        fs::write(dir.path().join("index.md"), "Goodbye").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(60);
+        fs::File::options().write(true).open(dir.path().join("index.md")).unwrap().set_modified(later).unwrap();
+        assert!(next().is_ok());
+        assert!(fs::read_to_string(target.path().join("index.html")).unwrap().contains("Goodbye"));
+    }
+}
@@ -3,6 +3,12 @@
 # Make this be a script that will deploy your site. Assume it'll be run from the
 # path of the built site, that the build succeeded, and that anything written to
 # stdout will be displayed in a dialog.
+#
+# Fleen tells the script where things are, as absolute paths, both as arguments
+# and in the environment:
+#   $1, $FLEEN_OUTPUT_DIR   the built site (also the current directory)
+#   $2, $FLEEN_SITE_ROOT    the site's source
+# And for a dry run, FLEEN_DRY_RUN is set to 1.
 
 # Example:
 # rsync -r --delete . root@example.com:/var/www/html 2>&1 && echo "Site deployed!"